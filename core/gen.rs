@@ -20,7 +20,7 @@ use rand::prelude::*;
 use rand::{random, thread_rng, Rng};
 
 use fots::types::{
-    Field, Flag, FnInfo, GroupId, NumInfo, NumLimit, PtrDir, StrType, TypeId, TypeInfo,
+    Field, Flag, FnId, FnInfo, GroupId, NumInfo, NumLimit, PtrDir, StrType, TypeId, TypeInfo,
 };
 
 use crate::analyze::{RTable, Relation};
@@ -36,6 +36,8 @@ pub struct Config {
     pub str_max_len: usize,
     pub path_max_depth: usize,
     pub sp_delta: f64,
+    /// Selection weight of syscalls, 1.0 for absent ones
+    pub weights: HashMap<FnId, f64>,
 }
 
 impl Default for Config {
@@ -47,6 +49,7 @@ impl Default for Config {
             str_max_len: 32,
             path_max_depth: 4,
             sp_delta: 0.4,
+            weights: HashMap::new(),
         }
     }
 }
@@ -67,7 +70,12 @@ pub fn gen<S: std::hash::BuildHasher>(
 
 pub fn gen_prog(gid: GroupId, r: &RTable, t: &Target, conf: &Config) -> Prog {
     // choose sequence
-    let seq = choose_seq(r, conf);
+    let weights = t.groups[&gid]
+        .fns
+        .iter()
+        .map(|f| conf.weights.get(&f.id).copied().unwrap_or(1.0))
+        .collect();
+    let seq = choose_seq(r, weights, conf);
     assert!(!seq.is_empty());
 
     gen_seq(&seq, gid, t, conf)
@@ -536,11 +544,11 @@ fn gen_num(type_info: &NumInfo) -> Value {
     }
 }
 
-fn choose_seq(rs: &RTable, conf: &Config) -> Vec<usize> {
+/// `sps`: selection prability list, initialized with weights of calls.
+fn choose_seq(rs: &RTable, mut sps: Vec<f64>, conf: &Config) -> Vec<usize> {
     assert!(!rs.is_empty());
+    assert_eq!(rs.len(), sps.len());
 
-    // selection prability list
-    let mut sps = std::iter::repeat(1.0).take(rs.len()).collect::<Vec<_>>();
     let mut seq = Vec::new();
     let mut i;
     while !should_stop(seq.len(), &conf) {
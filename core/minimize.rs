@@ -81,7 +81,7 @@ fn is_arg_ref(call: &Call, i: usize) -> bool {
     false
 }
 
-fn for_each_ref<F: FnMut(&ArgIndex)>(val: &Value, f: F) {
+pub(crate) fn for_each_ref<F: FnMut(&ArgIndex)>(val: &Value, f: F) {
     struct InnerF<F: FnMut(&ArgIndex)> {
         f: Box<F>,
     }
@@ -104,7 +104,7 @@ fn for_each_ref<F: FnMut(&ArgIndex)>(val: &Value, f: F) {
     do_for_each_ref(val, &mut f.f)
 }
 
-fn for_each_ref_mut<F: FnMut(&mut ArgIndex)>(val: &mut Value, f: F) {
+pub(crate) fn for_each_ref_mut<F: FnMut(&mut ArgIndex)>(val: &mut Value, f: F) {
     struct InnerF<F: FnMut(&mut ArgIndex)> {
         f: Box<F>,
     }
@@ -0,0 +1,47 @@
+use rand::prelude::*;
+
+use crate::minimize::{for_each_ref, for_each_ref_mut};
+use crate::prog::{CId, Prog};
+
+/// Shuffle calls of prog randomly while keeping every call after calls it refers to.
+/// Return shuffled prog and original index of each call in it.
+pub fn shuffle(p: &Prog) -> (Prog, Vec<CId>) {
+    let mut rng = thread_rng();
+    // deps[i]: calls that call i refers to
+    let deps = p
+        .calls
+        .iter()
+        .map(|c| {
+            let mut deps = Vec::new();
+            for arg in c.args.iter() {
+                for_each_ref(&arg.val, |(cid, _)| deps.push(*cid));
+            }
+            deps
+        })
+        .collect::<Vec<_>>();
+
+    let mut order = Vec::with_capacity(p.len());
+    let mut placed = vec![false; p.len()];
+    while order.len() != p.len() {
+        let ready = (0..p.len())
+            .filter(|&i| !placed[i] && deps[i].iter().all(|&d| placed[d]))
+            .collect::<Vec<_>>();
+        let i = *ready.choose(&mut rng).unwrap();
+        placed[i] = true;
+        order.push(i);
+    }
+
+    let mut new_index = vec![0; p.len()];
+    for (new, &old) in order.iter().enumerate() {
+        new_index[old] = new;
+    }
+    let mut shuffled = Prog::new(p.gid);
+    for &old in order.iter() {
+        let mut c = p.calls[old].clone();
+        for arg in c.args.iter_mut() {
+            for_each_ref_mut(&mut arg.val, |(ref mut cid, _)| *cid = new_index[*cid]);
+        }
+        shuffled.add_call(c);
+    }
+    (shuffled, order)
+}
@@ -1,6 +1,7 @@
 use nix::errno::Errno;
 use nix::sys::{mman, stat};
 use nix::{fcntl, libc, unistd, Result};
+use serde::{Deserialize, Serialize};
 use std::os::raw::c_void;
 use std::os::unix::io::RawFd;
 use std::ptr::NonNull;
@@ -14,6 +15,42 @@ const KCOV_INIT_TRACE: u8 = 1;
 const KCOV_ENABLE: u8 = 100;
 const KCOV_DISABLE: u8 = 101;
 
+/// Trace mode of kcov, passed to KCOV_ENABLE
+pub const KCOV_TRACE_PC: usize = 0;
+pub const KCOV_TRACE_CMP: usize = 1;
+
+/// Number of u64 words of each comparison record in kcov buffer: type, arg1, arg2, pc
+pub const KCOV_CMP_RECORD: usize = 4;
+const KCOV_CMP_CONST: u64 = 1;
+const KCOV_CMP_SIZE_MASK: u64 = 6;
+
+/// Operands of one comparison traced by kcov in KCOV_TRACE_CMP mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CmpArg {
+    pub pc: u64,
+    pub arg1: u64,
+    pub arg2: u64,
+    /// Size of operands in bytes: 1, 2, 4 or 8
+    pub size: u8,
+    /// One of operands is a compile time constant
+    pub is_const: bool,
+}
+
+/// Parse records of KCOV_TRACE_CMP buffer, the leading count word excluded.
+/// Trailing incomplete record is ignored.
+pub fn parse_cmps(records: &[u64]) -> Vec<CmpArg> {
+    records
+        .chunks_exact(KCOV_CMP_RECORD)
+        .map(|r| CmpArg {
+            pc: r[3],
+            arg1: r[1],
+            arg2: r[2],
+            size: 1 << ((r[0] & KCOV_CMP_SIZE_MASK) >> 1),
+            is_const: r[0] & KCOV_CMP_CONST != 0,
+        })
+        .collect()
+}
+
 unsafe fn kcov_init(fd: RawFd, len: usize) -> Result<libc::c_int> {
     let res = libc::ioctl(
         fd,
@@ -23,8 +60,8 @@ unsafe fn kcov_init(fd: RawFd, len: usize) -> Result<libc::c_int> {
     Errno::result(res)
 }
 
-unsafe fn kcov_enable(fd: RawFd) -> Result<libc::c_int> {
-    let res = libc::ioctl(fd, request_code_none!(KCOV_MAGIC, KCOV_ENABLE), 0);
+unsafe fn kcov_enable(fd: RawFd, mode: usize) -> Result<libc::c_int> {
+    let res = libc::ioctl(fd, request_code_none!(KCOV_MAGIC, KCOV_ENABLE), mode);
     Errno::result(res)
 }
 
@@ -40,6 +77,7 @@ pub struct CovHandle {
     mem: NonNull<c_void>,
 }
 
+/// Open kcov in KCOV_TRACE_PC mode.
 pub fn open() -> CovHandle {
     let fd = fcntl::open(KCOV, fcntl::OFlag::O_RDWR, stat::Mode::empty())
         .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to open {}: {}", KCOV, e));
@@ -89,7 +127,7 @@ impl CovHandle {
 
     fn enable(&self) -> Guard {
         unsafe {
-            kcov_enable(self.fd)
+            kcov_enable(self.fd, KCOV_TRACE_PC)
                 .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to enable kcov trace: {}", e));
         }
         Guard { inner: self }
@@ -125,3 +163,33 @@ impl<'a> Drop for Guard<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmps() {
+        // 4 bytes const cmp, 1 byte cmp, then an incomplete record
+        let records = [5, 0xdead, 0xbeef, 0xffff_ffff_8100_0000, 0, 1, 2, 0x10, 7];
+        assert_eq!(
+            parse_cmps(&records),
+            vec![
+                CmpArg {
+                    pc: 0xffff_ffff_8100_0000,
+                    arg1: 0xdead,
+                    arg2: 0xbeef,
+                    size: 4,
+                    is_const: true,
+                },
+                CmpArg {
+                    pc: 0x10,
+                    arg1: 1,
+                    arg2: 2,
+                    size: 1,
+                    is_const: false,
+                }
+            ]
+        );
+    }
+}
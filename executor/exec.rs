@@ -1,17 +1,22 @@
+use crate::cover::{parse_cmps, CmpArg};
 use crate::Config;
 use byte_slice_cast::*;
 use byteorder::*;
 use core::prog::Prog;
 use core::target::Target;
+use nix::errno::Errno;
 use nix::fcntl::{fcntl, FcntlArg};
+use nix::mount::{mount, MsFlags};
 use nix::poll::{poll, PollFd, PollFlags};
-use nix::sys::signal::{kill, Signal};
-use nix::sys::wait::{wait, waitpid, WaitPidFlag};
-use nix::unistd::{dup2, fork, ForkResult, Pid};
-use os_pipe::PipeWriter;
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::signal::{kill, signal, SigHandler, Signal};
+use nix::sys::wait::{wait, waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{dup2, fork, getpid, setgid, setgroups, setuid, ForkResult, Gid, Pid, Uid};
+use os_pipe::{PipeReader, PipeWriter};
 use rand::random;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::fs::{read_to_string, write};
 use std::io::Read;
@@ -20,28 +25,219 @@ use std::ops::Index;
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Once;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
+/// Pipe or process can't be created because fds or processes are exhausted for now,
+/// caller could retry later with less concurrency.
+#[derive(Debug)]
+pub struct Exhausted(&'static str, String);
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Resource exhausted, fail to {}: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for Exhausted {}
+
+fn is_exhausted(errno: Option<Errno>) -> bool {
+    matches!(
+        errno,
+        Some(Errno::EAGAIN) | Some(Errno::EMFILE) | Some(Errno::ENFILE)
+    )
+}
+
+fn new_pipe(what: &'static str) -> Result<(PipeReader, PipeWriter), Exhausted> {
+    match os_pipe::pipe() {
+        Ok(pipe) => Ok(pipe),
+        Err(e) if is_exhausted(e.raw_os_error().map(Errno::from_i32)) => {
+            Err(Exhausted(what, e.to_string()))
+        }
+        Err(e) => exits!(exitcode::OSERR, "Fail to {} : {}", what, e),
+    }
+}
+
+/// Default size of data pipe that coverage is transferred through.
+pub const DEFAULT_PIPE_SIZE: usize = 1024 * 1024;
+
+/// Pipe for transferring coverage, enlarged to `size` bytes if possible.
+fn data_pipe(size: usize) -> Result<(PipeReader, PipeWriter), Exhausted> {
+    let (rp, wp) = new_pipe("create data pipe")?;
+    // Larger pipe is only an optimization, default size still works, e.g. if size exceeds
+    // pipe-max-size sysctl.
+    let ret = i32::try_from(size)
+        .map_err(|_| Errno::EINVAL.into())
+        .and_then(|size| fcntl(wp.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(size)));
+    if let Err(e) = ret {
+        static WARN: Once = Once::new();
+        WARN.call_once(|| {
+            eprintln!(
+                "Warning: fail to set buf size for data pipe to {}, using default size: {}",
+                size, e
+            )
+        });
+    }
+    Ok((rp, wp))
+}
+
+/// Execute prog in subprocess, buffer coverage of all calls in result.
+/// With `Config::trace_cmp`, comparison operands are collected too, see `fork_exec_cmp`.
+pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> Result<ExecResult, Exhausted> {
+    if conf.trace_cmp {
+        return fork_exec_cmp(p, t, conf);
+    }
+    let mut covs = Vec::new();
+    Ok(
+        match fork_exec_streaming(p, t, conf, |cov| covs.push(cov))? {
+            ExecResult::Ok(_) => {
+                covs.shrink_to_fit();
+                ExecResult::Ok(covs)
+            }
+            ExecResult::Truncated(_) => {
+                covs.shrink_to_fit();
+                ExecResult::Truncated(covs)
+            }
+            ExecResult::Partial { err, .. } => {
+                covs.shrink_to_fit();
+                ExecResult::Partial { covs, err }
+            }
+            ret => ret,
+        },
+    )
+}
+
+/// Execute prog twice, first with pc coverage, then with comparison tracing, since kcov
+/// traces only one mode per thread. Result of the first run is returned as is unless it's
+/// `ExecResult::Ok`. Comparisons are best effort, they're empty if the second run fails.
+fn fork_exec_cmp(p: Prog, t: &Target, conf: &Config) -> Result<ExecResult, Exhausted> {
+    let pc_conf = Config {
+        trace_cmp: false,
+        ..conf.clone()
+    };
+    let covs = match fork_exec(p.clone(), t, &pc_conf)? {
+        ExecResult::Ok(covs) => covs,
+        ret => return Ok(ret),
+    };
+    let cmps = match fork_exec_streaming(p, t, conf, |_| ())? {
+        ExecResult::OkCmp { cmps, .. } => cmps,
+        _ => Vec::new(),
+    };
+    Ok(ExecResult::OkCmp { covs, cmps })
+}
+
+/// Default number of runs of `fork_exec_stable`.
+pub const STABLE_RUNS: usize = 3;
+
+/// Execute prog `runs` times, keep only coverage seen in every run, which filters noise of
+/// interrupts and scheduling. Result of first run that doesn't succeed is returned as is.
+pub fn fork_exec_stable(
+    p: Prog,
+    t: &Target,
+    conf: &Config,
+    runs: usize,
+) -> Result<ExecResult, Exhausted> {
+    assert!(runs > 0);
+    let mut all = Vec::with_capacity(runs);
+    let mut truncated = false;
+    for _ in 0..runs {
+        match fork_exec(p.clone(), t, conf)? {
+            ExecResult::Ok(covs) => all.push(covs),
+            ExecResult::Truncated(covs) => {
+                truncated = true;
+                all.push(covs)
+            }
+            ret => return Ok(ret),
+        }
+    }
+    let covs = intersect_covs(&all);
+    Ok(if truncated {
+        ExecResult::Truncated(covs)
+    } else {
+        ExecResult::Ok(covs)
+    })
+}
+
+/// Coverage of each call seen in every run, in order of first run. Calls not reached by
+/// every run are dropped.
+fn intersect_covs(runs: &[Vec<Vec<usize>>]) -> Vec<Vec<usize>> {
+    let calls = runs.iter().map(Vec::len).min().unwrap_or(0);
+    (0..calls)
+        .map(|i| {
+            let others = runs[1..]
+                .iter()
+                .map(|r| r[i].iter().collect::<HashSet<_>>())
+                .collect::<Vec<_>>();
+            runs[0][i]
+                .iter()
+                .filter(|pc| others.iter().all(|o| o.contains(pc)))
+                .copied()
+                .collect()
+        })
+        .collect()
+}
+
+/// Execute prog in subprocess, hand coverage of each call to `on_cov` as soon as it arrives.
+/// Coverage is not buffered, so coverage of any result of this function is always empty.
+/// Comparisons traced with `Config::trace_cmp` are not handed out, they're in the result.
+pub fn fork_exec_streaming<F: FnMut(Vec<usize>)>(
+    p: Prog,
+    t: &Target,
+    conf: &Config,
+    on_cov: F,
+) -> Result<ExecResult, Exhausted> {
+    #[cfg(feature = "kcov")]
+    {
+        let mut on_cov = on_cov;
+        let mut iter = iter_exec(p, t, conf)?;
+        for cov in &mut iter {
+            on_cov(cov);
+        }
+        Ok(iter.finish())
+    }
+
+    #[cfg(not(feature = "kcov"))]
+    {
+        let _ = on_cov;
+        let mut spawned = spawn(&p, t, conf)?;
+        drop(spawned.data);
+        Ok(watch(spawned.child, &mut spawned.err))
+    }
+}
+
+/// Execute prog in subprocess, returned iterator yields coverage of each call as soon as it
+/// arrives, see `IterExec`.
+#[cfg(feature = "kcov")]
+pub fn iter_exec(p: Prog, t: &Target, conf: &Config) -> Result<IterExec, Exhausted> {
+    let spawned = spawn(&p, t, conf)?;
+    Ok(IterExec::new(
+        spawned.child,
+        spawned.data,
+        spawned.err,
+        spawned.notifer,
+        conf,
+    ))
+}
+
+/// Subprocess executing a prog, with parent ends of its pipes.
+struct Spawned {
+    child: Pid,
+    data: PipeReader,
+    err: PipeReader,
+    #[cfg(feature = "kcov")]
+    notifer: crate::utils::Notifier,
+}
+
+fn spawn(p: &Prog, t: &Target, conf: &Config) -> Result<Spawned, Exhausted> {
     if conf.concurrency || random::<f64>() < 0.0025 {
-        bg_run(&p, t);
+        bg_run(p, t);
     }
     // transfer usefull data
-    let (mut rp, mut wp) = os_pipe::pipe()
-        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to create date pipe : {}", e));
-    fcntl(wp.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(1024 * 1024)).unwrap_or_else(|e| {
-        exits!(
-            exitcode::OSERR,
-            "Fail to set buf size for data pipe to {} :{}",
-            1024 * 1024,
-            e
-        )
-    });
+    let (rp, mut wp) = data_pipe(conf.pipe_size.unwrap_or(DEFAULT_PIPE_SIZE))?;
 
     // collect err msg
-    let (mut err_rp, err_wp) = os_pipe::pipe()
-        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to create err pipe : {}", e));
+    let (err_rp, err_wp) = new_pipe("create err pipe")?;
     // sync data transfer
     #[cfg(feature = "kcov")]
     let (notifer, waiter) = crate::utils::event();
@@ -70,10 +266,18 @@ pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
                 )
             });
             drop(err_wp);
+            if !conf.sandbox.is_empty() {
+                sandbox(conf.sandbox);
+            }
+            // With kcov, privileges are dropped after opening kcov.
+            #[cfg(not(feature = "kcov"))]
+            if let Some(user) = conf.run_as {
+                drop_privileges(user);
+            }
             #[cfg(feature = "kcov")]
-            sync_exec(&p, t, &mut wp, waiter, conf);
+            sync_exec(p, t, &mut wp, waiter, conf);
             #[cfg(not(feature = "kcov"))]
-            sync_exec(&p, t);
+            sync_exec(p, t);
             // subprocess exits here
             exit(exitcode::OK)
         }
@@ -83,18 +287,78 @@ pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
             #[cfg(feature = "kcov")]
             drop(waiter);
 
-            #[cfg(feature = "kcov")]
-            let ret = watch(child, &mut rp, &mut err_rp, notifer, conf);
+            Ok(Spawned {
+                child,
+                data: rp,
+                err: err_rp,
+                #[cfg(feature = "kcov")]
+                notifer,
+            })
+        }
+        Err(e) if is_exhausted(e.as_errno()) => Err(Exhausted("fork", e.to_string())),
+        Err(e) => exits!(exitcode::OSERR, "Fail to fork: {}", e),
+    }
+}
 
-            #[cfg(not(feature = "kcov"))]
-            let ret = watch(child, &mut err_rp);
+/// Unshare given namespaces for current process, so side effects of prog don't leak.
+/// Kcov is opened after this, coverage collection is not affected.
+/// With a new pid namespace, the rest runs in a forked child, see `enter_pid_ns`.
+fn sandbox(flags: CloneFlags) {
+    unshare(flags)
+        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to unshare {:?}: {}", flags, e));
+    if flags.contains(CloneFlags::CLONE_NEWNS) {
+        // stop mount events propagating back to the parent namespace
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to make mounts private: {}", e));
+    }
+    if flags.contains(CloneFlags::CLONE_NEWPID) {
+        enter_pid_ns();
+    }
+}
 
-            ret
+/// Unsharing pid namespace only moves children forked afterwards into it, so fork once more
+/// and return in the child, pid 1 of the namespace. This process waits for it and exits the
+/// same way, it's the one executor watches and kills.
+fn enter_pid_ns() {
+    match fork() {
+        Ok(ForkResult::Child) => {
+            // die with the waiting parent when it's killed on timeout
+            unsafe { nix::libc::prctl(nix::libc::PR_SET_PDEATHSIG, nix::libc::SIGKILL) };
         }
-        Err(e) => exits!(exitcode::OSERR, "Fail to fork: {}", e),
+        Ok(ForkResult::Parent { child }) => loop {
+            match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, code)) => exit(code),
+                Ok(WaitStatus::Signaled(_, sig, _)) => {
+                    // re-raise, so that executor sees the signal instead of an exit code
+                    unsafe {
+                        let _ = signal(sig, SigHandler::SigDfl);
+                    }
+                    let _ = kill(getpid(), sig);
+                    exit(128 + sig as i32)
+                }
+                Ok(_) => continue,
+                Err(e) => exits!(exitcode::OSERR, "Fail to wait pid namespace init: {}", e),
+            }
+        },
+        Err(e) => exits!(exitcode::OSERR, "Fail to fork into pid namespace: {}", e),
     }
 }
 
+/// Run as given uid and gid, supplementary groups are cleared.
+fn drop_privileges((uid, gid): (u32, u32)) {
+    let (uid, gid) = (Uid::from_raw(uid), Gid::from_raw(gid));
+    setgroups(&[gid])
+        .and_then(|_| setgid(gid))
+        .and_then(|_| setuid(uid))
+        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to run as {}:{}: {}", uid, gid, e));
+}
+
 fn bg_run(p: &Prog, t: &Target) {
     match fork() {
         Ok(ForkResult::Child) => match fork() {
@@ -140,7 +404,8 @@ fn bg_run(p: &Prog, t: &Target) {
         Ok(ForkResult::Parent { child }) => {
             waitpid(child, None).unwrap();
         }
-        Err(e) => exits!(exitcode::OSERR, "Fail to fork: {}", e),
+        // background run is optional, skip it if process can't be created
+        Err(_) => (),
     }
 }
 
@@ -189,15 +454,18 @@ fn watch<T: Read + AsRawFd>(child: Pid, err: &mut T) -> ExecResult {
     match poll(&mut fds, 5_000) {
         Ok(0) => {
             kill_and_wait(child);
-            ExecResult::Failed(Reason(String::from("Time out")))
+            ExecResult::Failed(Reason(String::from(TIME_OUT)))
         }
         Ok(_) => {
             assert!(fds[0].revents().is_some() && !fds[0].revents().unwrap().is_empty());
-            kill_and_wait(child);
+            let status = reap_or_kill(child);
             let mut err_msg = Vec::new();
             err.read_to_end(&mut err_msg).unwrap();
             if err_msg.is_empty() {
-                ExecResult::Ok(Default::default())
+                match status.and_then(exit_reason) {
+                    Some(reason) => ExecResult::Failed(reason),
+                    None => ExecResult::Ok(Default::default()),
+                }
             } else {
                 ExecResult::Failed(Reason(String::from_utf8(err_msg).unwrap()))
             }
@@ -206,92 +474,229 @@ fn watch<T: Read + AsRawFd>(child: Pid, err: &mut T) -> ExecResult {
     }
 }
 
+/// Execution of prog in subprocess, yields owned coverage of each call as soon as it arrives.
+///
+/// Iteration ends once the execution is over, its result is taken by `finish`. Calling `finish`
+/// early or dropping the iterator kills the subprocess, so caller can abort a prog whose
+/// remaining coverage is useless. Frames of comparison records are buffered in the result
+/// instead of yielded.
 #[cfg(feature = "kcov")]
-fn watch<T: Read + AsRawFd>(
+pub struct IterExec {
     child: Pid,
-    data: &mut T,
-    err: &mut T,
+    data: PipeReader,
+    err: PipeReader,
     notifer: crate::utils::Notifier,
-    conf: &Config,
-) -> ExecResult {
-    let mut fds = vec![
-        PollFd::new(data.as_raw_fd(), PollFlags::POLLIN),
-        PollFd::new(err.as_raw_fd(), PollFlags::POLLIN),
-    ];
-    let mut covs = Vec::new();
-    let wait_timeout = if conf.memleak_check { 3000 } else { 1000 };
-    let mut wait_time = Duration::from_secs(0);
+    conf: Config,
+    cov_num: usize,
+    cov_bytes: usize,
+    cmps: Vec<CmpArg>,
+    /// Failing polls are retried until it, `exec_timeout` if set, 10s from start otherwise
+    poll_deadline: Instant,
+    deadline: Option<Instant>,
+    reaped: bool,
+    result: Option<ExecResult>,
+}
 
-    loop {
-        match poll(&mut fds, wait_timeout) {
-            Ok(0) => {
-                // timeout
-                kill_and_wait(child);
-                return if covs.is_empty() {
-                    ExecResult::Failed(Reason(String::from("Time out")))
-                } else {
-                    covs.shrink_to_fit();
-                    ExecResult::Ok(covs)
+#[cfg(feature = "kcov")]
+impl IterExec {
+    fn new(
+        child: Pid,
+        data: PipeReader,
+        err: PipeReader,
+        notifer: crate::utils::Notifier,
+        conf: &Config,
+    ) -> Self {
+        Self {
+            child,
+            data,
+            err,
+            notifer,
+            conf: conf.clone(),
+            cov_num: 0,
+            cov_bytes: 0,
+            cmps: Vec::new(),
+            poll_deadline: Instant::now() + conf.exec_timeout.unwrap_or(Duration::from_secs(10)),
+            deadline: conf.exec_timeout.map(|t| Instant::now() + t),
+            reaped: false,
+            result: None,
+        }
+    }
+
+    /// Result of execution, with empty coverage. If execution is not over yet, subprocess is
+    /// killed and the result is `ExecResult::Truncated`.
+    pub fn finish(mut self) -> ExecResult {
+        match self.result.take() {
+            Some(ret) => ret,
+            None => {
+                self.kill();
+                ExecResult::Truncated(Vec::new())
+            }
+        }
+    }
+
+    fn kill(&mut self) {
+        kill_and_wait(self.child);
+        self.reaped = true;
+    }
+
+    fn ok(&mut self) -> ExecResult {
+        if self.conf.trace_cmp {
+            ExecResult::OkCmp {
+                covs: Vec::new(),
+                cmps: mem::take(&mut self.cmps),
+            }
+        } else {
+            ExecResult::Ok(Vec::new())
+        }
+    }
+
+    fn on_err(&mut self) -> ExecResult {
+        let status = reap_or_kill(self.child);
+        self.reaped = true;
+
+        let mut err_msg = Vec::new();
+        self.err.read_to_end(&mut err_msg).unwrap();
+        // silent child tells how it ended through its status only
+        let exit = status.and_then(exit_reason);
+        if self.cov_num == 0 {
+            return ExecResult::Failed(match exit {
+                Some(reason) if err_msg.is_empty() => reason,
+                _ => Reason(String::from_utf8_lossy(&err_msg).into_owned()),
+            });
+        }
+        if self.conf.memleak_check {
+            if let Some(leak) = check_leak(self.child.to_string()) {
+                return ExecResult::Failed(Reason(format!("CRASH-MEMLEAK:\n{}", leak)));
+            }
+        }
+        if !self.conf.trace_cmp {
+            // prog faulted after some calls, keep both
+            if !err_msg.is_empty() {
+                return ExecResult::Partial {
+                    covs: Vec::new(),
+                    err: Reason(String::from_utf8_lossy(&err_msg).into_owned()),
                 };
             }
-            Ok(_) => {
-                wait_time += Duration::from_millis(wait_timeout as u64);
-
-                if let Some(revents) = fds[1].revents() {
-                    if !revents.is_empty() {
-                        kill_and_wait(child);
-
-                        let mut err_msg = Vec::new();
-                        err.read_to_end(&mut err_msg).unwrap();
-                        return if covs.is_empty() {
-                            ExecResult::Failed(Reason(String::from_utf8(err_msg).unwrap()))
-                        } else {
-                            covs.shrink_to_fit();
-                            if conf.memleak_check {
-                                if let Some(leak) = check_leak(child.to_string()) {
-                                    return ExecResult::Failed(Reason(format!(
-                                        "CRASH-MEMLEAK:\n{}",
-                                        leak
-                                    )));
-                                }
-                            }
-                            ExecResult::Ok(covs)
-                        };
-                    }
+            if let Some(err) = exit {
+                return ExecResult::Partial {
+                    covs: Vec::new(),
+                    err,
+                };
+            }
+        }
+        self.ok()
+    }
+
+    /// Poll pipes until coverage of next call arrives, or execution is over and its result is set.
+    fn step(&mut self) -> Option<Vec<usize>> {
+        let wait_timeout = if self.conf.memleak_check { 3000 } else { 1000 };
+
+        loop {
+            let mut fds = [
+                PollFd::new(self.data.as_raw_fd(), PollFlags::POLLIN),
+                PollFd::new(self.err.as_raw_fd(), PollFlags::POLLIN),
+            ];
+            // quiet child is still running (pipes are open), only the deadline ends it if set
+            let poll_timeout = match self.deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis()
+                    .min(i32::MAX as u128) as i32,
+                None => wait_timeout,
+            };
+            match poll(&mut fds, poll_timeout) {
+                Ok(0) => {
+                    // timeout
+                    self.kill();
+                    self.result = Some(if self.cov_num == 0 {
+                        ExecResult::Failed(Reason(String::from(TIME_OUT)))
+                    } else {
+                        self.ok()
+                    });
+                    return None;
                 }
+                Ok(_) => {
+                    if let Some(revents) = fds[1].revents() {
+                        if !revents.is_empty() {
+                            self.result = Some(self.on_err());
+                            return None;
+                        }
+                    }
 
-                // Data pipe is ok
-                if let Some(revents) = fds[0].revents() {
-                    if revents.contains(PollFlags::POLLIN) {
-                        let len = data.read_u32::<NativeEndian>().unwrap_or_else(|e| {
-                            exits!(exitcode::OSERR, "Fail to read length of covs: {}", e)
-                        });
-                        let len = len as usize * mem::size_of::<usize>();
-                        let mut buf = bytes::BytesMut::with_capacity(len);
-                        unsafe {
-                            buf.set_len(len);
+                    // Data pipe is ok
+                    if let Some(revents) = fds[0].revents() {
+                        if revents.contains(PollFlags::POLLIN) {
+                            // child is on the same host, see transfer for endianness of wire protocol
+                            let header = self.data.read_u32::<NativeEndian>().unwrap_or_else(|e| {
+                                exits!(exitcode::OSERR, "Fail to read length of covs: {}", e)
+                            });
+                            let is_cmp = header & CMP_FRAME != 0;
+                            let len = (header & !CMP_FRAME) as usize * mem::size_of::<usize>();
+                            // comparisons are not coverage, they don't count toward the limit
+                            if !is_cmp {
+                                self.cov_bytes += len;
+                            }
+                            if matches!(self.conf.cov_limit, Some(limit) if self.cov_bytes > limit)
+                            {
+                                // stop collecting, keep coverage of previous calls
+                                self.kill();
+                                self.result = Some(ExecResult::Truncated(Vec::new()));
+                                return None;
+                            }
+                            let mut buf = vec![0u8; len];
+                            self.data.read_exact(&mut buf).unwrap_or_else(|e| {
+                                exits!(exitcode::IOERR, "Fail to read covs(len {}): {}", len, e)
+                            });
+                            self.notifer.notify();
+                            self.cov_num += 1;
+
+                            if is_cmp {
+                                let records = buf
+                                    .chunks_exact(mem::size_of::<u64>())
+                                    .map(|w| u64::from_ne_bytes(w.try_into().unwrap()))
+                                    .collect::<Vec<_>>();
+                                self.cmps.extend(parse_cmps(&records));
+                                continue;
+                            }
+                            let mut new_cov =
+                                Vec::from(buf.as_slice().as_slice_of::<usize>().unwrap());
+                            new_cov.shrink_to_fit();
+                            return Some(new_cov);
                         }
-                        data.read_exact(&mut buf).unwrap_or_else(|e| {
-                            exits!(exitcode::IOERR, "Fail to read covs(len {}): {}", len, e)
-                        });
-                        notifer.notify();
-
-                        let mut new_cov = Vec::from(buf.as_ref().as_slice_of::<usize>().unwrap());
-                        new_cov.shrink_to_fit();
-                        covs.push(new_cov);
                     }
                 }
-            }
-            Err(_) => {
-                wait_time += Duration::from_millis(wait_timeout as u64);
-                if wait_time > Duration::from_secs(10) {
-                    return ExecResult::Failed(Reason("Time out".to_string()));
+                Err(_) => {
+                    if Instant::now() >= self.poll_deadline {
+                        self.result = Some(ExecResult::Failed(Reason(String::from(TIME_OUT))));
+                        return None;
+                    }
                 }
             }
         }
     }
 }
 
+#[cfg(feature = "kcov")]
+impl Iterator for IterExec {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        if self.result.is_some() {
+            return None;
+        }
+        self.step()
+    }
+}
+
+#[cfg(feature = "kcov")]
+impl Drop for IterExec {
+    fn drop(&mut self) {
+        if !self.reaped {
+            kill_and_wait(self.child);
+        }
+    }
+}
+
 const MEM_LEAK: &str = "/sys/kernel/debug/kmemleak";
 
 fn mem_leak_clear() {
@@ -346,15 +751,98 @@ fn kill_and_wait(child: Pid) {
     waitpid(child, None);
 }
 
+/// Time given to child that closed its pipes to finish exiting before it's killed.
+const EXIT_GRACE: Duration = Duration::from_millis(50);
+
+/// Reap child if it ends by itself within `EXIT_GRACE`, kill it otherwise.
+/// Status is only returned for child that ended by itself.
+fn reap_or_kill(child: Pid) -> Option<WaitStatus> {
+    let start = Instant::now();
+    loop {
+        match waitpid(child, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) if start.elapsed() < EXIT_GRACE => {
+                sleep(Duration::from_millis(1))
+            }
+            Ok(WaitStatus::StillAlive) | Err(_) => {
+                kill_and_wait(child);
+                return None;
+            }
+            Ok(status) => return Some(status),
+        }
+    }
+}
+
+/// Reason of child that ended by itself without exiting cleanly, e.g. segfault.
+fn exit_reason(status: WaitStatus) -> Option<Reason> {
+    match status {
+        WaitStatus::Signaled(_, sig, _) => Some(Reason(format!("{} {}", KILLED_BY_SIGNAL, sig))),
+        WaitStatus::Exited(_, code) if code != exitcode::OK => {
+            Some(Reason(format!("{} {}", EXITED_WITH_CODE, code)))
+        }
+        _ => None,
+    }
+}
+
+/// Flag in length word of frame on data pipe, the frame carries comparison records of
+/// a call rather than its pcs. The rest of the word counts words of the frame.
+pub const CMP_FRAME: u32 = 1 << 31;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExecResult {
     Ok(Vec<Vec<usize>>),
     Failed(Reason),
+    /// Executor was killed by the guest OOM killer, detected by fuzzer from guest serial log.
+    Oom(Reason),
+    /// Same as `Ok`, but coverage exceeded limit, so coverage of later calls is missing.
+    Truncated(Vec<Vec<usize>>),
+    /// Same as `Ok`, with comparison operands of all calls if `Config::trace_cmp`
+    OkCmp {
+        covs: Vec<Vec<usize>>,
+        cmps: Vec<CmpArg>,
+    },
+    /// Prog wrote error after some calls finished, coverage of those calls and the error.
+    Partial {
+        covs: Vec<Vec<usize>>,
+        err: Reason,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Reason(pub String);
 
+/// Reason of prog that didn't finish its first call in time.
+pub const TIME_OUT: &str = "Time out";
+/// Prefix of reason of subprocess killed by signal it didn't get from executor, e.g. SIGSEGV.
+pub const KILLED_BY_SIGNAL: &str = "Killed by signal";
+/// Prefix of reason of subprocess that exited with non-zero code but wrote nothing.
+pub const EXITED_WITH_CODE: &str = "Exited with code";
+
+/// Cause of failed execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Prog itself didn't get through its first call: it timed out, exited silently or was
+    /// killed by signal. Common and expected for random progs.
+    Prog,
+    /// Executor errored: prog couldn't be compiled or run, kcov or resources failed,
+    /// any message is from executor rather than prog.
+    Executor,
+}
+
+impl Reason {
+    pub fn kind(&self) -> FailureKind {
+        let reason = self.0.trim();
+        if reason.is_empty()
+            || reason == TIME_OUT
+            || reason.starts_with(KILLED_BY_SIGNAL)
+            || reason.starts_with(EXITED_WITH_CODE)
+        {
+            FailureKind::Prog
+        } else {
+            FailureKind::Executor
+        }
+    }
+}
+
 impl fmt::Display for Reason {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(f, "{}", self.0)
@@ -383,7 +871,7 @@ pub fn sync_exec(
     use jit::exec;
     #[cfg(feature = "syscall")]
     use syscall::exec;
-    exec(p, t, out, waiter);
+    exec(p, t, out, waiter, conf);
 }
 
 #[cfg(not(feature = "kcov"))]
@@ -395,3 +883,319 @@ fn sync_exec(p: &Prog, t: &Target) {
 
     exec(p, t);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_pipe() {
+        use std::io::Write;
+
+        for size in [1, usize::MAX].iter() {
+            let (mut rp, mut wp) = data_pipe(*size).unwrap();
+            let cov: Vec<usize> = vec![0xffff_ffff_8100_0000, 0xffff_ffff_8100_0010];
+            wp.write_u32::<NativeEndian>(cov.len() as u32).unwrap();
+            wp.write_all(cov.as_byte_slice()).unwrap();
+
+            let len = rp.read_u32::<NativeEndian>().unwrap() as usize;
+            let mut buf = vec![0u8; len * mem::size_of::<usize>()];
+            rp.read_exact(&mut buf).unwrap();
+            assert_eq!(buf.as_slice().as_slice_of::<usize>().unwrap(), &cov[..]);
+        }
+    }
+
+    /// Fixtures shared by tests of `IterExec`.
+    #[cfg(feature = "kcov")]
+    mod fixture {
+        use super::*;
+
+        /// Short-lived child standing for prog subprocess, it still exits by itself if test
+        /// fails before `IterExec` kills it.
+        pub fn sleeper() -> std::process::Child {
+            std::process::Command::new("sleep")
+                .arg("1")
+                .spawn()
+                .unwrap()
+        }
+
+        pub fn conf() -> Config {
+            Config {
+                memleak_check: false,
+                concurrency: false,
+                sandbox: CloneFlags::empty(),
+                cov_limit: None,
+                run_as: None,
+                trace_cmp: false,
+                pipe_size: None,
+                exec_timeout: None,
+                backend: crate::ExecBackend::Tcc,
+            }
+        }
+
+        /// Iterate execution of `child` over given pipes, at most `take` calls, then check
+        /// child is reaped, so nothing is left behind by test.
+        pub fn run(
+            mut child: std::process::Child,
+            rp: PipeReader,
+            err_rp: PipeReader,
+            conf: &Config,
+            take: usize,
+        ) -> (Vec<Vec<usize>>, ExecResult) {
+            let (notifier, _waiter) = crate::utils::event();
+            let pid = Pid::from_raw(child.id() as i32);
+            let mut iter = IterExec::new(pid, rp, err_rp, notifier, conf);
+            let covs = iter.by_ref().take(take).collect::<Vec<_>>();
+            let ret = iter.finish();
+            assert!(child.try_wait().is_err());
+            (covs, ret)
+        }
+    }
+
+    #[cfg(feature = "kcov")]
+    #[test]
+    fn cov_exceeds_pipe() {
+        use std::io::Write;
+        use std::sync::mpsc::channel;
+
+        // pipe of 1 byte is rounded up to a page, coverage of the call is far larger
+        let cov = (0..64 * 1024)
+            .map(|i| 0xffff_ffff_8100_0000 + i * 16)
+            .collect::<Vec<usize>>();
+        let (rp, mut wp) = data_pipe(1).unwrap();
+        let (err_rp, err_wp) = new_pipe("create err pipe").unwrap();
+        let child = fixture::sleeper();
+        let writer = {
+            let cov = cov.clone();
+            std::thread::spawn(move || {
+                // blocks until pipe is drained
+                wp.write_u32::<NativeEndian>(cov.len() as u32).unwrap();
+                wp.write_all(cov.as_byte_slice()).unwrap();
+                drop(err_wp);
+            })
+        };
+
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            tx.send(fixture::run(
+                child,
+                rp,
+                err_rp,
+                &fixture::conf(),
+                usize::MAX,
+            ))
+            .unwrap();
+        });
+        let (got, ret) = rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("execution hangs on coverage larger than pipe");
+        writer.join().unwrap();
+        assert_eq!(got, vec![cov]);
+        assert!(matches!(ret, ExecResult::Ok(ref c) if c.is_empty()));
+    }
+
+    #[cfg(feature = "kcov")]
+    #[test]
+    fn cov_limit() {
+        use std::io::Write;
+
+        let (rp, mut wp) = data_pipe(DEFAULT_PIPE_SIZE).unwrap();
+        let (err_rp, err_wp) = new_pipe("create err pipe").unwrap();
+        let child = fixture::sleeper();
+        let covs: Vec<Vec<usize>> = vec![vec![0xffff_ffff_8100_0000], vec![0; 1024]];
+        for cov in covs.iter() {
+            wp.write_u32::<NativeEndian>(cov.len() as u32).unwrap();
+            wp.write_all(cov.as_byte_slice()).unwrap();
+        }
+        drop(err_wp);
+
+        // second call exceeds the limit, coverage of first call is kept
+        let conf = Config {
+            cov_limit: Some(4096),
+            ..fixture::conf()
+        };
+        let (got, ret) = fixture::run(child, rp, err_rp, &conf, usize::MAX);
+        assert_eq!(got, covs[..1]);
+        assert!(matches!(ret, ExecResult::Truncated(_)));
+    }
+
+    #[cfg(feature = "kcov")]
+    #[test]
+    fn cov_limit_ignores_cmps() {
+        use std::io::Write;
+
+        let (rp, mut wp) = data_pipe(DEFAULT_PIPE_SIZE).unwrap();
+        let (err_rp, err_wp) = new_pipe("create err pipe").unwrap();
+        let child = fixture::sleeper();
+        let cmps = vec![0u64; 8 * crate::cover::KCOV_CMP_RECORD];
+        let cov: Vec<usize> = vec![0xffff_ffff_8100_0000, 0xffff_ffff_8100_0010];
+        wp.write_u32::<NativeEndian>(cmps.len() as u32 | CMP_FRAME)
+            .unwrap();
+        wp.write_all(cmps.as_byte_slice()).unwrap();
+        wp.write_u32::<NativeEndian>(cov.len() as u32).unwrap();
+        wp.write_all(cov.as_byte_slice()).unwrap();
+        drop(err_wp);
+
+        // limit only fits pcs of the call, comparisons are far larger
+        let conf = Config {
+            cov_limit: Some(cov.len() * mem::size_of::<usize>()),
+            trace_cmp: true,
+            ..fixture::conf()
+        };
+        let (covs, ret) = fixture::run(child, rp, err_rp, &conf, usize::MAX);
+        assert_eq!(covs, vec![cov]);
+        match ret {
+            ExecResult::OkCmp { cmps, .. } => assert_eq!(cmps.len(), 8),
+            ret => panic!("unexpected result: {:?}", ret),
+        }
+    }
+
+    #[cfg(feature = "kcov")]
+    #[test]
+    fn partial() {
+        use std::io::Write;
+
+        let (rp, mut wp) = data_pipe(DEFAULT_PIPE_SIZE).unwrap();
+        let (err_rp, mut err_wp) = new_pipe("create err pipe").unwrap();
+        let child = fixture::sleeper();
+        let cov: Vec<usize> = vec![0xffff_ffff_8100_0000, 0xffff_ffff_8100_0010];
+        wp.write_u32::<NativeEndian>(cov.len() as u32).unwrap();
+        wp.write_all(cov.as_byte_slice()).unwrap();
+        // error comes after coverage of first call
+        let writer = std::thread::spawn(move || {
+            sleep(Duration::from_millis(200));
+            err_wp.write_all(b"fault in call 1").unwrap();
+        });
+
+        let (covs, ret) = fixture::run(child, rp, err_rp, &fixture::conf(), usize::MAX);
+        writer.join().unwrap();
+        match ret {
+            ExecResult::Partial { err, .. } => assert_eq!(err.0, "fault in call 1"),
+            ret => panic!("unexpected result: {:?}", ret),
+        }
+        assert_eq!(covs, vec![cov]);
+    }
+
+    #[cfg(feature = "kcov")]
+    #[test]
+    fn iter_exec() {
+        use std::io::Write;
+
+        let covs: Vec<Vec<usize>> = vec![
+            vec![0xffff_ffff_8100_0000, 0xffff_ffff_8100_0010],
+            vec![0xffff_ffff_8100_0020],
+            vec![
+                0xffff_ffff_8100_0030,
+                0xffff_ffff_8100_0040,
+                0xffff_ffff_8100_0050,
+            ],
+        ];
+        let run = |take: usize| {
+            let (rp, mut wp) = data_pipe(DEFAULT_PIPE_SIZE).unwrap();
+            let (err_rp, err_wp) = new_pipe("create err pipe").unwrap();
+            let child = fixture::sleeper();
+            for cov in covs.iter() {
+                wp.write_u32::<NativeEndian>(cov.len() as u32).unwrap();
+                wp.write_all(cov.as_byte_slice()).unwrap();
+            }
+            // child exits quietly after last call
+            let writer = std::thread::spawn(move || {
+                sleep(Duration::from_millis(200));
+                drop(err_wp);
+            });
+            let ret = fixture::run(child, rp, err_rp, &fixture::conf(), take);
+            writer.join().unwrap();
+            ret
+        };
+
+        // coverage of each call is yielded as it arrives
+        let (got, ret) = run(usize::MAX);
+        assert_eq!(got, covs);
+        assert!(matches!(ret, ExecResult::Ok(ref c) if c.is_empty()));
+
+        // execution is killed once caller stops iterating
+        let (got, ret) = run(1);
+        assert_eq!(got, covs[..1]);
+        assert!(matches!(ret, ExecResult::Truncated(_)));
+    }
+
+    #[test]
+    fn stable_covs() {
+        let runs = vec![
+            vec![vec![1, 2, 3, 4], vec![7, 8], vec![9]],
+            vec![vec![1, 3, 4, 5], vec![8, 7]],
+            vec![vec![4, 3, 1], vec![7, 8, 10]],
+        ];
+        assert_eq!(intersect_covs(&runs), vec![vec![1, 3, 4], vec![7, 8]]);
+        assert_eq!(intersect_covs(&runs[..1]), runs[0]);
+        assert_eq!(intersect_covs(&[]), Vec::<Vec<usize>>::new());
+    }
+
+    #[cfg(feature = "kcov")]
+    #[test]
+    fn child_signaled() {
+        let (rp, _wp) = data_pipe(DEFAULT_PIPE_SIZE).unwrap();
+        let (err_rp, err_wp) = new_pipe("create err pipe").unwrap();
+        let child = std::process::Command::new("sh")
+            .args(&["-c", "kill -SEGV $$"])
+            .stdout(std::process::Stdio::null())
+            .stderr(err_wp)
+            .spawn()
+            .unwrap();
+
+        // silent child tells how it ended through its status only
+        let (covs, ret) = fixture::run(child, rp, err_rp, &fixture::conf(), usize::MAX);
+        assert!(covs.is_empty());
+        match ret {
+            ExecResult::Failed(reason) => {
+                assert_eq!(reason.0, "Killed by signal SIGSEGV");
+                assert_eq!(reason.kind(), FailureKind::Prog);
+            }
+            ret => panic!("unexpected result: {:?}", ret),
+        }
+    }
+
+    #[test]
+    fn pid_sandbox() {
+        // unsharing pid namespace needs CAP_SYS_ADMIN
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let run = |f: fn()| match fork().unwrap() {
+            ForkResult::Child => {
+                sandbox(CloneFlags::CLONE_NEWPID);
+                f();
+                exit(getpid().as_raw())
+            }
+            ForkResult::Parent { child } => waitpid(child, None).unwrap(),
+        };
+        assert!(matches!(run(|| ()), WaitStatus::Exited(_, 1)));
+        let segv = || unsafe { std::ptr::null_mut::<u8>().write_volatile(0) };
+        assert!(matches!(
+            run(segv),
+            WaitStatus::Signaled(_, Signal::SIGSEGV, _)
+        ));
+    }
+
+    #[test]
+    fn failure_kind() {
+        assert_eq!(Reason(String::new()).kind(), FailureKind::Prog);
+        assert_eq!(Reason(String::from(TIME_OUT)).kind(), FailureKind::Prog);
+        assert_eq!(
+            Reason(String::from("Killed by signal SIGSEGV")).kind(),
+            FailureKind::Prog
+        );
+        assert_eq!(
+            Reason(String::from("Exited with code 3")).kind(),
+            FailureKind::Prog
+        );
+        assert_eq!(
+            Reason(String::from("Fail to execute: KcovEnableErr")).kind(),
+            FailureKind::Executor
+        );
+        assert_eq!(
+            Reason(String::from("Prog send blocked")).kind(),
+            FailureKind::Executor
+        );
+    }
+}
@@ -1,30 +1,62 @@
+use crate::cover::{KCOV, KCOV_CMP_RECORD};
+use crate::exec::CMP_FRAME;
 use crate::utils::Waiter;
+use crate::{Config, ExecBackend};
 use core::c;
 use core::c::cths::CTHS;
 use core::c::iter_trans;
 use core::prog::Prog;
 use core::target::Target;
+use nix::fcntl::{open, OFlag};
+use nix::libc::{c_void, dlopen, dlsym, RTLD_NOW};
+use nix::sys::stat::Mode;
 use os_pipe::PipeWriter;
+use std::collections::hash_map::DefaultHasher;
 use std::ffi::CString;
 use std::fmt::Write;
-use std::fs::{create_dir_all, write};
-use std::io::ErrorKind;
+use std::fs::{create_dir_all, remove_file, rename, write, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Write as _};
 use std::os::raw::c_int;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::*;
-use std::path::PathBuf;
-use std::process::exit;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
 use tcc::{Context, Guard};
 
 #[cfg(feature = "kcov")]
-pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
-    prepare_env();
+pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter, conf: &Config) {
+    let run_as = conf.run_as;
+    let trace_cmp = conf.trace_cmp;
+    // kcov is only accessible to root, open it before dropping privileges.
+    let kcov_fd = run_as.map(|_| {
+        open(KCOV, OFlag::O_RDWR, Mode::empty())
+            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to open {}: {}", KCOV, e))
+    });
     let p = {
-        instrument_prog(p, t, out.as_raw_fd(), waiter.as_raw_fd()).unwrap_or_else(|e| {
+        instrument_prog(
+            p,
+            t,
+            out.as_raw_fd(),
+            waiter.as_raw_fd(),
+            kcov_fd,
+            trace_cmp,
+        )
+        .unwrap_or_else(|e| {
             eprintln!("{}", e);
             exit(exitcode::SOFTWARE);
         })
     };
 
+    if let ExecBackend::Native { cc, build_dir } = &conf.backend {
+        let execute = load_native(cc, build_dir, &p).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(exitcode::SOFTWARE);
+        });
+        return run_execute(execute, run_as);
+    }
+
+    prepare_env();
     let p = CString::new(p.as_bytes()).unwrap();
     let sym = CString::new("execute").unwrap();
 
@@ -44,7 +76,14 @@ pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
         let symbol = p.get_symbol(&sym).unwrap();
         std::mem::transmute(symbol)
     };
+    run_execute(execute, run_as)
+}
 
+#[cfg(feature = "kcov")]
+fn run_execute(execute: fn() -> c_int, run_as: Option<(u32, u32)>) {
+    if let Some(user) = run_as {
+        super::drop_privileges(user);
+    }
     let code = execute();
     if code != 0 {
         exits!(
@@ -105,6 +144,8 @@ pub fn instrument_prog(
     t: &Target,
     data_fd: RawFd,
     sync_fd: RawFd,
+    kcov_fd: Option<RawFd>,
+    trace_cmp: bool,
 ) -> Result<String, String> {
     let mut includes = hashset! {
         "stdio.h".to_string(),
@@ -126,21 +167,23 @@ pub fn instrument_prog(
 #define KCOV_DISABLE     _IO('c', 101)
 #define COVER_SIZE       1024*1024
 #define KCOV_TRACE_PC    0
+#define KCOV_TRACE_CMP   1
     "#;
 
     let sync_send = format!(
         r#"
-int sync_send(unsigned long *cover, uint32_t len){{
+int sync_send(unsigned long *cover, uint32_t len, uint32_t flag){{
     if (len == 0){{
         return 0;
     }}
     char *cover_ = (void*)(cover + 1);
     int l2;
     int event_fd = {}, data_fd = {};
+    uint32_t header = len | flag;
     char l[4];
     char event[8];
 
-    memcpy(l, &len, 4);
+    memcpy(l, &header, 4);
     if (write(data_fd, l, 4) == -1){{
         return -1;
     }}
@@ -166,13 +209,17 @@ int sync_send(unsigned long *cover, uint32_t len){{
         sync_fd, data_fd
     );
 
+    let open_kcov = match kcov_fd {
+        Some(fd) => format!("fd = {};", fd),
+        None => format!("fd = open(\"{}\", O_RDWR);", KCOV),
+    };
     let kcov_open = format!(
         r#"
     int fd;
     unsigned long *cover;
     uint32_t len = 0;
 
-    fd = open("/sys/kernel/debug/kcov", O_RDWR);
+    {}
     if (fd == -1)
             return {};
     if (ioctl(fd, KCOV_INIT_TRACE, COVER_SIZE))
@@ -182,11 +229,23 @@ int sync_send(unsigned long *cover, uint32_t len){{
     if ((void*)cover == MAP_FAILED)
             return {};
     "#,
+        open_kcov,
         StatusCode::KcovOpenErr as i32,
         StatusCode::KcovInitErr as i32,
         StatusCode::MmapErr as i32
     );
 
+    // In cmp mode, cover[0] counts records rather than words, records are sent as is in
+    // frames flagged by CMP_FRAME.
+    let (mode, words, flag) = if trace_cmp {
+        (
+            "KCOV_TRACE_CMP",
+            format!(" * {}", KCOV_CMP_RECORD),
+            CMP_FRAME,
+        )
+    } else {
+        ("KCOV_TRACE_PC", String::new(), 0)
+    };
     let mut stmts = Vec::new();
     for (i, s) in iter_trans(p, t).enumerate() {
         let fn_info = t.fn_of(p.calls[i].fid);
@@ -209,18 +268,21 @@ int sync_send(unsigned long *cover, uint32_t len){{
         let generated_call = s.to_string();
         let s = format!(
             r#"
-    if (ioctl(fd, KCOV_ENABLE, KCOV_TRACE_PC))
+    if (ioctl(fd, KCOV_ENABLE, {}))
             return {};
     cover[0] = 0;
     {}
-    len = cover[0];
+    len = cover[0]{};
     if (ioctl(fd, KCOV_DISABLE, 0))
             return {};
-    if (sync_send(cover, len) == -1)
+    if (sync_send(cover, len, {}) == -1)
         return {};"#,
+            mode,
             StatusCode::KcovEnableErr as i32,
             generated_call,
+            words,
             StatusCode::KcovDisableErr as i32,
+            flag,
             StatusCode::CovSendErr as i32
         );
         stmts.push(s);
@@ -291,6 +353,78 @@ impl From<i32> for StatusCode {
     }
 }
 
+/// Compile instrumented prog to shared object with native compiler `cc`, load it and
+/// return its `execute` function. Objects are cached in `build_dir` by hash of source.
+#[cfg(feature = "kcov")]
+fn load_native(cc: &str, build_dir: &Path, p: &str) -> Result<fn() -> c_int, String> {
+    let mut hasher = DefaultHasher::new();
+    p.hash(&mut hasher);
+    let name = format!("{:016x}", hasher.finish());
+    let so = build_dir.join(format!("{}.so", name));
+    if !so.exists() {
+        compile_native(cc, p, build_dir, &name)?;
+    }
+
+    let so_path = CString::new(so.to_str().unwrap()).unwrap();
+    let sym = CString::new("execute").unwrap();
+    let handle = unsafe { dlopen(so_path.as_ptr(), RTLD_NOW) };
+    if handle.is_null() {
+        return Err(format!("Fail to load compiled prog {}", so.display()));
+    }
+    // handle is never closed, process exits after execution
+    let symbol = unsafe { dlsym(handle, sym.as_ptr()) };
+    if symbol.is_null() {
+        return Err(String::from("Symbol execute not found in compiled prog"));
+    }
+    Ok(unsafe { std::mem::transmute::<*mut c_void, fn() -> c_int>(symbol) })
+}
+
+/// Build `p` to `name.so` in `build_dir`. Output goes to a file of this process first and
+/// is renamed once complete, so a partial object is never loaded.
+#[cfg(feature = "kcov")]
+fn compile_native(cc: &str, p: &str, build_dir: &Path, name: &str) -> Result<(), String> {
+    let pid = std::process::id();
+    let src = build_dir.join(format!("{}.{}.c", name, pid));
+    let tmp_so = build_dir.join(format!("{}.{}.so", name, pid));
+    let so = build_dir.join(format!("{}.so", name));
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&src)
+        .map_err(|e| format!("Fail to create {}: {}", src.display(), e))?;
+    f.write_all(p.as_bytes())
+        .map_err(|e| format!("Fail to write {}: {}", src.display(), e))?;
+    drop(f);
+    // reserve output name, cc then writes into the file created here
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_so)
+        .map_err(|e| format!("Fail to create {}: {}", tmp_so.display(), e))?;
+
+    let output = Command::new(cc)
+        .args(&["-shared", "-fPIC", "-w", "-o"])
+        .arg(&tmp_so)
+        .arg(&src)
+        .output();
+    remove_file(&src).ok();
+    let output = output.map_err(|e| {
+        remove_file(&tmp_so).ok();
+        format!("Fail to spawn {}: {}", cc, e)
+    })?;
+    if !output.status.success() {
+        remove_file(&tmp_so).ok();
+        return Err(format!(
+            "Fail to compile generated prog with {}:\n{}",
+            cc,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    rename(&tmp_so, &so).map_err(|e| format!("Fail to rename {}: {}", tmp_so.display(), e))
+}
+
 const TCC_INCLUDE: &str = "/usr/local/include/healer/tcc";
 
 fn new_tcc<'a, 'b>(g: &'a mut Guard) -> Context<'a, 'b> {
@@ -333,3 +467,35 @@ fn prepare_env() {
     write(tcc_include.join("stddef.h"), stddef_h).unwrap();
     write(tcc_include.join("varargs.h"), varargs_h).unwrap();
 }
+
+#[cfg(all(test, feature = "kcov"))]
+mod tests {
+    use super::*;
+    use std::fs::{metadata, read_dir, remove_dir_all};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn native_cached_in_private_dir() {
+        let (cc, build_dir) = match ExecBackend::native(String::from("cc")).unwrap() {
+            ExecBackend::Native { cc, build_dir } => (cc, build_dir),
+            ExecBackend::Tcc => unreachable!(),
+        };
+        assert_eq!(
+            metadata(&build_dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+
+        let p = "int execute(){ return 42; }";
+        let execute = load_native(&cc, &build_dir, p).unwrap();
+        assert_eq!(execute(), 42);
+        let built = read_dir(&build_dir).unwrap().count();
+        assert_eq!(built, 1, "only the object is left");
+
+        // same source is loaded from cache, a broken compiler is never run
+        let execute = load_native("/nonexistent/cc", &build_dir, p).unwrap();
+        assert_eq!(execute(), 42);
+        assert!(load_native("/nonexistent/cc", &build_dir, "int execute(){ return 1; }").is_err());
+        assert_eq!(read_dir(&build_dir).unwrap().count(), built);
+        remove_dir_all(&build_dir).unwrap();
+    }
+}
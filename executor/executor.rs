@@ -1,5 +1,5 @@
 use core::target::Target;
-use executor::{exec_loop, Config};
+use executor::{exec_loop, handshake, parse_sandbox, parse_user, Config, ExecBackend};
 use fots::types::Items;
 use std::fs::{read, write};
 use std::net::TcpStream;
@@ -23,6 +23,34 @@ pub struct Settings {
 
     #[structopt(short = "m", long = "memleak-check")]
     memleak_check: bool,
+
+    /// Comma separated namespaces to unshare before executing each prog, e.g. mount,pid,net
+    #[structopt(short = "s", long)]
+    sandbox: Option<String>,
+
+    /// Max bytes of coverage collected per execution, coverage beyond this is dropped
+    #[structopt(short = "l", long = "cov-limit")]
+    cov_limit: Option<usize>,
+
+    /// Run progs as unprivileged user, `uid[:gid]`
+    #[structopt(short = "u", long)]
+    user: Option<String>,
+
+    /// Size of pipe coverage is transferred through in bytes
+    #[structopt(short = "P", long = "pipe-size")]
+    pipe_size: Option<usize>,
+
+    /// Total time budget of one execution in ms, child is killed once it's exhausted
+    #[structopt(short = "T", long = "exec-timeout")]
+    exec_timeout: Option<u64>,
+
+    /// Compile progs to shared object with this native compiler, e.g. gcc, instead of tcc
+    #[structopt(long)]
+    cc: Option<String>,
+
+    /// Also trace comparison operands, kernel needs CONFIG_KCOV_ENABLE_COMPARISONS
+    #[structopt(long = "trace-cmp")]
+    trace_cmp: bool,
 }
 
 fn main() {
@@ -43,7 +71,7 @@ fn main() {
     }
 
     let mut retry = 1;
-    let conn = loop {
+    let mut conn = loop {
         match TcpStream::connect(&settings.addr) {
             Ok(c) => break c,
             Err(e) => {
@@ -58,9 +86,38 @@ fn main() {
         }
     };
 
+    handshake(&mut conn).unwrap_or_else(|e| {
+        eprintln!("Handshake with healer-fuzzer failed: {}", e);
+        exit(exitcode::PROTOCOL);
+    });
+
+    let sandbox = parse_sandbox(settings.sandbox.as_deref().unwrap_or("")).unwrap_or_else(|e| {
+        eprintln!("Invalid sandbox: {}", e);
+        exit(exitcode::USAGE);
+    });
+    let run_as = settings.user.as_deref().map(|user| {
+        parse_user(user).unwrap_or_else(|e| {
+            eprintln!("Invalid user: {}", e);
+            exit(exitcode::USAGE);
+        })
+    });
+    let backend = match settings.cc {
+        Some(cc) => ExecBackend::native(cc).unwrap_or_else(|e| {
+            eprintln!("Fail to prepare native backend: {}", e);
+            exit(exitcode::CANTCREAT);
+        }),
+        None => ExecBackend::Tcc,
+    };
     let conf = Config {
         memleak_check: settings.memleak_check,
         concurrency: settings.concurrency,
+        sandbox,
+        cov_limit: settings.cov_limit,
+        run_as,
+        trace_cmp: settings.trace_cmp,
+        pipe_size: settings.pipe_size,
+        exec_timeout: settings.exec_timeout.map(Duration::from_millis),
+        backend,
     };
 
     exec_loop(target, conn, conf)
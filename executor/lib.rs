@@ -5,8 +5,17 @@ extern crate nix;
 #[macro_use]
 extern crate maplit;
 
+use core::prog::Prog;
 use core::target::Target;
-use std::io::{Read, Write};
+use nix::sched::CloneFlags;
+use rand::random;
+use std::env::temp_dir;
+use std::fs::DirBuilder;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
 
 #[macro_use]
 #[allow(dead_code)]
@@ -16,11 +25,139 @@ pub mod cover;
 pub mod exec;
 pub mod transfer;
 
-pub use exec::{ExecResult, Reason};
+pub use cover::CmpArg;
+pub use exec::{ExecResult, FailureKind, Reason, EXITED_WITH_CODE, KILLED_BY_SIGNAL, TIME_OUT};
 
+#[derive(Clone)]
 pub struct Config {
     pub memleak_check: bool,
     pub concurrency: bool,
+    /// Namespaces unshared before executing each prog, empty means no sandbox
+    pub sandbox: CloneFlags,
+    /// Max bytes of coverage collected per execution, unlimited if None
+    pub cov_limit: Option<usize>,
+    /// Uid and gid progs run as, current user if None
+    pub run_as: Option<(u32, u32)>,
+    /// Also trace comparison operands with KCOV_TRACE_CMP in a second run of each prog,
+    /// execution results in `ExecResult::OkCmp`
+    pub trace_cmp: bool,
+    /// Size of pipe coverage is transferred through, `exec::DEFAULT_PIPE_SIZE` if None.
+    /// Kernel default size is used if it can't be set.
+    pub pipe_size: Option<usize>,
+    /// Total time budget of one execution, child is killed once it's exhausted. If None,
+    /// child is killed at its first quiet period without output (1s, 3s with memleak check).
+    pub exec_timeout: Option<Duration>,
+    /// How generated prog is compiled before execution
+    pub backend: ExecBackend,
+}
+
+/// Compiler of generated prog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecBackend {
+    /// Compile in memory with embedded tcc, fast, but limited to C tcc supports
+    Tcc,
+    /// Compile to shared object with native compiler `cc`, e.g. gcc, and load it.
+    /// Objects are cached by source hash in private directory `build_dir`.
+    Native { cc: String, build_dir: PathBuf },
+}
+
+impl ExecBackend {
+    /// Native backend with compiler `cc`, building in a new private (0700) temp directory.
+    pub fn native(cc: String) -> Result<Self, String> {
+        loop {
+            let build_dir = temp_dir().join(format!("healer-cc-{:016x}", random::<u64>()));
+            match DirBuilder::new().mode(0o700).create(&build_dir) {
+                Ok(_) => return Ok(ExecBackend::Native { cc, build_dir }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("fail to create {}: {}", build_dir.display(), e)),
+            }
+        }
+    }
+}
+
+/// Namespaces supported by sandbox
+pub const SANDBOX_NAMESPACES: [&str; 5] = ["mount", "pid", "net", "ipc", "uts"];
+
+/// Parse comma separated namespaces, e.g. "mount,pid,net".
+pub fn parse_sandbox(namespaces: &str) -> Result<CloneFlags, String> {
+    let mut flags = CloneFlags::empty();
+    for ns in namespaces
+        .split(',')
+        .map(str::trim)
+        .filter(|ns| !ns.is_empty())
+    {
+        flags |= match ns {
+            "mount" => CloneFlags::CLONE_NEWNS,
+            "pid" => CloneFlags::CLONE_NEWPID,
+            "net" => CloneFlags::CLONE_NEWNET,
+            "ipc" => CloneFlags::CLONE_NEWIPC,
+            "uts" => CloneFlags::CLONE_NEWUTS,
+            _ => {
+                return Err(format!(
+                    "unknown namespace {}, supported: {:?}",
+                    ns, SANDBOX_NAMESPACES
+                ))
+            }
+        };
+    }
+    Ok(flags)
+}
+
+/// Parse user of form `uid[:gid]`, gid is same as uid if absent.
+pub fn parse_user(user: &str) -> Result<(u32, u32), String> {
+    let mut ids = user.splitn(2, ':');
+    let uid = ids.next().unwrap();
+    let uid = uid
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| format!("invalid uid {}: {}", uid, e))?;
+    let gid = match ids.next() {
+        Some(gid) => gid
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("invalid gid {}: {}", gid, e))?,
+        None => uid,
+    };
+    Ok((uid, gid))
+}
+
+/// Retries of executing prog when fds or processes are exhausted
+const EXHAUSTED_RETRY: u32 = 5;
+
+/// Execute prog, back off and retry without background runs if resources are exhausted.
+fn exec_with_backoff(p: Prog, t: &Target, conf: &Config) -> ExecResult {
+    let mut conf = conf.clone();
+    let mut retry = 0;
+    loop {
+        match exec::fork_exec(p.clone(), t, &conf) {
+            Ok(result) => return result,
+            Err(e) => {
+                if retry == EXHAUSTED_RETRY {
+                    return ExecResult::Failed(Reason(e.to_string()));
+                }
+                // background runs fork more processes
+                conf.concurrency = false;
+                sleep(Duration::from_millis(100 << retry));
+                retry += 1;
+            }
+        }
+    }
+}
+
+/// Answer handshake of fuzzer with version of this executor, error if versions mismatch.
+/// Pid of this executor follows, fuzzer matches it against processes killed in guest.
+pub fn handshake<T: Read + Write>(conn: &mut T) -> Result<(), String> {
+    let theirs: transfer::Handshake =
+        transfer::recv(conn).map_err(|e| format!("fail to recv handshake: {}", e))?;
+    let ours = transfer::Handshake::current();
+    transfer::send(&ours, conn).map_err(|e| format!("fail to send handshake: {}", e))?;
+    if theirs != ours {
+        return Err(format!(
+            "protocol version mismatch, fuzzer: {}, executor: {}",
+            theirs.version, ours.version
+        ));
+    }
+    transfer::send(&std::process::id(), conn).map_err(|e| format!("fail to send pid: {}", e))
 }
 
 /// Read prog from conn, translate by target, run the translated test program.
@@ -29,7 +166,7 @@ pub fn exec_loop<T: Read + Write>(t: Target, mut conn: T, conf: Config) {
         let p = transfer::recv_prog(&mut conn)
             .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to recv:{}", e));
 
-        let result = exec::fork_exec(p, &t, &conf);
+        let result = exec_with_backoff(p, &t, &conf);
 
         transfer::send(&result, &mut conn)
             .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to Send {:?}:{}", result, e));
@@ -1,8 +1,15 @@
 //! A implementation of very sample object transfer protocal.
+//!
+//! Header and body are encoded with bincode's default options, integers (including coverage)
+//! are fixed size and little endian, so fuzzer and executor may run on hosts of different
+//! endianness.
+//!
+//! Each connection starts with a [`Handshake`]: fuzzer sends its protocol version, executor
+//! replies with its own, both sides give up if they differ. Executor then sends its pid.
 
 use crate::ExecResult;
-use bytes::BytesMut;
 use core::prog::Prog;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::io;
 use std::io::{Read, Write};
@@ -13,6 +20,27 @@ pub struct Header {
     pub len: u32,
 }
 
+/// Version of protocol between fuzzer and executor, bump it whenever encoding of prog,
+/// result or coverage changes.
+/// 2: `ExecResult::OkCmp`, 3: `ExecResult::Partial`, 4: coverage in `ExecResult::OkCmp`,
+/// 5: pid of executor after handshake.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// First message of each connection.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u32,
+}
+
+impl Handshake {
+    /// Handshake of this build.
+    pub fn current() -> Self {
+        Handshake {
+            version: PROTOCOL_VERSION,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Io:{0}")]
@@ -22,22 +50,19 @@ pub enum Error {
 }
 
 pub fn recv_prog<S: Read>(src: &mut S) -> Result<Prog, Error> {
+    recv(src)
+}
+
+pub fn recv<T: DeserializeOwned, S: Read>(src: &mut S) -> Result<T, Error> {
     let header = Header::default();
     let headler_len = bincode::serialized_size(&header)? as usize;
 
-    let mut header_buf = BytesMut::with_capacity(headler_len);
-    assert!(header_buf.capacity() >= headler_len);
-    unsafe {
-        header_buf.set_len(headler_len);
-    }
+    let mut header_buf = vec![0u8; headler_len];
     src.read_exact(&mut header_buf)?;
     let header: Header = bincode::deserialize(&header_buf)?;
 
     let body_len = header.len as usize;
-    let mut body_buf = BytesMut::with_capacity(body_len);
-    unsafe {
-        body_buf.set_len(body_len);
-    }
+    let mut body_buf = vec![0u8; body_len];
     src.read_exact(&mut body_buf)?;
 
     bincode::deserialize(&body_buf).map_err(|e| e.into())
@@ -71,22 +96,49 @@ pub async fn async_send<T: Serialize, S: AsyncWrite + Unpin>(
 }
 
 pub async fn async_recv_result<T: AsyncRead + Unpin>(src: &mut T) -> Result<ExecResult, Error> {
+    async_recv(src).await
+}
+
+pub async fn async_recv<T: DeserializeOwned, S: AsyncRead + Unpin>(
+    src: &mut S,
+) -> Result<T, Error> {
     let header = Header::default();
     let headler_len = bincode::serialized_size(&header)? as usize;
-    let mut header_buf = BytesMut::with_capacity(headler_len);
-    assert!(header_buf.capacity() >= headler_len);
-    unsafe {
-        header_buf.set_len(headler_len);
-    }
+    let mut header_buf = vec![0u8; headler_len];
     src.read_exact(&mut header_buf).await?;
     let header: Header = bincode::deserialize(&header_buf)?;
 
     let body_len = header.len as usize;
-    let mut body_buf = BytesMut::with_capacity(body_len);
-    unsafe {
-        body_buf.set_len(body_len);
-    }
+    let mut body_buf = vec![0u8; body_len];
     src.read_exact(&mut body_buf).await?;
 
     bincode::deserialize(&body_buf).map_err(|e| e.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fixed_endian() {
+        let pc: usize = 0xffff_ffff_8100_0102;
+        let mut sent = Vec::new();
+        send(&ExecResult::Ok(vec![vec![pc]]), &mut sent).unwrap();
+
+        // Same message encoded by hand as any peer would, regardless of its endianness:
+        // variant, number of calls, number of pcs of first call, pc.
+        let mut body = Vec::new();
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&1u64.to_le_bytes());
+        body.extend_from_slice(&1u64.to_le_bytes());
+        body.extend_from_slice(&(pc as u64).to_le_bytes());
+        let mut expected = (body.len() as u32).to_le_bytes().to_vec();
+        expected.extend(body);
+        assert_eq!(sent, expected);
+
+        match async_recv_result(&mut expected.as_slice()).await.unwrap() {
+            ExecResult::Ok(covs) => assert_eq!(covs, vec![vec![pc]]),
+            _ => panic!("unexpected result"),
+        }
+    }
+}
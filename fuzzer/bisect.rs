@@ -0,0 +1,84 @@
+//! Bisect the call of crashing prog that triggers crash.
+use crate::exec::Executor;
+use crate::fuzzer::ReproConf;
+use crate::Config;
+use core::c::{iter_trans, to_script};
+use core::prog::Prog;
+use core::target::Target;
+
+/// Find shortest prefix of `p` that still crashes with the same signature, its last call
+/// is the culprit. Prefixes are assumed monotonic, i.e. once a prefix crashes longer ones
+/// crash too, so only log(len) prefixes are reproduced, unlike full minimization.
+pub async fn bisect(cfg: &Config, t: &Target, p: &Prog) {
+    if p.calls.is_empty() {
+        exits!(exitcode::DATAERR, "Empty prog, nothing to bisect");
+    }
+    let repro = cfg.repro.clone().unwrap_or_default();
+    let mut executor = Executor::new(cfg);
+    executor.start().await;
+
+    info!("Reproducing crash of prog with {} calls ...", p.len());
+    let signature = reproduce(&mut executor, t, p, &repro, None)
+        .await
+        .unwrap_or_else(|| exits!(exitcode::DATAERR, "Prog doesn't crash, nothing to bisect"));
+    info!(
+        "Crashed: {}",
+        signature.lines().next().unwrap_or("unknown title")
+    );
+
+    // p[..=hi] always crashes, p[..lo] never
+    let (mut lo, mut hi) = (0, p.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        info!("Trying prefix of {} calls ...", mid + 1);
+        let prefix = p.sub_prog(mid);
+        if reproduce(&mut executor, t, &prefix, &repro, Some(&signature))
+            .await
+            .is_some()
+        {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    let prefix = p.sub_prog(hi);
+    println!(
+        "Culprit: call {} ({})",
+        hi,
+        t.fn_of(p.calls[hi].fid).dec_name
+    );
+    println!("{}", iter_trans(&prefix, t).nth(hi).unwrap());
+    println!("Shortest crashing prefix:");
+    println!("{}", to_script(&prefix, t));
+}
+
+/// Run prog repeatedly, return signature of crash if it crashes often enough.
+/// Only crashes with `expected` signature count if given.
+async fn reproduce(
+    executor: &mut Executor,
+    t: &Target,
+    p: &Prog,
+    repro: &ReproConf,
+    expected: Option<&str>,
+) -> Option<String> {
+    let mut signature = expected.map(String::from);
+    let mut crashes = 0;
+    for _ in 0..repro.runs() {
+        if let Err(crash) = executor.exec(p, t).await {
+            executor.start().await;
+            let sig = crash.unwrap_or_default().signature();
+            match signature.as_ref() {
+                Some(s) if s != &sig => continue,
+                Some(_) => (),
+                None => signature = Some(sig),
+            }
+            crashes += 1;
+        }
+    }
+    if crashes as f64 / repro.runs() as f64 >= repro.threshold() {
+        signature
+    } else {
+        None
+    }
+}
@@ -0,0 +1,227 @@
+//! Http/json api for querying and pausing running fuzzer.
+use crate::crash::CrashMeta;
+use crate::metrics::SharedStats;
+use crate::report::TestCaseRecord;
+use crate::stats::Stats;
+use crate::utils::wait_stop;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::{timeout, Duration};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ControlConf {
+    /// Address the api is served on, e.g. "127.0.0.1:8080"
+    pub listen: String,
+}
+
+impl ControlConf {
+    pub fn check(&self) {
+        if self.listen.parse::<SocketAddr>().is_err() {
+            eprintln!(
+                "Config Error: invalid control listen address {}",
+                self.listen
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+/// Max time of reading request and writing response of one connection
+const CONN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Endpoints of control api.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    /// GET /stats, latest stats of each sampler
+    Stats,
+    /// GET /crashes, recorded crash signatures with their hits
+    Crashes,
+    /// POST /pause
+    Pause,
+    /// POST /resume
+    Resume,
+}
+
+/// Request of first line of http request, or status line of error response.
+fn parse_request(req: &str) -> Result<Request, &'static str> {
+    let mut parts = req.lines().next().unwrap_or_default().split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    let req = match path {
+        Some("/stats") => (Request::Stats, "GET"),
+        Some("/crashes") => (Request::Crashes, "GET"),
+        Some("/pause") => (Request::Pause, "POST"),
+        Some("/resume") => (Request::Resume, "POST"),
+        _ => return Err("404 Not Found"),
+    };
+    if method == Some(req.1) {
+        Ok(req.0)
+    } else {
+        Err("405 Method Not Allowed")
+    }
+}
+
+#[derive(Serialize)]
+struct GuestStats {
+    /// Id of guest if corpus is not shared
+    guest: Option<usize>,
+    stats: Stats,
+}
+
+#[derive(Serialize)]
+struct CrashEntry {
+    signature: String,
+    #[serde(flatten)]
+    meta: CrashMeta,
+}
+
+/// State shared by all connections.
+struct Control {
+    stats: Vec<SharedStats>,
+    record: Arc<TestCaseRecord>,
+    paused: Arc<AtomicBool>,
+}
+
+impl Control {
+    async fn handle(&self, req: Request) -> String {
+        match req {
+            Request::Stats => {
+                let mut latest = Vec::with_capacity(self.stats.len());
+                for (guest, s) in self.stats.iter() {
+                    latest.push(GuestStats {
+                        guest: *guest,
+                        stats: s.read().await.clone(),
+                    });
+                }
+                serde_json::to_string(&latest).unwrap()
+            }
+            Request::Crashes => {
+                let crashes = self
+                    .record
+                    .crashes()
+                    .await
+                    .into_iter()
+                    .map(|(signature, meta)| CrashEntry { signature, meta })
+                    .collect::<Vec<_>>();
+                serde_json::to_string(&crashes).unwrap()
+            }
+            Request::Pause | Request::Resume => {
+                let paused = req == Request::Pause;
+                if self.paused.swap(paused, Ordering::SeqCst) != paused {
+                    if paused {
+                        warn!("Fuzzing paused through control api");
+                    } else {
+                        warn!("Fuzzing resumed through control api");
+                    }
+                }
+                format!("{{\"paused\":{}}}", paused)
+            }
+        }
+    }
+}
+
+/// Serve control api on `listen` until shutdown.
+pub async fn serve(
+    listen: String,
+    stats: Vec<SharedStats>,
+    record: Arc<TestCaseRecord>,
+    paused: Arc<AtomicBool>,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let mut listener = TcpListener::bind(&listen).await.unwrap_or_else(|e| {
+        exits!(
+            exitcode::UNAVAILABLE,
+            "Fail to serve control api on {}: {}",
+            listen,
+            e
+        )
+    });
+    info!("Serving control api on {}", listen);
+    let control = Arc::new(Control {
+        stats,
+        record,
+        paused,
+    });
+    let accept = async {
+        loop {
+            let conn = match listener.accept().await {
+                Ok((conn, _)) => conn,
+                Err(e) => {
+                    warn!("Fail to accept control connection: {}", e);
+                    continue;
+                }
+            };
+            let control = control.clone();
+            tokio::spawn(async move {
+                if let Err(e) = timeout(CONN_TIMEOUT, respond(conn, &control)).await {
+                    warn!("Control connection: {}", e);
+                }
+            });
+        }
+    };
+    tokio::select! {
+        _ = wait_stop(&mut shutdown) => (),
+        _ = accept => (),
+    }
+}
+
+async fn respond(mut conn: TcpStream, control: &Control) {
+    let mut req = vec![0; 1024];
+    let n = match conn.read(&mut req).await {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let resp = match parse_request(&String::from_utf8_lossy(&req[..n])) {
+        Ok(req) => {
+            let body = control.handle(req).await;
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        Err(status) => format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        ),
+    };
+    let _ = conn.write_all(resp.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            parse_request("GET /stats HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Ok(Request::Stats)
+        );
+        assert_eq!(
+            parse_request("GET /crashes HTTP/1.1\r\n"),
+            Ok(Request::Crashes)
+        );
+        assert_eq!(
+            parse_request("POST /pause HTTP/1.1\r\n"),
+            Ok(Request::Pause)
+        );
+        assert_eq!(
+            parse_request("POST /resume HTTP/1.1\r\n"),
+            Ok(Request::Resume)
+        );
+        assert_eq!(
+            parse_request("GET /pause HTTP/1.1\r\n"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(
+            parse_request("GET /metrics HTTP/1.1\r\n"),
+            Err("404 Not Found")
+        );
+        assert_eq!(parse_request(""), Err("404 Not Found"));
+    }
+}
@@ -1,17 +1,104 @@
+use crate::feedback::Branch;
+use crate::prog_io::{self, ProgIoError};
 use core::prog::Prog;
-use std::collections::HashSet;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
+use std::process::exit;
 use tokio::sync::Mutex;
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CorpusConf {
+    /// Max number of progs in corpus, unlimited by default
+    pub max_size: Option<usize>,
+}
+
+impl CorpusConf {
+    pub fn check(&self) {
+        if self.max_size == Some(0) {
+            eprintln!("Config Error: corpus max_size must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Corpus {
     pub inner: Mutex<HashSet<Prog>>,
+    /// Coverage of progs, only tracked if size of corpus is capped
+    cover: Mutex<Cover>,
+    max_size: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Cover {
+    /// Insertion order and branches of each prog
+    progs: HashMap<Prog, (usize, HashSet<Branch>)>,
+    /// Number of progs covering each branch
+    refs: HashMap<Branch, usize>,
+    inserted: usize,
+}
+
+impl Cover {
+    fn add(&mut self, p: Prog, branches: HashSet<Branch>) {
+        for b in branches.iter() {
+            *self.refs.entry(b.clone()).or_default() += 1;
+        }
+        self.progs.insert(p, (self.inserted, branches));
+        self.inserted += 1;
+    }
+
+    /// Remove the least valuable prog, the one covering fewest branches and oldest among them.
+    /// Sole cover of any branch is never removed, so coverage of corpus doesn't shrink.
+    fn evict(&mut self) -> Option<Prog> {
+        let refs = &self.refs;
+        let victim = self
+            .progs
+            .iter()
+            .filter(|(_, (_, branches))| branches.iter().all(|b| refs[b] > 1))
+            .min_by_key(|(_, (inserted, branches))| (branches.len(), *inserted))
+            .map(|(p, _)| p.clone())?;
+        let (_, branches) = self.progs.remove(&victim).unwrap();
+        for b in branches.iter() {
+            *self.refs.get_mut(b).unwrap() -= 1;
+        }
+        Some(victim)
+    }
 }
 
 impl Corpus {
-    pub async fn insert(&self, p: Prog) -> bool {
+    /// Corpus holding at most `max_size` progs, see `insert`.
+    pub fn with_max_size(max_size: Option<usize>) -> Self {
+        Self {
+            max_size,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Insert prog with branches it covers. If corpus exceeds its max size then, least
+    /// valuable progs are evicted, unless each remaining prog is the sole cover of some branch.
+    pub async fn insert(&self, p: Prog, branches: HashSet<Branch>) -> bool {
         let mut inner = self.inner.lock().await;
-        inner.insert(p)
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return inner.insert(p),
+        };
+        if !inner.insert(p.clone()) {
+            return false;
+        }
+        let mut cover = self.cover.lock().await;
+        cover.add(p, branches);
+        while inner.len() > max_size {
+            match cover.evict() {
+                Some(victim) => inner.remove(&victim),
+                None => break,
+            };
+        }
+        true
     }
 
     pub async fn len(&self) -> usize {
@@ -35,14 +122,70 @@ impl Corpus {
             })
             .collect::<Vec<_>>();
         progs.shrink_to_fit();
-        bincode::serialize(&progs)
+        prog_io::dump(&progs)
     }
 
-    pub fn load(c: &[u8]) -> bincode::Result<Self> {
-        let mut progs: Vec<Prog> = bincode::deserialize(c)?;
-        progs.shrink_to_fit();
+    pub fn load(c: &[u8]) -> Result<Self, ProgIoError> {
         Ok(Self {
-            inner: Mutex::new(HashSet::from_iter(progs)),
+            inner: Mutex::new(HashSet::from_iter(Self::load_progs(c)?)),
+            ..Default::default()
         })
     }
+
+    /// Progs of dumped corpus, see `prog_io::load`.
+    pub fn load_progs(c: &[u8]) -> Result<Vec<Prog>, ProgIoError> {
+        let mut progs: Vec<Prog> = prog_io::load(c)?;
+        progs.shrink_to_fit();
+        Ok(progs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feedback::Block;
+    use core::prog::{Call, Prog};
+
+    fn prog(fid: usize) -> Prog {
+        let mut p = Prog::new(0);
+        p.add_call(Call::new(fid));
+        p
+    }
+
+    fn branches(raw: &[usize]) -> HashSet<Branch> {
+        raw.iter()
+            .map(|b| Branch::from((Block::from(0), Block::from(*b))))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn dump_load() {
+        let corpus = Corpus::default();
+        for fid in 1..3 {
+            corpus.insert(prog(fid), HashSet::new()).await;
+        }
+
+        let data = corpus.dump().await.unwrap();
+        let loaded = Corpus::load(&data).unwrap();
+        assert_eq!(*loaded.inner.lock().await, *corpus.inner.lock().await);
+
+        let mut newer = data;
+        newer[4..8].copy_from_slice(&(prog_io::PROG_VERSION + 1).to_le_bytes());
+        assert!(Corpus::load_progs(&newer).is_err());
+    }
+
+    #[tokio::test]
+    async fn evict_least_valuable() {
+        let corpus = Corpus::with_max_size(Some(2));
+        assert!(corpus.insert(prog(1), branches(&[1, 2, 3])).await);
+        assert!(corpus.insert(prog(2), branches(&[2])).await);
+        // prog 2 is covered by prog 1 entirely
+        assert!(corpus.insert(prog(3), branches(&[4])).await);
+        let inner = corpus.inner.lock().await.clone();
+        assert_eq!(inner, vec![prog(1), prog(3)].into_iter().collect());
+
+        // every prog is sole cover of some branch, cap is exceeded instead
+        assert!(corpus.insert(prog(4), branches(&[5])).await);
+        assert_eq!(corpus.len().await, 3);
+    }
 }
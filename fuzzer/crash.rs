@@ -0,0 +1,461 @@
+//! Extract title and call trace of kernel crash log for grouping crashes.
+
+use chrono::{DateTime, Local};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+lazy_static! {
+    static ref TIMESTAMP: Regex = Regex::new(r"^\[\s*\d+\.\d+\]\s?").unwrap();
+    static ref FRAME: Regex =
+        Regex::new(r"^\s*(\? )?(\[<([0-9a-f]+)>\] )?([A-Za-z0-9_.$]+)\+0x[0-9a-f]+/0x[0-9a-f]+")
+            .unwrap();
+    static ref OFFSET: Regex = Regex::new(r"\+0x[0-9a-f]+/0x[0-9a-f]+").unwrap();
+    static ref ADDR: Regex = Regex::new(r"0x[0-9a-f]+|[0-9a-f]{8,}").unwrap();
+    static ref CPU_PID: Regex = Regex::new(r"CPU: \d+ PID: \d+").unwrap();
+}
+
+/// Beginning of crash titles reported by linux kernel.
+pub const TITLE_PATTERNS: [&str; 9] = [
+    "BUG:",
+    "WARNING:",
+    "INFO:",
+    "general protection fault",
+    "kernel BUG at",
+    "Kernel panic",
+    "Unable to handle kernel",
+    "divide error",
+    "CRASH-MEMLEAK",
+];
+
+/// Lines of log without timestamp prefix.
+fn lines_of(log: &str) -> impl Iterator<Item = &str> {
+    log.lines().map(|l| match TIMESTAMP.find(l) {
+        Some(m) => &l[m.end()..],
+        None => l,
+    })
+}
+
+/// First line of log that looks like a crash title.
+pub fn title_of(log: &str) -> Option<&str> {
+    lines_of(log)
+        .map(str::trim)
+        .find(|l| TITLE_PATTERNS.iter().any(|p| l.contains(p)))
+}
+
+/// Title without addresses, offsets and cpu/pid, which vary between crashes of same bug.
+pub fn normalize_title(title: &str) -> String {
+    let title = OFFSET.replace_all(title, "");
+    let title = CPU_PID.replace_all(&title, "CPU: * PID: *");
+    ADDR.replace_all(&title, "*").into_owned()
+}
+
+/// Reliable frame of call trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub func: String,
+    /// Address in `[<...>]` before function, not printed by recent kernels
+    pub addr: Option<u64>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            Some(addr) => write!(f, "{} [<{:x}>]", self.func, addr),
+            None => write!(f, "{}", self.func),
+        }
+    }
+}
+
+/// Function names of reliable frames in first call trace of log.
+pub fn frames_of(log: &str) -> Vec<String> {
+    trace_of(log).into_iter().map(|f| f.func).collect()
+}
+
+/// Reliable frames in first call trace of log, with their addresses.
+pub fn trace_of(log: &str) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    let mut in_trace = false;
+    for l in lines_of(log) {
+        if !in_trace {
+            in_trace = l.trim_start().starts_with("Call Trace:");
+            continue;
+        }
+        match FRAME.captures(l) {
+            Some(cap) => {
+                // frames marked with '?' are unreliable
+                if cap.get(1).is_none() {
+                    frames.push(Frame {
+                        func: cap[4].to_string(),
+                        addr: cap
+                            .get(3)
+                            .and_then(|a| u64::from_str_radix(a.as_str(), 16).ok()),
+                    });
+                }
+            }
+            None if frames.is_empty() && !l.contains("---[ end trace") => continue,
+            None => break,
+        }
+    }
+    frames
+}
+
+/// Signature of crash, crashes with same signature are likely caused by same bug.
+/// It's normalized title and first frame of call trace that is not panic or report machinery.
+pub fn signature_of(log: &str) -> String {
+    let mut sig = title_of(log).map(normalize_title).unwrap_or_default();
+    if let Some(f) = frames_of(log).iter().find(|f| !is_machinery(f)) {
+        sig.push('\n');
+        sig.push_str(f);
+    }
+    sig
+}
+
+/// Functions printing reports, panicking or handling traps, found on top of call traces
+/// of all kinds of bugs.
+const MACHINERY: [&str; 21] = [
+    "dump_stack",
+    "dump_stack_lvl",
+    "__dump_stack",
+    "show_stack",
+    "print_address_description",
+    "print_report",
+    "check_memory_region",
+    "__warn",
+    "warn_slowpath_fmt",
+    "report_bug",
+    "fixup_bug",
+    "handle_bug",
+    "panic",
+    "die",
+    "__die",
+    "do_trap",
+    "do_error_trap",
+    "do_invalid_op",
+    "no_context",
+    "page_fault",
+    "do_page_fault",
+];
+
+/// Prefixes of machinery functions, e.g. kasan hooks and exception entries.
+const MACHINERY_PREFIXES: [&str; 5] = ["kasan_", "__kasan_", "__asan_", "exc_", "asm_exc_"];
+
+fn is_machinery(func: &str) -> bool {
+    MACHINERY.contains(&func) || MACHINERY_PREFIXES.iter().any(|p| func.starts_with(p))
+}
+
+/// Class of kernel crash, judged by its title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CrashKind {
+    Kasan,
+    Gpf,
+    Warning,
+    Bug,
+    /// Hung task, rcu stall, lockup or guest unreachable without any report
+    Hang,
+    Other,
+}
+
+impl CrashKind {
+    fn of(title: &str) -> Self {
+        // gpf title ends with kernel config, which may contain "KASAN"
+        if title.contains("general protection fault") {
+            CrashKind::Gpf
+        } else if title.contains("KASAN") {
+            CrashKind::Kasan
+        } else if title.starts_with("WARNING:") {
+            CrashKind::Warning
+        } else if ["hung", "stall", "lockup", "blocked for more than"]
+            .iter()
+            .any(|k| title.contains(k))
+        {
+            CrashKind::Hang
+        } else if title.starts_with("BUG:") || title.starts_with("kernel BUG at") {
+            CrashKind::Bug
+        } else {
+            CrashKind::Other
+        }
+    }
+}
+
+impl fmt::Display for CrashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            CrashKind::Kasan => "KASAN",
+            CrashKind::Gpf => "GPF",
+            CrashKind::Warning => "WARNING",
+            CrashKind::Bug => "BUG",
+            CrashKind::Hang => "Hang",
+            CrashKind::Other => "Other",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// Crash log broken into parts useful for triage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CrashReport {
+    /// First report line, e.g. "KASAN: use-after-free in foo_bar+0x1a/0x40"
+    pub title: String,
+    pub kind: CrashKind,
+    /// Function names of reliable frames of first call trace
+    pub call_trace: Vec<String>,
+    pub raw: String,
+}
+
+/// Parse crash log, log without any report line is treated as hang.
+pub fn parse(log: &str) -> CrashReport {
+    let (title, kind) = match title_of(log) {
+        // KASAN reports are prefixed with "BUG: " for historical reasons
+        Some(title) => (
+            title.trim_start_matches("BUG: ").to_string(),
+            CrashKind::of(title),
+        ),
+        None => (String::from("no output from kernel"), CrashKind::Hang),
+    };
+    CrashReport {
+        title,
+        kind,
+        call_trace: frames_of(log),
+        raw: log.to_string(),
+    }
+}
+
+/// Log with timestamps, addresses, offsets and cpu/pid stripped from each line.
+pub fn normalize_log(log: &str) -> String {
+    lines_of(log)
+        .map(normalize_title)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// First occurrence and number of occurrences of a crash signature, across runs once
+/// merged with meta saved by previous runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashMeta {
+    pub first_seen: DateTime<Local>,
+    pub hits: usize,
+}
+
+/// Occurrences of each crash signature, only first occurrence is worth recording,
+/// unless it wasn't reproduced and a later one is.
+#[derive(Default)]
+pub struct CrashDedup {
+    seen: HashMap<String, CrashMeta>,
+    /// Signatures with a saved case, and whether that case was reproduced
+    saved: HashMap<String, bool>,
+}
+
+impl CrashDedup {
+    /// Count one occurrence of signature, return its meta so far.
+    pub fn insert(&mut self, signature: String) -> CrashMeta {
+        let meta = self.seen.entry(signature).or_insert_with(|| CrashMeta {
+            first_seen: Local::now(),
+            hits: 0,
+        });
+        meta.hits += 1;
+        meta.clone()
+    }
+
+    /// Merge meta of signature saved by a previous run: keep its first_seen, add its hits.
+    pub fn merge(&mut self, signature: &str, saved: &CrashMeta) -> CrashMeta {
+        let meta = self
+            .seen
+            .entry(signature.to_string())
+            .or_insert_with(|| CrashMeta {
+                first_seen: saved.first_seen,
+                hits: 0,
+            });
+        meta.first_seen = meta.first_seen.min(saved.first_seen);
+        meta.hits += saved.hits;
+        meta.clone()
+    }
+
+    /// Whether crash of signature should be saved: no case of it is saved yet, or only
+    /// an unreproduced one that this reproduced crash replaces. Marked saved if so.
+    pub fn claim(&mut self, signature: &str, reproduced: bool) -> bool {
+        match self.saved.get_mut(signature) {
+            Some(saved) if *saved || !reproduced => false,
+            Some(saved) => {
+                *saved = true;
+                true
+            }
+            None => {
+                self.saved.insert(signature.to_string(), reproduced);
+                true
+            }
+        }
+    }
+
+    /// Whether a case of signature is saved.
+    pub fn is_saved(&self, signature: &str) -> bool {
+        self.saved.contains_key(signature)
+    }
+
+    pub fn meta(&self, signature: &str) -> Option<&CrashMeta> {
+        self.seen.get(signature)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &CrashMeta)> {
+        self.seen.iter()
+    }
+
+    /// Number of distinct signatures with a saved case.
+    pub fn unique(&self) -> usize {
+        self.saved.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r"[   12.345678] BUG: KASAN: use-after-free in foo_bar+0x1a/0x40
+[   12.345679] Read of size 8 at addr ffff888012345678 by task a.out/123
+[   12.345680] CPU: 0 PID: 123 Comm: a.out Not tainted 5.8.0 #1
+[   12.345681] Call Trace:
+[   12.345682]  dump_stack+0x9d/0xe0
+[   12.345683]  ? foo_bar+0x1a/0x40
+[   12.345684]  foo_bar+0x1a/0x40
+[   12.345685]  __x64_sys_ioctl+0x8c/0xd0
+[   12.345686] Allocated by task 123:
+";
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            title_of(LOG),
+            Some("BUG: KASAN: use-after-free in foo_bar+0x1a/0x40")
+        );
+        assert_eq!(
+            normalize_title(title_of(LOG).unwrap()),
+            "BUG: KASAN: use-after-free in foo_bar"
+        );
+        assert_eq!(
+            frames_of(LOG),
+            vec!["dump_stack", "foo_bar", "__x64_sys_ioctl"]
+        );
+        assert_eq!(
+            signature_of(LOG),
+            signature_of(&LOG.replace("0x1a", "0x2b"))
+        );
+        assert!(trace_of(LOG).iter().all(|f| f.addr.is_none()));
+
+        let old = "Call Trace:\n [<ffffffff8100101a>] foo_bar+0x1a/0x40\n";
+        let frame = &trace_of(old)[0];
+        assert_eq!(frame.addr, Some(0xffffffff8100101a));
+        assert_eq!(frame.to_string(), "foo_bar [<ffffffff8100101a>]");
+    }
+
+    #[test]
+    fn signature_skips_machinery() {
+        let a = r"[   12.345678] BUG: KASAN: use-after-free in foo_bar+0x1a/0x40
+[   12.345679] Read of size 8 at addr ffff888012345678 by task a.out/123
+[   12.345680] CPU: 0 PID: 123 Comm: a.out Not tainted 5.8.0 #1
+[   12.345681] Call Trace:
+[   12.345682]  [<ffffffff81001000>] dump_stack+0x9d/0xe0
+[   12.345683]  [<ffffffff81002000>] print_address_description+0x1f/0x220
+[   12.345684]  [<ffffffff81003000>] kasan_report+0x37/0x50
+[   12.345685]  [<ffffffff8100101a>] foo_bar+0x1a/0x40
+[   12.345686]  [<ffffffff81004000>] __x64_sys_ioctl+0x8c/0xd0
+";
+        let b = a
+            .replace("ffff888012345678", "ffff888087654321")
+            .replace("ffffffff81", "ffffffffa1")
+            .replace("0x1a/0x40", "0x2b/0x40")
+            .replace("0x9d/0xe0", "0xa3/0xe0");
+        assert_ne!(a, b);
+        assert_eq!(signature_of(a), signature_of(&b));
+        assert_eq!(
+            signature_of(a),
+            "BUG: KASAN: use-after-free in foo_bar\nfoo_bar"
+        );
+    }
+
+    const GPF: &str = r"[  105.170151] general protection fault, probably for non-canonical address 0xdffffc0000000003: 0000 [#1] PREEMPT SMP KASAN
+[  105.171564] KASAN: null-ptr-deref in range [0x0000000000000018-0x000000000000001f]
+[  105.172394] CPU: 1 PID: 8435 Comm: syz-executor Not tainted 5.8.0-rc4 #1
+[  105.173189] RIP: 0010:tcp_sendmsg_locked+0x2a1/0x2d70
+[  105.178832] Call Trace:
+[  105.179099]  tcp_sendmsg+0x2b/0x40
+[  105.179482]  inet_sendmsg+0x99/0xe0
+[  105.179862]  ? inet_send_prepare+0x4d0/0x4d0
+[  105.180317]  sock_sendmsg+0xcf/0x120
+[  105.180702]  __sys_sendto+0x21c/0x320
+[  105.181087]  do_syscall_64+0x60/0xe0
+[  105.181465]  entry_SYSCALL_64_after_hwframe+0x44/0xa9
+[  105.181999] Modules linked in:
+";
+
+    #[test]
+    fn report() {
+        let kasan = super::parse(LOG);
+        assert_eq!(kasan.kind, CrashKind::Kasan);
+        assert_eq!(kasan.title, "KASAN: use-after-free in foo_bar+0x1a/0x40");
+        assert_eq!(kasan.call_trace, frames_of(LOG));
+        assert_eq!(kasan.raw, LOG);
+
+        let gpf = super::parse(GPF);
+        assert_eq!(gpf.kind, CrashKind::Gpf);
+        assert!(gpf.title.starts_with("general protection fault"));
+        assert_eq!(
+            gpf.call_trace,
+            vec![
+                "tcp_sendmsg",
+                "inet_sendmsg",
+                "sock_sendmsg",
+                "__sys_sendto",
+                "do_syscall_64",
+                "entry_SYSCALL_64_after_hwframe"
+            ]
+        );
+
+        assert_eq!(super::parse("").kind, CrashKind::Hang);
+        assert_eq!(
+            super::parse("[  300.1] INFO: task a.out:123 blocked for more than 143 seconds.").kind,
+            CrashKind::Hang
+        );
+        assert_eq!(
+            super::parse("[  300.1] INFO: rcu_sched self-detected stall on CPU").kind,
+            CrashKind::Hang
+        );
+    }
+
+    #[test]
+    fn dedup() {
+        let mut dedup = CrashDedup::default();
+        assert_eq!(dedup.insert(signature_of(LOG)).hits, 1);
+        assert_eq!(
+            dedup
+                .insert(signature_of(&LOG.replace("CPU: 0", "CPU: 1")))
+                .hits,
+            2
+        );
+        assert_eq!(
+            normalize_log("[   1.000001] CPU: 3 PID: 42 at ffff888012345678"),
+            normalize_log("[   2.000002] CPU: 1 PID: 7 at ffff888087654321")
+        );
+        assert_eq!(dedup.insert(normalize_log("rcu stall")).hits, 1);
+        assert!(dedup.claim(&signature_of(LOG), false));
+        assert!(dedup.claim(&normalize_log("rcu stall"), true));
+        assert_eq!(dedup.unique(), 2);
+    }
+
+    #[test]
+    fn reproduced_replaces_unreproduced() {
+        let mut dedup = CrashDedup::default();
+        let sig = signature_of(LOG);
+        dedup.insert(sig.clone());
+        assert!(dedup.claim(&sig, false));
+        dedup.insert(sig.clone());
+        assert!(!dedup.claim(&sig, false));
+        dedup.insert(sig.clone());
+        assert!(dedup.claim(&sig, true));
+        dedup.insert(sig.clone());
+        assert!(!dedup.claim(&sig, true));
+        assert!(!dedup.claim(&sig, false));
+        assert_eq!(dedup.meta(&sig).unwrap().hits, 4);
+        assert_eq!(dedup.unique(), 1);
+    }
+}
@@ -1,22 +1,24 @@
 use crate::guest;
-use crate::guest::{Crash, Guest};
+use crate::guest::{CmdOutput, Crash, Guest, GuestError};
 use crate::utils::cli::{App, Arg, OptVal};
-use crate::utils::free_ipv4_port;
+use crate::utils::{free_ipv4_port, meminfo_mb};
 use crate::Config;
 use core::c::to_prog;
 use core::prog::Prog;
 use core::target::Target;
-use executor::transfer::{async_recv_result, async_send};
-use executor::{ExecResult, Reason};
+use executor::transfer::{async_recv, async_recv_result, async_send, Handshake};
+use executor::{ExecResult, Reason, KILLED_BY_SIGNAL, TIME_OUT};
+use nix::sys::signal::Signal;
 use std::env::temp_dir;
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use tokio::fs::write;
 use tokio::io::AsyncReadExt;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Child;
 use tokio::sync::oneshot;
-use tokio::time::{delay_for, timeout, Duration};
+use tokio::time::{timeout, Duration, Instant};
 
 // config for executor
 #[derive(Debug, Clone, Deserialize)]
@@ -26,6 +28,81 @@ pub struct ExecutorConf {
     pub concurrency: bool,
     pub memleak_check: bool,
     pub script_mode: bool,
+    /// Number of fresh boots for retrying kcov probe when guest is flaky, 3 by default
+    pub kcov_probe_retry: Option<u8>,
+    /// Namespaces to unshare before executing each prog, e.g. ["mount", "pid", "net"]
+    pub sandbox: Option<Vec<String>>,
+    /// Interval in seconds of running nop command in idle guest to keep it warm, disabled by default
+    pub keepalive_interval: Option<u64>,
+    /// Max bytes of coverage collected per execution, unlimited by default
+    pub cov_limit: Option<usize>,
+    /// Size in bytes of pipe executor transfers coverage through, 1MB by default
+    pub pipe_size: Option<usize>,
+    /// Number of executor processes running progs concurrently in each guest, 1 by default
+    pub procs: Option<usize>,
+    /// Run progs as unprivileged user in guest, `uid[:gid]`, root by default
+    pub user: Option<String>,
+    /// Shell command run in guest before each prog, not covered
+    pub pre_exec: Option<String>,
+    /// Shell command run in guest after each prog, not covered
+    pub post_exec: Option<String>,
+    /// Log output of post exec command, false by default
+    pub capture_post_exec: Option<bool>,
+    /// Reduce procs when guest memory is tight, `procs` is the upper bound
+    pub adaptive_procs: Option<AdaptiveProcsConf>,
+    /// Total time budget of one execution in ms, executor kills prog at its first quiet
+    /// period without output by default
+    pub exec_timeout_ms: Option<u64>,
+    /// Native compiler in guest progs are compiled with, e.g. gcc, embedded tcc by default
+    pub cc: Option<String>,
+    /// Also trace comparison operands of each prog in a second run, false by default.
+    /// Guest kernel needs CONFIG_KCOV_ENABLE_COMPARISONS.
+    pub trace_cmp: Option<bool>,
+}
+
+/// Config of tuning number of procs in use by available memory of guest
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdaptiveProcsConf {
+    /// Lower bound of procs in use, 1 by default
+    pub min_procs: Option<usize>,
+    /// Procs in use is reduced if available memory of guest is below this, in MB, 128 by default.
+    /// It grows again once available memory is twice of this.
+    pub low_mem: Option<u64>,
+    /// Interval in seconds of reading available memory of guest, 10 by default
+    pub interval: Option<u64>,
+}
+
+impl AdaptiveProcsConf {
+    pub fn check(&self, procs: usize) {
+        if self.min_procs() == 0 || self.min_procs() > procs {
+            eprintln!(
+                "Config Error: adaptive min procs {} must be in [1, {}]",
+                self.min_procs(),
+                procs
+            );
+            exit(exitcode::CONFIG)
+        }
+        if self.low_mem == Some(0) {
+            eprintln!("Config Error: adaptive low mem must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+        if self.interval == Some(0) {
+            eprintln!("Config Error: adaptive interval must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+    }
+
+    pub fn min_procs(&self) -> usize {
+        self.min_procs.unwrap_or(1)
+    }
+
+    pub fn low_mem(&self) -> u64 {
+        self.low_mem.unwrap_or(128)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(10))
+    }
 }
 
 impl ExecutorConf {
@@ -50,6 +127,92 @@ impl ExecutorConf {
                 exit(exitcode::CONFIG)
             }
         }
+
+        if let Some(sandbox) = self.sandbox.as_ref() {
+            if let Err(e) = executor::parse_sandbox(&sandbox.join(",")) {
+                eprintln!("Config Error: invalid sandbox: {}", e);
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let Some(user) = self.user.as_ref() {
+            if let Err(e) = executor::parse_user(user) {
+                eprintln!("Config Error: invalid executor user: {}", e);
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if self.cov_limit == Some(0) {
+            eprintln!("Config Error: coverage limit must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+
+        if self.pipe_size == Some(0) {
+            eprintln!("Config Error: pipe size must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+
+        if let Some(ms) = self.exec_timeout_ms {
+            if ms == 0 || ms >= EXEC_RESULT_TIMEOUT.as_millis() as u64 {
+                eprintln!(
+                    "Config Error: exec timeout must be between 1 and {} ms, fuzzer gives up waiting result then",
+                    EXEC_RESULT_TIMEOUT.as_millis() - 1
+                );
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if matches!(self.cc.as_deref(), Some(cc) if cc.trim().is_empty()) {
+            eprintln!("Config Error: empty executor cc");
+            exit(exitcode::CONFIG)
+        }
+
+        if self.keepalive_interval == Some(0) {
+            eprintln!("Config Error: keepalive interval must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+
+        if self.procs == Some(0) {
+            eprintln!("Config Error: executor procs must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+        if self.script_mode && self.procs() != 1 {
+            eprintln!("Config Error: multiple executor procs is not supported in script mode");
+            exit(exitcode::CONFIG)
+        }
+        if self.script_mode && self.trace_cmp() {
+            eprintln!("Config Error: trace cmp is not supported in script mode");
+            exit(exitcode::CONFIG)
+        }
+
+        if let Some(adaptive) = self.adaptive_procs.as_ref() {
+            adaptive.check(self.procs());
+        }
+
+        for hook in self.pre_exec.iter().chain(self.post_exec.iter()) {
+            if hook.trim().is_empty() {
+                eprintln!("Config Error: empty pre/post exec command");
+                exit(exitcode::CONFIG)
+            }
+        }
+        if self.pre_exec.is_some() || self.post_exec.is_some() {
+            if self.script_mode {
+                eprintln!("Config Error: pre/post exec command is not supported in script mode");
+                exit(exitcode::CONFIG)
+            }
+            if self.procs() != 1 {
+                eprintln!("Config Error: pre/post exec command requires executor procs 1");
+                exit(exitcode::CONFIG)
+            }
+        }
+    }
+
+    pub fn procs(&self) -> usize {
+        self.procs.unwrap_or(1)
+    }
+
+    pub fn trace_cmp(&self) -> bool {
+        self.trace_cmp.unwrap_or(false)
     }
 }
 
@@ -58,7 +221,7 @@ pub struct Executor {
 }
 
 enum ExecutorImpl {
-    Linux(LinuxExecutor),
+    Linux(Box<LinuxExecutor>),
     Scripy(ScriptExecutor),
 }
 
@@ -67,7 +230,7 @@ impl Executor {
         let inner = if cfg.executor.script_mode {
             ExecutorImpl::Scripy(ScriptExecutor::new(cfg))
         } else {
-            ExecutorImpl::Linux(LinuxExecutor::new(cfg))
+            ExecutorImpl::Linux(Box::new(LinuxExecutor::new(cfg)))
         };
         Self { inner }
     }
@@ -79,11 +242,145 @@ impl Executor {
         }
     }
 
-    pub async fn exec(&mut self, p: &Prog, t: &Target) -> Result<ExecResult, Option<Crash>> {
+    /// Like `start`, but return error instead of exiting, e.g. for guest of verify kernel.
+    pub async fn try_start(&mut self) -> Result<(), String> {
         match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.try_start().await,
+            ExecutorImpl::Scripy(ref mut e) => e.guest.boot().await.map_err(|e| e.to_string()),
+        }
+    }
+
+    pub async fn exec(&mut self, p: &Prog, t: &Target) -> Result<ExecResult, Option<Crash>> {
+        let ret = match self.inner {
             ExecutorImpl::Linux(ref mut e) => e.exec(p).await,
             ExecutorImpl::Scripy(ref mut e) => e.exec(p, t).await,
+        };
+        ret.map_err(|crash| {
+            crash.map(|mut crash| {
+                crash.kernel_version = self.guest().kernel_version();
+                crash
+            })
+        })
+    }
+
+    /// Execute progs concurrently on executor processes of guest, at most `procs` progs.
+    pub async fn exec_batch(
+        &mut self,
+        ps: &[Prog],
+        t: &Target,
+    ) -> Vec<Result<ExecResult, Option<Crash>>> {
+        let mut rets = match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.exec_batch(ps).await,
+            ExecutorImpl::Scripy(ref mut e) => vec![e.exec(&ps[0], t).await],
+        };
+        for ret in rets.iter_mut() {
+            if let Err(Some(ref mut crash)) = ret {
+                crash.kernel_version = self.guest().kernel_version();
+            }
         }
+        rets
+    }
+
+    /// Kill executor processes and guest, e.g. qemu, before fuzzer exits.
+    pub async fn shutdown(&mut self) {
+        match self.inner {
+            ExecutorImpl::Linux(ref mut e) => {
+                // handle should be set to kill on drop
+                for s in e.sessions.iter_mut() {
+                    s.exec_handle = None;
+                }
+                e.guest.shutdown().await
+            }
+            ExecutorImpl::Scripy(ref mut e) => e.guest.shutdown().await,
+        }
+    }
+
+    /// Number of progs could be executed concurrently.
+    pub fn procs(&self) -> usize {
+        match self.inner {
+            ExecutorImpl::Linux(ref e) => e.active_procs,
+            ExecutorImpl::Scripy(_) => 1,
+        }
+    }
+
+    /// Run nop command in guest to keep ssh connection and page cache warm.
+    /// Not counted as execution.
+    pub async fn keepalive(&self) {
+        if !self.guest().is_alive().await {
+            warn!("Keepalive: guest is not responding");
+        }
+    }
+
+    fn guest(&self) -> &Guest {
+        match self.inner {
+            ExecutorImpl::Linux(ref e) => &e.guest,
+            ExecutorImpl::Scripy(ref e) => &e.guest,
+        }
+    }
+}
+
+/// Max time of waiting result of one execution from executor
+const EXEC_RESULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Fresh boots for retrying start of executor when copying it to guest fails
+const START_RETRY: usize = 3;
+
+const OOM_MSG: &str = "Out of memory: Killed process";
+
+/// Parse `MemAvailable` of /proc/meminfo, in MB.
+fn mem_available(meminfo: &str) -> Option<u64> {
+    meminfo_mb(meminfo, "MemAvailable")
+}
+
+/// Max length of task comm in kernel messages, longer names are truncated
+const TASK_COMM_LEN: usize = 15;
+
+/// Process killed by guest OOM killer
+struct OomKill<'a> {
+    pid: u32,
+    /// Message of OOM killer
+    msg: &'a str,
+}
+
+/// Processes named `comm` killed by OOM killer in guest serial log, i.e. executors or
+/// their children. Other victims say nothing about executed progs.
+fn oom_kills<'a>(log: &'a str, comm: &str) -> Vec<OomKill<'a>> {
+    let comm = &comm.as_bytes()[..comm.len().min(TASK_COMM_LEN)];
+    log.lines()
+        .filter_map(|l| {
+            // e.g. Out of memory: Killed process 1234 (executor) total-vm:...
+            let killed = l[l.find(OOM_MSG)? + OOM_MSG.len()..].trim_start();
+            let start = killed.find('(')?;
+            let end = start + killed[start..].find(')')?;
+            if &killed.as_bytes()[start + 1..end] != comm {
+                return None;
+            }
+            let pid = killed[..start].trim().parse().ok()?;
+            Some(OomKill { pid, msg: l })
+        })
+        .collect()
+}
+
+/// OOM kills of session whose executor is `pid`, given its reply: executor itself was
+/// killed if its connection is lost, prog was killed if it failed with SIGKILL and a
+/// process other than executors of `sessions` was killed.
+fn oom_of(kills: &[OomKill], pid: Option<u32>, sessions: &[u32], reply: &Reply) -> Option<String> {
+    let sigkill = format!("{} {}", KILLED_BY_SIGNAL, Signal::SIGKILL);
+    let oom = kills
+        .iter()
+        .filter(|k| match reply {
+            Reply::Lost => Some(k.pid) == pid,
+            Reply::Result(ExecResult::Failed(reason)) => {
+                reason.0.trim() == sigkill && !sessions.contains(&k.pid)
+            }
+            _ => false,
+        })
+        .map(|k| k.msg)
+        .collect::<Vec<_>>();
+    if oom.is_empty() {
+        None
+    } else {
+        Some(oom.join("\n"))
     }
 }
 
@@ -103,7 +400,9 @@ impl ScriptExecutor {
     }
 
     pub async fn start(&mut self) {
-        self.guest.boot().await;
+        if let Err(e) = self.guest.boot().await {
+            exits!(e.code(), "{}", e);
+        }
     }
 
     pub async fn exec(&mut self, p: &Prog, t: &Target) -> Result<ExecResult, Option<Crash>> {
@@ -118,18 +417,29 @@ impl ScriptExecutor {
             exit(1);
         }
 
-        let guest_case_file = self.guest.copy(&tmp).await;
+        // case file changes on every execution, hashing it is useless
+        let guest_case_file = match self.guest.copy_force(&tmp).await {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("{}, rebooting guest...", e);
+                self.start().await;
+                return Ok(ExecResult::Failed(Reason(e.to_string())));
+            }
+        };
         let mut executor = App::new(self.path_on_host.to_str().unwrap());
         executor.arg(Arg::new_flag(guest_case_file.to_str().unwrap()));
 
-        let mut exec_handle = self.guest.run_cmd(&executor).await;
-
-        match timeout(Duration::new(15, 0), &mut exec_handle).await {
-            Err(_) => Ok(ExecResult::Failed(Reason("Time out".to_string()))),
-            Ok(_) => {
-                let mut stdout = exec_handle.stdout.take().unwrap();
-                let mut output = String::new();
-                stdout.read_to_string(&mut output).await.unwrap();
+        match self
+            .guest
+            .run_cmd_timeout(&executor, Duration::new(15, 0))
+            .await
+        {
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                Ok(ExecResult::Failed(Reason(TIME_OUT.to_string())))
+            }
+            Err(e) => exits!(exitcode::OSERR, "Fail to run executor: {}", e),
+            Ok(output) => {
+                let output = String::from_utf8_lossy(&output.stdout).into_owned();
                 self.parse_exec_result(output).await
             }
         }
@@ -151,12 +461,18 @@ impl ScriptExecutor {
             } else if result_line.contains("failed") {
                 return Ok(ExecResult::Failed(Reason(out)));
             } else if result_line.contains("crashed") {
-                return Err(Some(Crash { inner: out }));
+                return Err(Some(Crash {
+                    inner: out,
+                    ..Default::default()
+                }));
             }
         }
 
         if !self.guest.is_alive().await {
-            Err(Some(Crash { inner: out }))
+            Err(Some(Crash {
+                inner: out,
+                ..Default::default()
+            }))
         } else {
             Ok(ExecResult::Ok(Default::default()))
         }
@@ -165,66 +481,266 @@ impl ScriptExecutor {
 
 struct LinuxExecutor {
     guest: Guest,
-    port: u16,
-    exec_handle: Option<Child>,
-    conn: Option<TcpStream>,
+    sessions: Vec<Session>,
+    /// Number of sessions in use, less than sessions under memory pressure
+    active_procs: usize,
+    adaptive_procs: Option<AdaptiveProcsConf>,
+    /// Last time available memory of guest was read
+    mem_checked: Instant,
     concurrency: bool,
     memleak_check: bool,
+    sandbox: Option<String>,
+    cov_limit: Option<usize>,
+    pipe_size: Option<usize>,
+    exec_timeout_ms: Option<u64>,
+    cc: Option<String>,
+    trace_cmp: bool,
+    user: Option<String>,
+    pre_exec: Option<String>,
+    post_exec: Option<String>,
+    capture_post_exec: bool,
     executor_bin_path: PathBuf,
     target_path: PathBuf,
     host_ip: String,
+    /// Address of host that executor in guest connects to
+    guest_host_ip: String,
+    kcov_probe_retry: u8,
+    kcov_probed: bool,
+}
+
+/// Connection with one executor process in guest.
+/// Each process collects coverage with its own kcov handle.
+struct Session {
+    port: u16,
+    exec_handle: Option<Child>,
+    conn: Option<TcpStream>,
+    /// Pid of executor in guest, told in handshake
+    pid: Option<u32>,
+}
+
+/// Reply of executor process for one prog.
+enum Reply {
+    Result(ExecResult),
+    /// Connection lost, executor process exited or guest crashed
+    Lost,
+    Blocked(&'static str),
+}
+
+async fn talk(conn: &mut TcpStream, p: &Prog) -> Reply {
+    if let Err(e) = timeout(Duration::new(15, 0), async_send(p, conn)).await {
+        info!("Prog send blocked: {}, restarting...", e);
+        return Reply::Blocked("Prog send blocked");
+    }
+    match timeout(EXEC_RESULT_TIMEOUT, async_recv_result(conn)).await {
+        Err(e) => {
+            info!("Prog recv blocked: {}, restarting...", e);
+            Reply::Blocked("Prog send blocked")
+        }
+        Ok(Ok(result)) => Reply::Result(result),
+        Ok(Err(_)) => Reply::Lost,
+    }
+}
+
+/// Exchange protocol version with executor, error if they mismatch.
+/// Executor that predates handshake takes it as a malformed prog and exits.
+/// Return pid of executor in guest.
+async fn handshake(conn: &mut TcpStream) -> Result<u32, String> {
+    let ours = Handshake::current();
+    let theirs = timeout(Duration::new(15, 0), async {
+        async_send(&ours, conn).await?;
+        async_recv::<Handshake, _>(conn).await
+    })
+    .await
+    .map_err(|_| String::from("time out"))?
+    .map_err(|e| format!("executor may be too old to handshake: {}", e))?;
+    if theirs != ours {
+        return Err(format!(
+            "protocol version mismatch, fuzzer: {}, executor: {}",
+            ours.version, theirs.version
+        ));
+    }
+    timeout(Duration::new(15, 0), async_recv::<u32, _>(conn))
+        .await
+        .map_err(|_| String::from("time out"))?
+        .map_err(|e| format!("fail to recv pid: {}", e))
 }
 
 impl LinuxExecutor {
     pub fn new(cfg: &Config) -> Self {
         let guest = Guest::new(cfg);
-        let port = free_ipv4_port()
-            .unwrap_or_else(|| exits!(exitcode::TEMPFAIL, "No Free port for executor driver"));
+        let sessions = (0..cfg.executor.procs())
+            .map(|_| Session {
+                port: free_ipv4_port().unwrap_or_else(|| {
+                    exits!(exitcode::TEMPFAIL, "No Free port for executor driver")
+                }),
+                exec_handle: None,
+                conn: None,
+                pid: None,
+            })
+            .collect();
         let host_ip = cfg
             .executor
             .host_ip
             .as_ref()
             .map(String::from)
             .unwrap_or_else(|| String::from(guest::LINUX_QEMU_HOST_IP_ADDR));
+        // qemu user net maps host to a fixed address, real machine and ns reach host by host_ip
+        let guest_host_ip = match cfg.guest.platform.as_str() {
+            "real" | "ns" => host_ip.clone(),
+            _ => String::from(guest::LINUX_QEMU_USER_NET_HOST_IP_ADDR),
+        };
 
         Self {
             guest,
-            port,
-            exec_handle: None,
-            conn: None,
+            active_procs: cfg.executor.procs(),
+            sessions,
+            adaptive_procs: cfg.executor.adaptive_procs.clone(),
+            mem_checked: Instant::now(),
 
             concurrency: cfg.executor.concurrency,
             memleak_check: cfg.executor.memleak_check,
+            sandbox: cfg
+                .executor
+                .sandbox
+                .as_ref()
+                .map(|s| s.join(","))
+                .or_else(|| {
+                    // progs run on host kernel, isolate them as much as executor can
+                    if cfg.guest.platform == "ns" {
+                        Some(executor::SANDBOX_NAMESPACES.join(","))
+                    } else {
+                        None
+                    }
+                }),
+            cov_limit: cfg.executor.cov_limit,
+            pipe_size: cfg.executor.pipe_size,
+            exec_timeout_ms: cfg.executor.exec_timeout_ms,
+            cc: cfg.executor.cc.clone(),
+            trace_cmp: cfg.executor.trace_cmp(),
+            user: cfg.executor.user.clone(),
+            pre_exec: cfg.executor.pre_exec.clone(),
+            post_exec: cfg.executor.post_exec.clone(),
+            capture_post_exec: cfg.executor.capture_post_exec.unwrap_or(false),
             executor_bin_path: cfg.executor.path.clone(),
             target_path: PathBuf::from(&cfg.fots_bin),
             host_ip,
+            guest_host_ip,
+            kcov_probe_retry: cfg.executor.kcov_probe_retry.unwrap_or(3),
+            kcov_probed: false,
         }
     }
 
     pub async fn start(&mut self) {
-        // handle should be set to kill on drop
-        self.exec_handle = None;
-        self.guest.boot().await;
+        let mut retry = 0;
+        loop {
+            // handle should be set to kill on drop
+            for s in self.sessions.iter_mut() {
+                s.exec_handle = None;
+            }
+            self.boot_guest().await;
+            if !self.kcov_probed {
+                self.probe_kcov().await;
+                self.kcov_probed = true;
+            }
 
-        self.start_executer().await
+            match self.start_executer().await {
+                Ok(()) => return,
+                Err(e) if retry != START_RETRY => {
+                    retry += 1;
+                    warn!(
+                        "{}, retrying on fresh guest ({}/{})...",
+                        e, retry, START_RETRY
+                    );
+                }
+                Err(e) => exits!(e.code(), "{}", e),
+            }
+        }
     }
 
-    pub async fn start_executer(&mut self) {
-        use tokio::io::ErrorKind::*;
+    /// Boot guest and start executors once, without retrying or exiting.
+    pub async fn try_start(&mut self) -> Result<(), String> {
+        for s in self.sessions.iter_mut() {
+            s.exec_handle = None;
+        }
+        self.guest.boot().await.map_err(|e| e.to_string())?;
+        match self.guest.probe_kcov().await {
+            Ok(true) => (),
+            Ok(false) => {
+                return Err(format!(
+                    "KCOV is not available in guest: {} not found",
+                    guest::KCOV_PATH
+                ))
+            }
+            Err(e) => return Err(format!("Fail to probe KCOV: {}", e)),
+        }
+        self.start_executer().await.map_err(|e| e.to_string())
+    }
+
+    async fn boot_guest(&mut self) {
+        if let Err(e) = self.guest.boot().await {
+            exits!(e.code(), "{}", e);
+        }
+    }
+
+    /// Check kcov of guest, reboot guest and retry if guest is unreachable.
+    /// Only exit if kcov is truly missing or retry exhausted.
+    async fn probe_kcov(&mut self) {
+        let mut retry = 0;
+        loop {
+            match self.guest.probe_kcov().await {
+                Ok(true) => return,
+                Ok(false) => exits!(
+                    exitcode::CONFIG,
+                    "KCOV is not available in guest: {} not found, check CONFIG_KCOV and debugfs",
+                    guest::KCOV_PATH
+                ),
+                Err(e) => {
+                    if retry == self.kcov_probe_retry {
+                        exits!(
+                            exitcode::UNAVAILABLE,
+                            "Fail to probe KCOV after {} fresh boots: {}",
+                            retry,
+                            e
+                        );
+                    }
+                    retry += 1;
+                    warn!("Fail to probe KCOV: {}, retrying on fresh guest...", e);
+                    self.boot_guest().await;
+                }
+            }
+        }
+    }
+
+    pub async fn start_executer(&mut self) -> Result<(), GuestError> {
+        let all = (0..self.sessions.len()).collect::<Vec<_>>();
+        self.start_sessions(&all).await
+    }
 
-        self.exec_handle = None;
-        let target = self.guest.copy(&self.target_path).await;
+    /// (Re)start executor processes of given sessions.
+    async fn start_sessions(&mut self, sessions: &[usize]) -> Result<(), GuestError> {
+        for &i in sessions {
+            self.sessions[i].exec_handle = None;
+        }
+        let target = self.guest.copy(&self.target_path).await?;
+        for &i in sessions {
+            self.start_session(i, &target).await?;
+        }
+        Ok(())
+    }
+
+    async fn start_session(&mut self, i: usize, target: &Path) -> Result<(), GuestError> {
+        use tokio::io::ErrorKind::*;
 
         let (tx, rx) = oneshot::channel();
         let mut retry = 0;
         let mut listener;
         loop {
-            let host_addr = format!("{}:{}", self.host_ip, self.port);
+            let host_addr = format!("{}:{}", self.host_ip, self.sessions[i].port);
             listener = match TcpListener::bind(&host_addr).await {
                 Ok(l) => l,
                 Err(e) => {
                     if e.kind() == AddrInUse && retry != 5 {
-                        self.port = free_ipv4_port().unwrap();
+                        self.sessions[i].port = free_ipv4_port().unwrap();
                         retry += 1;
                         continue;
                     } else {
@@ -254,11 +770,7 @@ impl LinuxExecutor {
             .arg(Arg::new_opt("-t", OptVal::normal(target.to_str().unwrap())))
             .arg(Arg::new_opt(
                 "-a",
-                OptVal::normal(&format!(
-                    "{}:{}",
-                    guest::LINUX_QEMU_USER_NET_HOST_IP_ADDR,
-                    self.port
-                )),
+                OptVal::normal(&format!("{}:{}", self.guest_host_ip, self.sessions[i].port)),
             ));
         if self.memleak_check {
             executor.arg(Arg::new_flag("-m"));
@@ -266,96 +778,312 @@ impl LinuxExecutor {
         if self.concurrency {
             executor.arg(Arg::new_flag("-c"));
         }
+        if let Some(sandbox) = self.sandbox.as_ref() {
+            executor.arg(Arg::new_opt("-s", OptVal::normal(sandbox)));
+        }
+        if let Some(limit) = self.cov_limit {
+            executor.arg(Arg::new_opt("-l", OptVal::normal(&limit.to_string())));
+        }
+        if let Some(size) = self.pipe_size {
+            executor.arg(Arg::new_opt("-P", OptVal::normal(&size.to_string())));
+        }
+        if let Some(ms) = self.exec_timeout_ms {
+            executor.arg(Arg::new_opt("-T", OptVal::normal(&ms.to_string())));
+        }
+        if let Some(cc) = self.cc.as_ref() {
+            executor.arg(Arg::new_opt("--cc", OptVal::normal(cc)));
+        }
+        if self.trace_cmp {
+            executor.arg(Arg::new_flag("--trace-cmp"));
+        }
+        if let Some(user) = self.user.as_ref() {
+            executor.arg(Arg::new_opt("-u", OptVal::normal(user)));
+        }
 
-        self.exec_handle = Some(self.guest.run_cmd(&executor).await);
-        self.conn = match timeout(Duration::new(32, 0), rx).await {
+        let session = &mut self.sessions[i];
+        session.exec_handle = Some(self.guest.run_cmd(&executor).await?);
+        session.conn = match timeout(Duration::new(32, 0), rx).await {
             Err(_) => {
-                self.exec_handle = None;
+                session.exec_handle = None;
                 eprintln!("Time out: wait executor connection {}", host_addr);
                 exit(1)
             }
             Ok(conn) => Some(conn.unwrap()),
         };
+        match handshake(session.conn.as_mut().unwrap()).await {
+            Ok(pid) => session.pid = Some(pid),
+            Err(e) => exits!(
+                exitcode::PROTOCOL,
+                "Handshake with executor {} failed: {}, is it stale? Redeploy it to guest",
+                self.executor_bin_path.display(),
+                e
+            ),
+        }
+        Ok(())
     }
 
     pub async fn exec(&mut self, p: &Prog) -> Result<ExecResult, Option<Crash>> {
-        // send must be success
-        assert!(self.conn.is_some());
-        if let Err(e) = timeout(
-            Duration::new(15, 0),
-            async_send(p, self.conn.as_mut().unwrap()),
-        )
-        .await
-        {
-            info!("Prog send blocked: {}, restarting...", e);
-            self.start().await;
-            return Ok(ExecResult::Failed(Reason("Prog send blocked".into())));
-        }
-        // async_send(p, self.conn.as_mut().unwrap()).await.unwrap();
-        let ret = {
-            match timeout(
-                Duration::new(15, 0),
-                async_recv_result(self.conn.as_mut().unwrap()),
-            )
+        self.exec_batch(std::slice::from_ref(p))
             .await
-            {
-                Err(e) => {
-                    info!("Prog recv blocked: {}, restarting...", e);
-                    self.start().await;
-                    return Ok(ExecResult::Failed(Reason("Prog send blocked".into())));
+            .pop()
+            .unwrap()
+    }
+
+    /// Execute progs concurrently, each on its own executor process.
+    /// Crash of guest is attributed to the first prog whose connection is lost,
+    /// other lost ones are treated as internal error.
+    pub async fn exec_batch(&mut self, ps: &[Prog]) -> Vec<Result<ExecResult, Option<Crash>>> {
+        // send must be success
+        assert!(ps.len() <= self.sessions.len());
+        self.adapt_procs().await;
+        if let Some(cmd) = self.pre_exec.as_ref() {
+            self.run_hook("Pre", cmd).await;
+        }
+        let mut talks = Vec::with_capacity(ps.len());
+        for (s, p) in self.sessions.iter_mut().zip(ps) {
+            let mut conn = s.conn.take().unwrap();
+            let p = p.clone();
+            talks.push(tokio::spawn(async move {
+                let reply = talk(&mut conn, &p).await;
+                (conn, reply)
+            }));
+        }
+        let mut replies = Vec::with_capacity(ps.len());
+        for (s, t) in self.sessions.iter_mut().zip(talks) {
+            let (conn, reply) = t.await.unwrap();
+            s.conn = Some(conn);
+            replies.push(reply);
+        }
+
+        let lost = replies
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| matches!(r, Reply::Lost))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if let (Some(cmd), true) = (self.post_exec.as_ref(), lost.is_empty()) {
+            if let Some(out) = self.run_hook("Post", cmd).await {
+                if self.capture_post_exec {
+                    info!("Post exec output:\n{}{}", out.stdout, out.stderr);
                 }
-                Ok(ret) => ret,
             }
-        };
-        match ret {
-            Ok(result) => {
-                self.guest.clear().await;
-                if let ExecResult::Failed(ref reason) = result {
+        }
+        let crashed = !lost.is_empty() && self.guest.is_crashed().await;
+        let mut crash = None;
+        let mut outputs = Vec::new();
+        if crashed {
+            crash = Some(self.guest.try_collect_crash().await);
+        } else {
+            for &i in lost.iter() {
+                outputs.push(self.reap(i).await);
+            }
+        }
+        let blocked = replies.iter().any(|r| matches!(r, Reply::Blocked(_)));
+        if blocked && crash.is_none() && self.guest.nmi_on_hang() && !self.guest.is_alive().await {
+            if let Some(hang) = self.guest.dump_hang().await {
+                crash = Some(Some(hang));
+            }
+        }
+        let log = self.guest.clear().await;
+        let comm = self
+            .executor_bin_path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        let kills = oom_kills(&log, &comm);
+        let pids = self
+            .sessions
+            .iter()
+            .filter_map(|s| s.pid)
+            .collect::<Vec<_>>();
+        let mut oom_killed = false;
+
+        let mut restart = false;
+        let mut results = Vec::with_capacity(replies.len());
+        for (s, reply) in self.sessions.iter().zip(replies) {
+            let oom = oom_of(&kills, s.pid, &pids, &reply);
+            oom_killed |= oom.is_some();
+            let result = match reply {
+                Reply::Result(ExecResult::Failed(reason)) => {
                     let rea = reason.to_string();
                     if rea.contains("CRASH-MEMLEAK") {
-                        return Err(Some(Crash { inner: rea }));
+                        Err(Some(Crash {
+                            inner: rea,
+                            ..Default::default()
+                        }))
+                    } else if let Some(oom) = oom {
+                        Ok(ExecResult::Oom(Reason(oom)))
+                    } else {
+                        Ok(ExecResult::Failed(reason))
                     }
                 }
-                return Ok(result);
-            }
-            Err(_) => {
-                let mut crashed: bool;
-                let mut retry: u8 = 0;
-                loop {
-                    crashed = !self.guest.is_alive().await;
-                    if crashed || retry == 10 {
-                        break;
-                    } else {
-                        retry += 1;
-                        delay_for(Duration::from_millis(500)).await;
+                Reply::Result(result) => Ok(result),
+                Reply::Blocked(reason) => match crash.take() {
+                    Some(crash) => Err(crash),
+                    None => {
+                        restart = true;
+                        Ok(ExecResult::Failed(Reason(reason.into())))
                     }
+                },
+                Reply::Lost => match (crash.take(), oom) {
+                    (Some(crash), _) => Err(crash),
+                    (None, Some(oom)) if !crashed => Ok(ExecResult::Oom(Reason(oom))),
+                    // Caused by internal err
+                    _ => Ok(ExecResult::Ok(Vec::new())),
+                },
+            };
+            results.push(result);
+        }
+
+        if results.iter().any(|r| r.is_err()) {
+            // restarted by crash handling
+        } else if restart {
+            self.start().await;
+        } else if !lost.is_empty() {
+            if oom_killed {
+                warn!("Executor: killed by guest OOM killer, restarting...");
+                self.reduce_procs();
+            } else {
+                for (out, err) in outputs {
+                    warn!("Executor: Connection lost. STDOUT:{}. STDERR: {}", out, err);
                 }
+            }
+            if let Err(e) = self.start_sessions(&lost).await {
+                warn!("{}, restarting guest...", e);
+                self.start().await;
+            }
+        }
+        results
+    }
 
-                if crashed {
-                    return Err(self.guest.try_collect_crash().await);
-                } else {
-                    let mut handle = self.exec_handle.take().unwrap();
-                    let mut stdout = handle.stdout.take().unwrap();
-                    let mut stderr = handle.stderr.take().unwrap();
-                    handle.await.unwrap_or_else(|e| {
-                        exits!(exitcode::OSERR, "Fail to wait executor handle:{}", e)
-                    });
-
-                    let mut err = Vec::new();
-                    stderr.read_to_end(&mut err).await.unwrap();
-                    let mut out = Vec::new();
-                    stdout.read_to_end(&mut out).await.unwrap();
+    /// Read available memory of guest every interval, use fewer procs if it is low,
+    /// more if it recovers.
+    async fn adapt_procs(&mut self) {
+        let conf = match self.adaptive_procs.as_ref() {
+            Some(conf) if self.mem_checked.elapsed() >= conf.interval() => conf,
+            _ => return,
+        };
+        self.mem_checked = Instant::now();
+        let mut cat = App::new("cat");
+        cat.arg(Arg::new_flag("/proc/meminfo"));
+        let avail = match self.guest.run_cmd_output(cat, Duration::new(10, 0)).await {
+            Ok(out) if out.success() => mem_available(&out.stdout),
+            _ => None,
+        };
+        match avail {
+            Some(avail) if avail < conf.low_mem() => self.reduce_procs(),
+            Some(avail)
+                if avail >= conf.low_mem() * 2 && self.active_procs < self.sessions.len() =>
+            {
+                self.active_procs += 1;
+                info!(
+                    "Guest memory recovered ({}MB available), using {} procs",
+                    avail, self.active_procs
+                );
+            }
+            Some(_) => (),
+            None => warn!("Fail to read available memory of guest"),
+        }
+    }
 
+    fn reduce_procs(&mut self) {
+        let min_procs = match self.adaptive_procs.as_ref() {
+            Some(conf) => conf.min_procs(),
+            None => return,
+        };
+        if self.active_procs > min_procs {
+            self.active_procs -= 1;
+            warn!("Guest memory is tight, using {} procs", self.active_procs);
+        }
+    }
+
+    /// Run pre or post exec command in guest, outside of executor so it's not covered.
+    async fn run_hook(&self, hook: &str, cmd: &str) -> Option<CmdOutput> {
+        match self
+            .guest
+            .run_cmd_output(guest::shell(cmd), Duration::new(10, 0))
+            .await
+        {
+            Ok(out) => {
+                if !out.success() {
                     warn!(
-                        "Executor: Connection lost. STDOUT:{}. STDERR: {}",
-                        String::from_utf8(out).unwrap(),
-                        String::from_utf8(err).unwrap()
+                        "{} exec command exited with {:?}: {}",
+                        hook, out.code, out.stderr
                     );
-                    self.start_executer().await;
                 }
+                Some(out)
+            }
+            Err(e) => {
+                warn!("{} exec command failed: {}", hook, e);
+                None
             }
         }
-        // Caused by internal err
-        Ok(ExecResult::Ok(Vec::new()))
+    }
+
+    /// Wait exited executor process of session, return its stdout and stderr.
+    async fn reap(&mut self, i: usize) -> (String, String) {
+        let mut handle = self.sessions[i].exec_handle.take().unwrap();
+        let mut stdout = handle.stdout.take().unwrap();
+        let mut stderr = handle.stderr.take().unwrap();
+        handle
+            .await
+            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to wait executor handle:{}", e));
+
+        let mut err = Vec::new();
+        stderr.read_to_end(&mut err).await.unwrap();
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).await.unwrap();
+        (
+            String::from_utf8_lossy(&out).into_owned(),
+            String::from_utf8_lossy(&err).into_owned(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meminfo() {
+        let meminfo = "MemTotal:        2035848 kB\nMemFree:          101592 kB\nMemAvailable:     524288 kB\n";
+        assert_eq!(mem_available(meminfo), Some(512));
+        assert_eq!(mem_available("MemTotal: 2035848 kB\n"), None);
+    }
+
+    #[test]
+    fn oom_of_executor() {
+        let log = "[   10.1] Out of memory: Killed process 231 (sshd) total-vm:8000kB\n\
+                   [   12.3] Out of memory: Killed process 1234 (syz-executor) total-vm:9000kB\n\
+                   [   14.5] Out of memory: Killed process 1240 (healer-executor) total-vm:9000kB\n";
+        assert!(oom_kills(log, "executor").is_empty());
+        let kills = oom_kills(log, "healer-executor-amd64");
+        assert_eq!(kills.len(), 1);
+        assert_eq!(kills[0].pid, 1240);
+        assert!(kills[0].msg.contains("1240"));
+    }
+
+    #[test]
+    fn oom_of_session() {
+        let log =
+            "[   14.5] Out of memory: Killed process 1240 (healer-executor) total-vm:9000kB\n";
+        let kills = oom_kills(log, "healer-executor");
+        let sigkill = || {
+            let reason = format!("{} {}", KILLED_BY_SIGNAL, Signal::SIGKILL);
+            Reply::Result(ExecResult::Failed(Reason(reason)))
+        };
+        let timeout = Reply::Result(ExecResult::Failed(Reason(String::from(TIME_OUT))));
+
+        // executor 1240 itself is killed, only its lost session is hit
+        let pids = [1240, 1250];
+        assert!(oom_of(&kills, Some(1240), &pids, &Reply::Lost).is_some());
+        assert!(oom_of(&kills, Some(1250), &pids, &Reply::Lost).is_none());
+        assert!(oom_of(&kills, Some(1250), &pids, &sigkill()).is_none());
+
+        // a prog child is killed, innocent progs failing otherwise are not hit
+        let pids = [1230, 1250];
+        assert!(oom_of(&kills, Some(1230), &pids, &sigkill()).is_some());
+        assert!(oom_of(&kills, Some(1250), &pids, &timeout).is_none());
+        assert!(oom_of(&kills, Some(1250), &pids, &Reply::Lost).is_none());
     }
 }
@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 use std::iter::Extend;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
 #[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq)]
@@ -11,6 +12,13 @@ impl From<usize> for Block {
     }
 }
 
+impl Block {
+    /// Raw pc of block
+    pub fn pc(&self) -> usize {
+        self.0
+    }
+}
+
 #[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq)]
 pub struct Branch(usize);
 
@@ -19,19 +27,28 @@ impl From<(Block, Block)> for Branch {
         let mut a = b1.0 as u32;
         // hash algorithm from syzkaller
         a = (a ^ 61) ^ (a >> 16);
-        a = a + (a << 3);
+        a = a.wrapping_add(a << 3);
         a = a ^ (a >> 4);
-        a *= 0x27d4_eb2d;
+        a = a.wrapping_mul(0x27d4_eb2d);
         a = a ^ (a >> 15);
 
         Self(a as usize ^ b2.0)
     }
 }
 
+impl Branch {
+    /// Raw hash of branch
+    pub fn raw(&self) -> usize {
+        self.0
+    }
+}
+
 #[derive(Default)]
 pub struct FeedBack {
     branches: Mutex<HashSet<Branch>>,
     blocks: Mutex<HashSet<Block>>,
+    /// When new coverage was merged last time
+    last_merge: Mutex<Option<Instant>>,
 }
 
 impl FeedBack {
@@ -70,6 +87,12 @@ impl FeedBack {
             let mut inner = self.blocks.lock().await;
             inner.extend(blocks);
         }
+        *self.last_merge.lock().await = Some(Instant::now());
+    }
+
+    /// Time since new coverage was merged last time, None if nothing is merged yet.
+    pub async fn since_last_merge(&self) -> Option<Duration> {
+        self.last_merge.lock().await.map(|t| t.elapsed())
     }
 
     pub async fn is_empty(&self) -> bool {
@@ -86,6 +109,14 @@ impl FeedBack {
         block_empty || branch_empty
     }
 
+    /// Covered blocks in ascending order.
+    pub async fn blocks(&self) -> Vec<Block> {
+        let inner = self.blocks.lock().await;
+        let mut blocks = inner.iter().cloned().collect::<Vec<_>>();
+        blocks.sort();
+        blocks
+    }
+
     pub async fn len(&self) -> (usize, usize) {
         tokio::join!(
             async {
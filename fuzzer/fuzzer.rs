@@ -2,9 +2,16 @@ use crate::corpus::Corpus;
 use crate::exec::Executor;
 use crate::feedback::{Block, Branch, FeedBack};
 use crate::guest::Crash;
-use crate::report::TestCaseRecord;
-use crate::stats::StatSource;
+use crate::interesting::{InterestingLog, INTERESTING_LOG_PATH};
+use crate::journal::{Journal, JOURNAL_PATH};
+use crate::prog_io;
+use crate::report::{CrashedCase, ReproRate, TestCaseRecord};
+use crate::reporter::reporters;
+use crate::stats::{CallStats, StatSource};
+use crate::store::crash_store;
+use crate::symbolize::Symbolizer;
 use crate::utils::queue::CQueue;
+use crate::utils::wait_stop;
 use crate::Config;
 use core::analyze::prog_analyze;
 use core::analyze::static_analyze;
@@ -15,16 +22,29 @@ use core::minimize::remove;
 use core::mutate::mutate;
 use core::prog::Prog;
 use core::target::Target;
-use executor::{ExecResult, Reason};
-use fots::types::GroupId;
+use executor::{ExecResult, FailureKind, Reason};
+use fots::types::{FnId, GroupId};
 use itertools::Itertools;
 use regex::Regex;
 use std::collections::{HashMap, HashSet};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::fs::write;
 use tokio::sync::broadcast;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{delay_for, Duration};
+
+/// Path of data persisted on exit by fuzzer of `guest`, `None` if corpus is shared.
+pub(crate) fn persisted_path(name: &str, guest: Option<usize>) -> String {
+    match guest {
+        Some(id) => format!("./{}-{}", name, id),
+        None => format!("./{}", name),
+    }
+}
 
 #[derive(Clone)]
 pub struct Fuzzer {
@@ -33,29 +53,222 @@ pub struct Fuzzer {
     pub conf: core::gen::Config,
     pub corpus: Arc<Corpus>,
     pub feedback: Arc<FeedBack>,
+    /// Initial corpus waiting for execution, closed once it's fed entirely
     pub candidates: Arc<CQueue<Prog>>,
     pub record: Arc<TestCaseRecord>,
     pub exec_cnt: Arc<AtomicUsize>,
+    pub oom_cnt: Arc<AtomicUsize>,
+    pub truncated_cnt: Arc<AtomicUsize>,
+    pub call_stats: Arc<CallStats>,
     pub crash_digests: Arc<Mutex<HashSet<md5::Digest>>>,
+    /// Permits of handling crash, shared by all guests
+    pub crash_permits: Arc<Semaphore>,
+    /// Reproducers of saved crashes being minimized
+    pub crash_backlog: Arc<CQueue<CrashMinimization>>,
+    pub minimize_crashes: bool,
+    /// Compute coverage fingerprint of reproduced crashes
+    pub crash_fingerprint: bool,
+    /// Record failures of progs themselves as failed cases too, not only executor errors
+    pub record_prog_failures: bool,
 
     pub suppressions: Vec<Regex>,
     pub ignores: Vec<Regex>,
+    pub keepalive: Option<Duration>,
+    pub journal: Option<Arc<Journal>>,
+    pub interesting_log: Option<Arc<InterestingLog>>,
+    /// Config for booting verify kernel, if configured
+    pub verify_cfg: Option<Arc<Config>>,
+    /// Id of guest owning this fuzzer if corpus is not shared
+    pub guest: Option<usize>,
+    pub repro: ReproConf,
+    /// Fuzzing of all guests is paused through control api
+    pub paused: Arc<AtomicBool>,
+}
+
+/// Interval of checking whether paused fuzzing is resumed
+const PAUSE_POLL: Duration = Duration::from_secs(1);
+
+/// Selection weight of priority syscalls in generation, others are 1.0
+const PRIORITY_WEIGHT: f64 = 8.0;
+
+/// Fuzzing is idle if all candidates are executed and no new coverage is found for this long
+const IDLE_TIME: Duration = Duration::from_secs(300);
+
+/// Reproducer of saved crash being minimized. Removal of one call is tried at a time, so that
+/// minimization is interleaved with fuzzing and crash permit is only held while executing.
+pub struct CrashMinimization {
+    p: Prog,
+    case: CrashedCase,
+    signature: String,
+    /// Index of call to try removing next
+    next: usize,
+    /// Calls of reproducer before minimization
+    len: usize,
+}
+
+impl CrashMinimization {
+    pub fn new(p: Prog, case: CrashedCase) -> Self {
+        Self {
+            signature: case.crash.signature(),
+            len: p.len(),
+            next: 0,
+            p,
+            case,
+        }
+    }
+}
+
+/// Syscalls matching given names, either declared name (e.g. ioctl$DRM_IOCTL_VERSION)
+/// or called name (e.g. ioctl) matching all its variants.
+fn priority_of(t: &Target, names: &[String]) -> HashSet<FnId> {
+    let mut priority = HashSet::new();
+    for name in names {
+        let fids = t
+            .iter_group()
+            .flat_map(|g| g.fns.iter())
+            .filter(|f| &f.dec_name == name || &f.call_name == name)
+            .map(|f| f.id)
+            .collect::<Vec<_>>();
+        if fids.is_empty() {
+            exits!(exitcode::CONFIG, "Unknown priority syscall: {}", name);
+        }
+        priority.extend(fids);
+    }
+    priority
+}
+
+/// Config of fuzz loop
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FuzzConf {
+    /// Max progs waiting in candidate queue, unbounded by default
+    pub max_candidates: Option<usize>,
+}
+
+impl FuzzConf {
+    pub fn check(&self) {
+        if self.max_candidates == Some(0) {
+            eprintln!("Config Error: fuzz max_candidates must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+    }
+}
+
+/// Queue of initial corpus, progs are decoded from corpus files `sources` one at a time by a
+/// task that waits for free slots of bounded queue. Queue is closed once all files are fed.
+fn candidate_queue(sources: Vec<PathBuf>, cap: Option<usize>) -> Arc<CQueue<Prog>> {
+    let queue = Arc::new(CQueue::with_capacity(cap));
+    let feeder = queue.clone();
+    tokio::spawn(async move {
+        for path in sources {
+            let progs = match File::open(&path) {
+                Ok(f) => prog_io::stream(BufReader::new(f)).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            let progs = match progs {
+                Ok(progs) => progs,
+                Err(e) => {
+                    warn!("Fail to load candidates {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for p in progs {
+                match p {
+                    Ok(p) => feeder.push(p).await,
+                    Err(e) => warn!("Fail to load candidates {}: {}", path.display(), e),
+                }
+            }
+        }
+        feeder.close();
+    });
+    queue
+}
+
+/// Config of measuring reproduction rate of crash
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReproConf {
+    /// Runs of reproducer on fresh guest, 1 by default
+    pub runs: Option<usize>,
+    /// Min rate of crashed runs for being a reproducer, otherwise it's flaky, 1.0 by default
+    pub threshold: Option<f64>,
+}
+
+impl ReproConf {
+    pub fn check(&self) {
+        if self.runs == Some(0) {
+            eprintln!("Config Error: repro runs must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+        if !(self.threshold() > 0.0 && self.threshold() <= 1.0) {
+            eprintln!(
+                "Config Error: repro threshold {} must be in (0, 1]",
+                self.threshold()
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+
+    pub fn runs(&self) -> usize {
+        self.runs.unwrap_or(1)
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold.unwrap_or(1.0)
+    }
 }
 
 impl Fuzzer {
-    pub fn new(target: Target, candidates: Vec<Prog>, cfg: &Config) -> Self {
+    /// Fuzzer whose candidates are progs of corpus files `candidates`.
+    pub fn new(target: Target, candidates: Vec<PathBuf>, cfg: &Config) -> Self {
         let target = Arc::new(target);
-        let record = Arc::new(TestCaseRecord::new(target.clone()));
+        let record = Arc::new(TestCaseRecord::new(
+            target.clone(),
+            cfg.labels.clone().unwrap_or_default(),
+            crash_store(&cfg.crash_store),
+            reporters(&cfg.sampler.as_ref().and_then(|s| s.reporters.clone())),
+            cfg.qemu.as_ref().and_then(|q| {
+                if q.symbolize() {
+                    Some(Symbolizer::new(q.vmlinux(), q.nm()))
+                } else {
+                    warn!(
+                        "{} not found, crash reports are not symbolized",
+                        q.vmlinux().display()
+                    );
+                    None
+                }
+            }),
+        ));
         let rt = static_analyze(&target);
+        let priority = priority_of(&target, cfg.priority_syscalls.as_deref().unwrap_or(&[]));
+        let call_stats = Arc::new(CallStats::new(target.clone(), priority.clone()));
+        let conf = core::gen::Config {
+            weights: priority
+                .into_iter()
+                .map(|fid| (fid, PRIORITY_WEIGHT))
+                .collect(),
+            ..Default::default()
+        };
         Self {
             target,
             record,
             crash_digests: Arc::new(Mutex::new(HashSet::new())),
+            crash_permits: Arc::new(Semaphore::new(cfg.crash_handling_limit())),
+            crash_backlog: Arc::new(CQueue::default()),
+            minimize_crashes: cfg.minimize_crashes.unwrap_or(false),
+            crash_fingerprint: cfg.crash_fingerprint.unwrap_or(false),
+            record_prog_failures: cfg.failed_policy() == "all",
             exec_cnt: Arc::new(AtomicUsize::new(0)),
+            oom_cnt: Arc::new(AtomicUsize::new(0)),
+            truncated_cnt: Arc::new(AtomicUsize::new(0)),
+            call_stats,
             rt: Arc::new(Mutex::new(rt)),
-            conf: Default::default(),
-            candidates: Arc::new(CQueue::from(candidates)),
-            corpus: Arc::new(Corpus::default()),
+            conf,
+            candidates: candidate_queue(
+                candidates,
+                cfg.fuzz.as_ref().and_then(|f| f.max_candidates),
+            ),
+            corpus: Arc::new(Corpus::with_max_size(
+                cfg.corpus.as_ref().and_then(|c| c.max_size),
+            )),
             feedback: Arc::new(FeedBack::default()),
 
             suppressions: cfg
@@ -72,47 +285,150 @@ impl Fuzzer {
                 .iter()
                 .map(|i| Regex::new(i).unwrap())
                 .collect(),
+            keepalive: cfg.executor.keepalive_interval.map(Duration::from_secs),
+            verify_cfg: cfg
+                .qemu
+                .as_ref()
+                .and_then(|q| q.verify_kernel.as_ref())
+                .map(|k| {
+                    let mut cfg = cfg.clone();
+                    cfg.qemu.as_mut().unwrap().kernel = k.clone();
+                    Arc::new(cfg)
+                }),
+            journal: if cfg.journal.unwrap_or(false) {
+                Some(Arc::new(Journal::create(JOURNAL_PATH)))
+            } else {
+                None
+            },
+            interesting_log: if cfg.interesting_log.unwrap_or(false) {
+                Some(Arc::new(InterestingLog::create(INTERESTING_LOG_PATH)))
+            } else {
+                None
+            },
+            guest: None,
+            repro: cfg.repro.clone().unwrap_or_default(),
+            paused: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Fuzzer of guest `id` with independent corpus, feedback and stats.
+    /// Crash record is still shared.
+    pub fn isolate(&self, id: usize, candidates: Vec<PathBuf>) -> Self {
+        Self {
+            rt: Arc::new(Mutex::new(static_analyze(&self.target))),
+            corpus: Arc::new(Corpus::with_max_size(self.corpus.max_size())),
+            feedback: Arc::new(FeedBack::default()),
+            candidates: candidate_queue(candidates, self.candidates.capacity()),
+            exec_cnt: Arc::new(AtomicUsize::new(0)),
+            oom_cnt: Arc::new(AtomicUsize::new(0)),
+            truncated_cnt: Arc::new(AtomicUsize::new(0)),
+            call_stats: Arc::new(CallStats::new(
+                self.target.clone(),
+                self.conf.weights.keys().copied().collect(),
+            )),
+            guest: Some(id),
+            ..self.clone()
         }
     }
 
     pub fn stats(&self) -> StatSource {
         StatSource {
             exec: self.exec_cnt.clone(),
+            oom: self.oom_cnt.clone(),
+            truncated: self.truncated_cnt.clone(),
+            calls: self.call_stats.clone(),
             corpus: self.corpus.clone(),
             feedback: self.feedback.clone(),
             candidates: self.candidates.clone(),
             record: self.record.clone(),
+            guest: self.guest,
         }
     }
-    pub async fn fuzz(self, executor: Executor, mut shutdown: broadcast::Receiver<()>) {
+    pub async fn fuzz(self, mut executor: Executor, mut shutdown: broadcast::Receiver<()>) {
         tokio::select! {
-            _ = shutdown.recv() => (),
-            _ = self.do_fuzz(executor) => ()
+            _ = wait_stop(&mut shutdown) => (),
+            _ = self.do_fuzz(&mut executor) => ()
         }
+        executor.shutdown().await;
     }
 
-    async fn do_fuzz(&self, mut executor: Executor) {
+    async fn do_fuzz(&self, executor: &mut Executor) {
         let mut gen_cnt = 0;
         loop {
-            let p = self.get_prog(&mut gen_cnt).await;
-            match executor.exec(&p, &self.target).await {
-                Ok(exec_result) => match exec_result {
-                    ExecResult::Ok(raw_branches) => {
-                        self.feedback_analyze(p, raw_branches, &mut executor).await
-                    }
-                    ExecResult::Failed(reason) => self.failed_analyze(p, reason).await,
-                },
-                Err(crash) => {
-                    self.crash_analyze(p, crash.unwrap_or_default(), &mut executor)
-                        .await
+            self.wait_resumed(executor).await;
+            // minimize saved crashes only when fuzzing is idle, a step between fuzzing batches
+            if self.is_idle().await {
+                if let Some(m) = self.crash_backlog.pop().await {
+                    self.minimize_crash(m, executor).await;
                 }
-            };
-            self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+            }
+            let mut ps = Vec::with_capacity(executor.procs());
+            for _ in 0..executor.procs() {
+                ps.push(self.next_prog(&mut gen_cnt, executor).await);
+            }
+            let rets = self.exec_batch(executor, &ps).await;
+            for (p, ret) in ps.into_iter().zip(rets) {
+                match ret {
+                    Ok(exec_result) => match exec_result {
+                        ExecResult::Ok(raw_branches) => {
+                            self.feedback_analyze(p, raw_branches, executor).await
+                        }
+                        ExecResult::Truncated(raw_branches) => {
+                            self.truncated_cnt.fetch_add(1, Ordering::SeqCst);
+                            self.feedback_analyze(p, raw_branches, executor).await
+                        }
+                        ExecResult::Failed(reason) => self.failed_analyze(p, reason).await,
+                        ExecResult::Oom(reason) => self.oom_analyze(reason),
+                        ExecResult::Partial { covs, err } => {
+                            self.feedback_analyze(p.clone(), covs, executor).await;
+                            self.failed_analyze(p, err).await
+                        }
+                        ExecResult::OkCmp { covs, cmps } => {
+                            // not used for mutation yet
+                            debug!("{} comparisons traced", cmps.len());
+                            self.feedback_analyze(p, covs, executor).await
+                        }
+                    },
+                    Err(crash) => {
+                        self.crash_analyze(p, crash.unwrap_or_default(), executor)
+                            .await
+                    }
+                };
+                self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+            }
         }
     }
 
+    /// Execute progs concurrently, record them to journal if enabled.
+    async fn exec_batch(
+        &self,
+        executor: &mut Executor,
+        ps: &[Prog],
+    ) -> Vec<Result<ExecResult, Option<Crash>>> {
+        if let Some(journal) = self.journal.as_ref() {
+            for p in ps {
+                journal.append(p).await;
+            }
+        }
+        let rets = executor.exec_batch(ps, &self.target).await;
+        for (p, ret) in ps.iter().zip(rets.iter()) {
+            self.call_stats.record(p, ret).await;
+        }
+        rets
+    }
+
+    /// Execute prog, record it to journal if enabled.
+    async fn exec(&self, executor: &mut Executor, p: &Prog) -> Result<ExecResult, Option<Crash>> {
+        if let Some(journal) = self.journal.as_ref() {
+            journal.append(p).await;
+        }
+        let ret = executor.exec(p, &self.target).await;
+        self.call_stats.record(p, &ret).await;
+        ret
+    }
+
     pub async fn persist(self) {
-        let corpus_path = "./corpus";
+        let corpus_path = self.path_of("corpus");
         let corpus = self
             .corpus
             .dump()
@@ -126,14 +442,59 @@ impl Fuzzer {
                 e
             )
         });
+        self.persist_coverage().await;
         self.record.psersist().await;
     }
 
+    /// Dump covered pcs, one hex pc per line.
+    async fn persist_coverage(&self) {
+        let cover_path = self.path_of("coverage");
+        let cover = self
+            .feedback
+            .blocks()
+            .await
+            .iter()
+            .map(|b| format!("{:#x}\n", b.pc()))
+            .collect::<String>();
+        write(&cover_path, cover).await.unwrap_or_else(|e| {
+            exits!(
+                exitcode::IOERR,
+                "Fail to persist coverage to {} : {}",
+                cover_path,
+                e
+            )
+        });
+    }
+
+    /// Path of persisted data, suffixed with guest id if corpus is not shared.
+    fn path_of(&self, name: &str) -> String {
+        persisted_path(name, self.guest)
+    }
+
+    /// Record failed case according to failed policy. Progs that time out or exit before
+    /// finishing first call are normal results of fuzzing, only executor errors are failures
+    /// by default.
     async fn failed_analyze(&self, p: Prog, reason: Reason) {
-        self.record.insert_failed(p, reason).await
+        if self.record_prog_failures || reason.kind() == FailureKind::Executor {
+            self.record.insert_failed(p, reason).await
+        }
+    }
+
+    fn oom_analyze(&self, reason: Reason) {
+        self.oom_cnt.fetch_add(1, Ordering::SeqCst);
+        warn!(
+            "Executor killed by guest OOM killer, consider reducing concurrency or increasing guest memory:\n{}",
+            reason
+        );
     }
 
     async fn crash_analyze(&self, p: Prog, crash: Crash, executor: &mut Executor) {
+        // Crashes of other guests wait here, so a crash storm doesn't thrash io and memory.
+        let _permit = self.crash_permits.acquire().await;
+        self.handle_crash(p, crash, executor).await
+    }
+
+    async fn handle_crash(&self, p: Prog, crash: Crash, executor: &mut Executor) {
         if self.should_ignore(&crash.inner) {
             warn!("Crashed, match ignores, restarting ...");
             executor.start().await;
@@ -141,7 +502,9 @@ impl Fuzzer {
         }
 
         if self.should_suppress(&crash.inner).await {
-            self.record.insert_crash(p, crash, false).await;
+            self.record
+                .insert_crash(p, crash, false, None, None, None)
+                .await;
             warn!("Crashed, match suppressions, restarting ...");
             executor.start().await;
             return;
@@ -153,25 +516,184 @@ impl Fuzzer {
         warn!("Restarting to repro ...");
         executor.start().await;
 
+        let mut rate = ReproRate {
+            runs: self.repro.runs(),
+            crashes: 0,
+        };
+        let mut repro_crash = None;
+        for i in 0..rate.runs {
+            self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+            match self.exec(executor, &p).await {
+                Ok(exec_result) => {
+                    match exec_result {
+                        ExecResult::Ok(_)
+                        | ExecResult::Truncated(_)
+                        | ExecResult::OkCmp { .. }
+                        | ExecResult::Partial { .. } => {
+                            warn!("Repo failed, executed successfully")
+                        }
+                        ExecResult::Failed(reason) => {
+                            warn!("Repo failed, executed failed: {}", reason)
+                        }
+                        ExecResult::Oom(_) => warn!("Repo failed, executor killed by OOM killer"),
+                    }
+                    // each run starts on fresh guest, state left by this one must not leak
+                    if i + 1 != rate.runs {
+                        executor.start().await;
+                    }
+                }
+                Err(crash) => {
+                    rate.crashes += 1;
+                    if repro_crash.is_none() {
+                        repro_crash = crash;
+                    }
+                    executor.start().await;
+                }
+            }
+        }
+
+        let repo = rate.rate() >= self.repro.threshold();
+        if repo {
+            warn!(
+                "Repo successfully, {}/{} runs crashed",
+                rate.crashes, rate.runs
+            );
+        } else if rate.crashes != 0 {
+            warn!(
+                "Repo flaky, only {}/{} runs crashed",
+                rate.crashes, rate.runs
+            );
+        }
+        let verified = if repo { self.verify(&p).await } else { None };
+        let fingerprint = if repo && self.crash_fingerprint {
+            self.fingerprint(&p, executor).await
+        } else {
+            None
+        };
+        let case = self
+            .record
+            .insert_crash(
+                p.clone(),
+                repro_crash.unwrap_or(crash),
+                repo,
+                Some(rate),
+                verified,
+                fingerprint,
+            )
+            .await;
+        if let (Some(case), true) = (case, repo && self.minimize_crashes && p.len() > 1) {
+            self.crash_backlog
+                .push(CrashMinimization::new(p, case))
+                .await;
+        }
+    }
+
+    /// Coverage fingerprint of crash: sorted edges covered by calls before the last one of
+    /// reproducer, i.e. the path leading to crash. None if that prefix crashes or fails too.
+    async fn fingerprint(&self, p: &Prog, executor: &mut Executor) -> Option<Vec<usize>> {
+        if p.len() == 1 {
+            return Some(Vec::new());
+        }
         self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(&p, &self.target).await {
-            Ok(exec_result) => {
-                match exec_result {
-                    ExecResult::Ok(_) => warn!("Repo failed, executed successfully"),
-                    ExecResult::Failed(reason) => warn!("Repo failed, executed failed: {}", reason),
-                };
-                self.record.insert_crash(p, crash, false).await
+        let prefix = p.sub_prog(p.len() - 2);
+        match self.exec(executor, &prefix).await {
+            Ok(ExecResult::Ok(raw_blocks)) | Ok(ExecResult::Truncated(raw_blocks)) => {
+                let mut edges = raw_blocks
+                    .iter()
+                    .flat_map(|raw_blocks| self.cook_raw_block(raw_blocks).1)
+                    .map(|b| b.raw())
+                    .collect::<Vec<_>>();
+                edges.sort_unstable();
+                edges.dedup();
+                Some(edges)
             }
-            Err(repo_crash) => {
-                self.record
-                    .insert_crash(p, repo_crash.unwrap_or(crash), true)
-                    .await;
-                warn!("Repo successfully, restarting guest ...");
+            Ok(_) => None,
+            Err(_) => {
                 executor.start().await;
+                None
             }
         }
     }
 
+    /// All candidates are executed and no new coverage is found for `IDLE_TIME`.
+    async fn is_idle(&self) -> bool {
+        self.candidates.is_done().await
+            && self
+                .feedback
+                .since_last_merge()
+                .await
+                .map_or(true, |t| t >= IDLE_TIME)
+    }
+
+    /// Try removing next call of reproducer of saved crash, it's removed if prog still crashes
+    /// with the same signature without it. Unfinished minimization is queued back to backlog.
+    async fn minimize_crash(&self, mut m: CrashMinimization, executor: &mut Executor) {
+        if m.next == 0 && m.p.len() == m.len {
+            warn!("Minimizing reproducer of {} ...", m.case.meta.title);
+        }
+        while m.next < m.p.len() - 1 {
+            let mut q = m.p.clone();
+            if !remove(&mut q, m.next) {
+                m.next += 1;
+                continue;
+            }
+            let crash = {
+                let _permit = self.crash_permits.acquire().await;
+                self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+                match self.exec(executor, &q).await {
+                    Err(crash) => {
+                        executor.start().await;
+                        crash
+                    }
+                    Ok(_) => None,
+                }
+            };
+            if matches!(crash, Some(c) if c.signature() == m.signature) {
+                m.p = q;
+            } else {
+                m.next += 1;
+            }
+            self.crash_backlog.push(m).await;
+            return;
+        }
+        warn!(
+            "Minimized reproducer of {}: {} -> {} calls",
+            m.case.meta.title,
+            m.len,
+            m.p.len()
+        );
+        if self.crash_fingerprint && m.p.len() != m.len {
+            let _permit = self.crash_permits.acquire().await;
+            m.case.fingerprint = self.fingerprint(&m.p, executor).await;
+        }
+        self.record.update_minimized(m.case, &m.p).await;
+    }
+
+    /// Run reproducer on fresh guest with verify kernel, return whether it crashes there too.
+    async fn verify(&self, p: &Prog) -> Option<bool> {
+        let cfg = self.verify_cfg.as_ref()?;
+        warn!(
+            "Verifying crash on kernel {} ...",
+            cfg.qemu.as_ref().unwrap().kernel
+        );
+        let mut verifier = Executor::new(cfg);
+        // not journaled or counted, verify kernel is not the one being fuzzed
+        let crashed = match verifier.try_start().await {
+            Ok(()) => Some(verifier.exec(p, &self.target).await.is_err()),
+            Err(e) => {
+                warn!("Fail to start verify kernel: {}", e);
+                None
+            }
+        };
+        verifier.shutdown().await;
+        match crashed {
+            Some(true) => warn!("Verify kernel crashed too"),
+            Some(false) => warn!("Verify kernel not crashed, crash may be debug config only"),
+            None => (),
+        }
+        crashed
+    }
+
     fn should_ignore(&self, reason: &str) -> bool {
         if reason.is_empty() {
             true
@@ -209,7 +731,8 @@ impl Fuzzer {
                 let p = p.sub_prog(call_index);
                 let exec_result = self.exec_no_crash(executor, &p).await;
 
-                if let ExecResult::Ok(raw_blocks) = exec_result {
+                if let ExecResult::Ok(raw_blocks) | ExecResult::Truncated(raw_blocks) = exec_result
+                {
                     if raw_blocks.len() == call_index + 1 {
                         let (new_block_2, new_branches_2) =
                             self.check_new_feedback(&raw_blocks[call_index]).await;
@@ -250,7 +773,12 @@ impl Fuzzer {
                                     &new_branches,
                                 )
                                 .await;
-                            self.corpus.insert(minimized_p).await;
+                            if let Some(log) = self.interesting_log.as_ref() {
+                                log.append(&minimized_p, &new_block, &new_branches).await;
+                            }
+                            self.corpus
+                                .insert(minimized_p, branches.into_iter().flatten().collect())
+                                .await;
                             self.feedback.merge(new_block, new_branches).await;
                         }
                     }
@@ -278,7 +806,9 @@ impl Fuzzer {
             p_orig = p.clone();
             if !remove(&mut p, i) {
                 i += 1;
-            } else if let ExecResult::Ok(cover) = self.exec_no_crash(executor, &p).await {
+            } else if let ExecResult::Ok(cover) | ExecResult::Truncated(cover) =
+                self.exec_no_crash(executor, &p).await
+            {
                 let (new_blocks_1, _) = self.check_new_feedback(cover.last().unwrap()).await;
                 if new_blocks_1.is_empty() || new_blocks_1.intersection(new_block).count() == 0 {
                     i += 1;
@@ -320,7 +850,7 @@ impl Fuzzer {
 
     async fn exec_no_crash(&self, executor: &mut Executor, p: &Prog) -> ExecResult {
         self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(p, &self.target).await {
+        match self.exec(executor, p).await {
             Ok(exec_result) => exec_result,
             Err(crash) => {
                 self.crash_analyze(p.clone(), crash.unwrap_or_default(), executor)
@@ -332,10 +862,17 @@ impl Fuzzer {
 
     async fn exec_no_fail(&self, executor: &mut Executor, p: &Prog) -> Vec<Vec<usize>> {
         self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(p, &self.target).await {
+        match self.exec(executor, p).await {
             Ok(exec_result) => match exec_result {
-                ExecResult::Ok(raw_branches) => raw_branches,
-                ExecResult::Failed(_) => Default::default(),
+                ExecResult::Ok(raw_branches)
+                | ExecResult::Truncated(raw_branches)
+                | ExecResult::OkCmp {
+                    covs: raw_branches, ..
+                }
+                | ExecResult::Partial {
+                    covs: raw_branches, ..
+                } => raw_branches,
+                ExecResult::Failed(_) | ExecResult::Oom(_) => Default::default(),
             },
             Err(crash) => {
                 self.crash_analyze(p.clone(), crash.unwrap_or_default(), executor)
@@ -345,8 +882,39 @@ impl Fuzzer {
         }
     }
 
+    /// Wait while fuzzing is paused, keep guest warm with nop command if configured.
+    async fn wait_resumed(&self, executor: &Executor) {
+        let mut idle = Duration::default();
+        while self.paused.load(Ordering::SeqCst) {
+            delay_for(PAUSE_POLL).await;
+            idle += PAUSE_POLL;
+            if matches!(self.keepalive, Some(interval) if idle >= interval) {
+                executor.keepalive().await;
+                idle = Duration::default();
+            }
+        }
+    }
+
+    /// Get next prog, keep guest warm with nop command if it takes too long.
+    async fn next_prog(&self, gen_cnt: &mut usize, executor: &Executor) -> Prog {
+        let interval = match self.keepalive {
+            Some(interval) => interval,
+            None => return self.get_prog(gen_cnt).await,
+        };
+        let p = self.get_prog(gen_cnt);
+        tokio::pin!(p);
+        loop {
+            tokio::select! {
+                p = &mut p => return p,
+                _ = delay_for(interval) => executor.keepalive().await,
+            }
+        }
+    }
+
+    /// Progs are generated on demand by fuzz loop of each guest right before execution,
+    /// so generation pauses by itself while guest is rebooting and never piles up.
     async fn get_prog(&self, gen_cnt: &mut usize) -> Prog {
-        if let Some(p) = self.candidates.pop().await {
+        if let Some(p) = self.candidates.pop_wait().await {
             p
         } else if self.corpus.is_empty().await || *gen_cnt % 100 != 0 {
             *gen_cnt += 1;
@@ -1,102 +1,117 @@
 /// Driver for kernel to be tested
+use crate::crash::{self, normalize_log, CrashReport};
 use crate::utils::cli::{App, Arg, OptVal};
-use crate::utils::free_ipv4_port;
+use crate::utils::cmdline::Cmdline;
+use crate::utils::restore::ImageGuard;
+use crate::utils::rotate::RotatingFile;
+use crate::utils::{free_ipv4_port, meminfo_mb};
 use crate::Config;
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::unistd::{access, AccessFlags};
 use os_pipe::{pipe, PipeReader, PipeWriter};
+use regex::Regex;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::env::temp_dir;
 use std::fmt;
-use std::io::{ErrorKind, Read};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::process::exit;
-use tokio::process::Child;
+use std::process::{exit, id, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use thiserror::Error;
+use tokio::fs::remove_file;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::runtime::Handle;
+use tokio::task::spawn_blocking;
 use tokio::time::{delay_for, timeout, Duration};
 
 lazy_static! {
     static ref QEMUS: HashMap<String, App> = {
         let mut qemus = HashMap::new();
 
+        // With `panic=1` in kernel cmdline, guest reboots 1s after panic, and `-no-reboot`
+        // turns that reboot into qemu exiting. So exit of qemu is a reliable crash signal,
+        // see `crash_signal` of QemuConf.
         let arg_common = vec![
             Arg::new_flag("-no-reboot"),
             Arg::new_opt("-display", OptVal::normal("none")),
-            Arg::new_opt("-serial", OptVal::normal("stdio")),
-            Arg::new_flag("-snapshot"),
         ];
 
+        // kvm and cpu of amd64 are added by build_qemu_cli, see `no_kvm` of QemuConf
         let mut linux_amd64 = App::new("qemu-system-x86_64");
         linux_amd64
-            .arg(Arg::new_flag("-enable-kvm"))
             .args(arg_common.iter())
-            .arg(Arg::new_opt(
-                "-cpu",
-                OptVal::multiple(vec!["host", "migratable=off"], Some(',')),
-            ))
             .arg(Arg::new_opt(
                 "-net",
                 OptVal::multiple(vec!["nic", "model=e1000"], Some(',')),
-            ))
-            .arg(Arg::new_opt(
-                "-append",
-                OptVal::multiple(
-                    vec![
-                        "earlyprintk=serial",
-                        "oops=panic",
-                        "nmi_watchdog=panic",
-                        "panic_on_warn=1",
-                        "panic=1",
-                        "ftrace_dump_on_oops=orig_cpu",
-                        "rodata=n",
-                        "vsyscall=native",
-                        "net.ifnames=0",
-                        "biosdevname=0",
-                        "root=/dev/sda",
-                        "console=ttyS0",
-                        "kvm-intel.nested=1",
-                        "kvm-intel.unrestricted_guest=1",
-                        "kvm-intel.vmm_exclusive=1",
-                        "kvm-intel.fasteoi=1",
-                        "kvm-intel.ept=1",
-                        "kvm-intel.flexpriority=1",
-                        "kvm-intel.vpid=1",
-                        "kvm-intel.emulate_invalid_guest_state=1",
-                        "kvm-intel.eptad=1",
-                        "kvm-intel.enable_shadow_vmcs=1",
-                        "kvm-intel.pml=1",
-                        "kvm-intel.enable_apicv=1",
-                    ],
-                    Some(' '),
-                ),
             ));
 
         let mut linux_arm = App::new("qemu-system-arm");
         linux_arm
             .args(arg_common.iter())
-            .arg(Arg::new_opt("-net", OptVal::normal("nic")))
-            .arg(Arg::new_opt(
-                "-append",
-                OptVal::multiple(vec!["root=/dev/vda", "console=ttyAMA0"], Some(' ')),
-            ));
+            .arg(Arg::new_opt("-net", OptVal::normal("nic")));
 
         let mut linux_arm64 = App::new("qemu-system-aarch64");
         linux_arm64
             .args(arg_common.iter())
-            .arg(Arg::new_opt(
-                "-machine",
-                OptVal::multiple(vec!["virt", "virtualization=on"], Some(',')),
-            ))
             .arg(Arg::new_opt("-cpu", OptVal::normal("cortex-a57")))
-            .arg(Arg::new_opt("-net", OptVal::normal("nic")))
-            .arg(Arg::new_opt(
-                "-append",
-                OptVal::multiple(vec!["root=/dev/vda", "console=ttyAMA0"], Some(' ')),
-            ));
+            .arg(Arg::new_opt("-net", OptVal::normal("nic")));
 
         qemus.insert("linux/amd64".to_string(), linux_amd64);
         qemus.insert("linux/arm".to_string(), linux_arm);
         qemus.insert("linux/arm64".to_string(), linux_arm64);
         qemus
     };
+    /// Default machine type of each target, rendered as `-machine` of qemu.
+    /// Target absent here uses default machine of qemu.
+    static ref MACHINES: HashMap<String, String> = {
+        let mut machines = HashMap::new();
+        machines.insert("linux/arm64".to_string(), "virt,virtualization=on".to_string());
+        machines
+    };
+    /// Default kernel cmdline of each target, rendered as `-append` of qemu.
+    static ref CMDLINES: HashMap<String, Cmdline> = {
+        let mut cmdlines = HashMap::new();
+        cmdlines.insert(
+            "linux/amd64".to_string(),
+            Cmdline::from(vec![
+                "earlyprintk=serial",
+                "oops=panic",
+                "nmi_watchdog=panic",
+                "panic_on_warn=1",
+                "panic=1",
+                "ftrace_dump_on_oops=orig_cpu",
+                "rodata=n",
+                "vsyscall=native",
+                "net.ifnames=0",
+                "biosdevname=0",
+                "root=/dev/sda",
+                "console=ttyS0",
+                "kvm-intel.nested=1",
+                "kvm-intel.unrestricted_guest=1",
+                "kvm-intel.vmm_exclusive=1",
+                "kvm-intel.fasteoi=1",
+                "kvm-intel.ept=1",
+                "kvm-intel.flexpriority=1",
+                "kvm-intel.vpid=1",
+                "kvm-intel.emulate_invalid_guest_state=1",
+                "kvm-intel.eptad=1",
+                "kvm-intel.enable_shadow_vmcs=1",
+                "kvm-intel.pml=1",
+                "kvm-intel.enable_apicv=1",
+            ]),
+        );
+        let arm = Cmdline::from(vec!["root=/dev/vda", "console=ttyAMA0"]);
+        cmdlines.insert("linux/arm".to_string(), arm.clone());
+        cmdlines.insert("linux/arm64".to_string(), arm);
+        cmdlines
+    };
     pub static ref SSH: App = {
         let mut ssh = App::new("ssh");
         ssh.arg(Arg::new_opt("-F", OptVal::normal("/dev/null")))
@@ -128,6 +143,8 @@ lazy_static! {
             ));
         scp
     };
+    /// Cursor movements and erasures echoed by qemu monitor
+    static ref TERM_ESCAPE: Regex = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -136,12 +153,18 @@ pub struct GuestConf {
     pub os: String,
     /// Arch of build kernel
     pub arch: String,
-    /// Platform to run kernel, qemu or real env
+    /// Platform to run kernel, qemu, real (running machine reached over ssh)
+    /// or ns (executor runs on host kernel, requires --unsafe-host)
     pub platform: String,
+    /// Kernel versions supported by target
+    pub kernel_version: Option<KernelVersionConf>,
+    /// Qemu monitor commands run on crashed guest before qemu is killed, output is saved
+    /// with crash, e.g. ["info registers", "info cpus"]. Qemu only.
+    pub triage_cmds: Option<Vec<String>>,
 }
 
-pub const PLATFORM: [&str; 1] = ["qemu"];
-pub const ARCH: [&str; 1] = ["amd64"];
+pub const PLATFORM: [&str; 3] = ["qemu", "real", "ns"];
+pub const ARCH: [&str; 2] = ["amd64", "arm64"];
 pub const OS: [&str; 1] = ["linux"];
 
 impl GuestConf {
@@ -156,19 +179,181 @@ impl GuestConf {
             );
             exit(exitcode::CONFIG)
         }
+
+        if let Some(kernel_version) = self.kernel_version.as_ref() {
+            kernel_version.check()
+        }
+
+        if let Some(cmds) = self.triage_cmds.as_ref() {
+            if cmds.iter().any(|c| c.trim().is_empty()) {
+                eprintln!("Config Error: empty triage command");
+                exit(exitcode::CONFIG)
+            }
+            if self.platform != "qemu" {
+                eprintln!("Config Error: triage_cmds is only supported on qemu");
+                exit(exitcode::CONFIG)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KernelVersionConf {
+    /// Minimal kernel version supported by target, e.g. "5.4"
+    pub min: Option<String>,
+    /// Maximal kernel version supported by target, e.g. "5.9"
+    pub max: Option<String>,
+    /// Exit instead of warning if guest kernel version mismatches
+    pub strict: Option<bool>,
+}
+
+impl KernelVersionConf {
+    pub fn check(&self) {
+        for v in self.min.iter().chain(self.max.iter()) {
+            if parse_kernel_version(v).is_none() {
+                eprintln!("Config Error: invalid kernel version {}", v);
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let (Some(min), Some(max)) = (self.min.as_ref(), self.max.as_ref()) {
+            if parse_kernel_version(min) > parse_kernel_version(max) {
+                eprintln!(
+                    "Config Error: min kernel version {} is bigger than max version {}",
+                    min, max
+                );
+                exit(exitcode::CONFIG)
+            }
+        }
+    }
+
+    /// Judge if given version in range [min, max], only compare numeric parts that both have.
+    pub fn matches(&self, version: &str) -> bool {
+        let version = match parse_kernel_version(version) {
+            Some(v) => v,
+            None => return false,
+        };
+        let cmp = |bound: &str| {
+            let bound = parse_kernel_version(bound).unwrap();
+            let n = bound.len().min(version.len());
+            version[..n].cmp(&bound[..n])
+        };
+
+        self.min.iter().all(|min| cmp(min).is_ge()) && self.max.iter().all(|max| cmp(max).is_le())
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict.unwrap_or(false)
+    }
+}
+
+impl fmt::Display for KernelVersionConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}, {}]",
+            self.min.as_deref().unwrap_or("*"),
+            self.max.as_deref().unwrap_or("*")
+        )
+    }
+}
+
+/// Parse numeric parts of kernel version, e.g. "5.8.0-rc3+" -> [5, 8, 0].
+pub fn parse_kernel_version(version: &str) -> Option<Vec<u32>> {
+    let mut nums = Vec::new();
+    for part in version.trim().split('.') {
+        let digits = part
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>();
+        if digits.is_empty() {
+            break;
+        }
+        nums.push(digits.parse().ok()?);
+        if digits.len() != part.len() {
+            break;
+        }
+    }
+    if nums.is_empty() {
+        None
+    } else {
+        Some(nums)
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct QemuConf {
     pub cpu_num: u32,
+    /// Memory of guest in MB, passed to `-m`
     pub mem_size: u32,
     pub image: String,
     pub kernel: String,
     pub wait_boot_time: Option<u8>,
+    /// Extra data disks attached to guest besides root image
+    pub extra_disks: Option<Vec<DiskConf>>,
+    /// Backend of guest serial, stdio, file, pty or unix, stdio by default
+    pub serial: Option<String>,
+    /// Number of retries with new port if qemu fails to set up host forwarding, 64 by default
+    pub hostfwd_retry: Option<u8>,
+    /// Number of attempts to boot guest if it's not reachable within 3 * wait_boot_time,
+    /// 1 by default
+    pub boot_retry: Option<u8>,
+    /// Signal of crash: `exit` only treats exit of qemu as crash,
+    /// `both` also treats unreachable guest as crash. `both` by default.
+    pub crash_signal: Option<String>,
+    /// Run with `-snapshot`, so writes to image and data disks are discarded, true by default
+    pub snapshot: Option<bool>,
+    /// Kernel with different config, reproduced crashes are verified on it
+    pub verify_kernel: Option<String>,
+    /// Save guest console output of each guest to rotated log files
+    pub serial_log: Option<SerialLogConf>,
+    /// Extra kernel cmdline, overrides default values of same keys
+    pub cmdline: Option<String>,
+    /// Machine type passed to `-machine` as is, overrides default of target
+    pub machine: Option<String>,
+    /// Golden copy of image, image is restored from it before each boot without snapshot,
+    /// or if it's mutated in snapshot mode
+    pub image_backup: Option<String>,
+    /// Inject NMI through qemu monitor before rebooting a hung guest, so that kernel
+    /// panics and dumps backtrace as crash, false by default
+    pub nmi_on_hang: Option<bool>,
+    /// Run without kvm (e.g. in CI container), cpu is emulated by TCG, false by default
+    pub no_kvm: Option<bool>,
+    /// Extra arguments appended to qemu cli verbatim, one token each, e.g. `["-device", "virtio-rng-pci"]`
+    pub extra_args: Option<Vec<String>>,
+    /// Uncompressed kernel with symbols used to symbolize raw addresses of crash reports,
+    /// `vmlinux` next to `kernel` by default
+    pub vmlinux: Option<String>,
+    /// `nm` of toolchain the kernel is built with, `nm` by default
+    pub nm: Option<String>,
 }
 
+pub const SERIAL: [&str; 4] = ["stdio", "file", "pty", "unix"];
+pub const CRASH_SIGNAL: [&str; 2] = ["both", "exit"];
+/// Targets run with kvm unless `no_kvm` is set
+pub const KVM_TARGETS: [&str; 1] = ["linux/amd64"];
+const KVM_DEV: &str = "/dev/kvm";
+/// Targets whose machine has no ide bus, root image is attached as virtio disk `/dev/vda`
+pub const VIRTIO_DISK_TARGETS: [&str; 2] = ["linux/arm", "linux/arm64"];
+
 impl QemuConf {
+    pub fn vmlinux(&self) -> PathBuf {
+        match self.vmlinux.as_ref() {
+            Some(v) => PathBuf::from(v),
+            None => Path::new(&self.kernel).with_file_name("vmlinux"),
+        }
+    }
+
+    /// Crash reports are symbolized only if vmlinux exists, kernel is then booted with `nokaslr`,
+    /// otherwise randomized addresses would resolve to wrong symbols.
+    pub fn symbolize(&self) -> bool {
+        self.vmlinux().is_file()
+    }
+
+    pub fn nm(&self) -> String {
+        self.nm.clone().unwrap_or_else(|| String::from("nm"))
+    }
+
     pub fn check(&self) {
         let cpu_num = num_cpus::get() as u32;
         if self.cpu_num > cpu_num * 8 || self.cpu_num == 0 {
@@ -180,11 +365,21 @@ impl QemuConf {
             exit(exitcode::CONFIG)
         }
 
-        if self.mem_size < 512 {
-            eprintln!(
-                "Config Error: invalid mem size {}, mem size must bigger than 512 bytes",
-                self.mem_size
-            );
+        if self.extra_args.iter().flatten().any(|a| a.is_empty()) {
+            eprintln!("Config Error: empty extra qemu argument");
+            exit(exitcode::CONFIG)
+        }
+
+        if self.boot_retry == Some(0) {
+            eprintln!("Config Error: boot retry must be at least 1");
+            exit(exitcode::CONFIG)
+        }
+
+        let host_mem = std::fs::read_to_string("/proc/meminfo")
+            .ok()
+            .and_then(|m| meminfo_mb(&m, "MemTotal"));
+        if let Err(e) = self.check_mem(host_mem) {
+            eprintln!("Config Error: {}", e);
             exit(exitcode::CONFIG)
         }
 
@@ -198,12 +393,241 @@ impl QemuConf {
             eprintln!("Config Error: kernel {} is invalid", self.kernel);
             exit(exitcode::CONFIG)
         }
+        if let Some(backup) = self.image_backup.as_ref() {
+            if !Path::new(backup).is_file() {
+                eprintln!("Config Error: image backup {} is invalid", backup);
+                exit(exitcode::CONFIG)
+            }
+            if Path::new(backup) == Path::new(&self.image) {
+                eprintln!("Config Error: image backup must be a different file from image");
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let Some(cmdline) = self.cmdline.as_ref() {
+            if let Err(e) = Cmdline::parse(cmdline) {
+                eprintln!("Config Error: invalid kernel cmdline: {}", e);
+                exit(exitcode::CONFIG)
+            }
+        }
+        if let Some(verify_kernel) = self.verify_kernel.as_ref() {
+            if !Path::new(verify_kernel).is_file() {
+                eprintln!("Config Error: verify kernel {} is invalid", verify_kernel);
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let Some(disks) = self.extra_disks.as_ref() {
+            for disk in disks {
+                disk.check()
+            }
+        }
+
+        for (path, reason) in self.writable_paths() {
+            if access(path.as_path(), AccessFlags::W_OK).is_err() {
+                eprintln!(
+                    "Config Error: {} is not writable, which is required by {}",
+                    path.display(),
+                    reason
+                );
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let Some(serial_log) = self.serial_log.as_ref() {
+            serial_log.check()
+        }
+
+        if !CRASH_SIGNAL.contains(&self.crash_signal()) {
+            eprintln!(
+                "Config Error: invalid crash signal {}, supported signals: {:?}",
+                self.crash_signal(),
+                CRASH_SIGNAL
+            );
+            exit(exitcode::CONFIG)
+        }
+
+        if !SERIAL.contains(&self.serial()) {
+            eprintln!(
+                "Config Error: invalid serial backend {}, supported backends: {:?}",
+                self.serial(),
+                SERIAL
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+
+    pub fn serial(&self) -> &str {
+        self.serial.as_deref().unwrap_or("stdio")
+    }
+
+    pub fn boot_retry(&self) -> u8 {
+        self.boot_retry.unwrap_or(1)
+    }
+
+    pub fn crash_signal(&self) -> &str {
+        self.crash_signal.as_deref().unwrap_or("both")
+    }
+
+    pub fn snapshot(&self) -> bool {
+        self.snapshot.unwrap_or(true)
+    }
+
+    pub fn nmi_on_hang(&self) -> bool {
+        self.nmi_on_hang.unwrap_or(false)
+    }
+
+    /// Mem size (MB) should be at least 512 MB and not exceed memory of host (MB) if known.
+    fn check_mem(&self, host_mem: Option<u64>) -> Result<(), String> {
+        if self.mem_size < 512 {
+            return Err(format!(
+                "invalid mem size {} MB, mem size must be at least 512 MB",
+                self.mem_size
+            ));
+        }
+        match host_mem {
+            Some(host_mem) if u64::from(self.mem_size) > host_mem => Err(format!(
+                "invalid mem size {} MB, mem size must not exceed {} MB memory of host",
+                self.mem_size, host_mem
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn no_kvm(&self) -> bool {
+        self.no_kvm.unwrap_or(false)
+    }
+
+    /// Check that kvm is usable if target of `g` runs with it.
+    pub fn check_kvm(&self, g: &GuestConf) {
+        let target = format!("{}/{}", g.os, g.arch);
+        if self.no_kvm() || !KVM_TARGETS.contains(&target.as_str()) {
+            return;
+        }
+        if let Err(e) = access(KVM_DEV, AccessFlags::R_OK | AccessFlags::W_OK) {
+            eprintln!(
+                "Config Error: {} is not accessible: {}, enable kvm or set qemu.no_kvm to run without it",
+                KVM_DEV, e
+            );
+            exit(exitcode::CONFIG)
+        }
+    }
+
+    /// Paths qemu needs to write with current config, and why.
+    fn writable_paths(&self) -> Vec<(PathBuf, &'static str)> {
+        let mut paths = Vec::new();
+        // overlays of -snapshot and zero_per_boot disks are created in temp dir
+        paths.push((temp_dir(), "-snapshot overlays and scratch disks"));
+        if !self.snapshot() {
+            paths.push((PathBuf::from(&self.image), "snapshot = false"));
+        }
+        for disk in self.extra_disks.iter().flatten() {
+            if !self.snapshot() && !disk.zero_per_boot() {
+                paths.push((PathBuf::from(&disk.path), "snapshot = false"));
+            }
+        }
+        paths
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SerialLogConf {
+    /// Dir of serial logs, ./serial_logs by default
+    pub dir: Option<String>,
+    /// Max size of each log file in MB, 64 by default
+    pub max_size: Option<u64>,
+    /// Number of rotated log files kept per guest, 3 by default
+    pub keep: Option<usize>,
+    /// Rotate log at each boot of guest, so each file starts with a boot, false by default
+    pub rotate_on_boot: Option<bool>,
+}
+
+impl SerialLogConf {
+    pub fn check(&self) {
+        if self.max_size == Some(0) {
+            eprintln!("Config Error: max size of serial log must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+    }
+
+    pub fn dir(&self) -> &str {
+        self.dir.as_deref().unwrap_or("./serial_logs")
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(64) * 1024 * 1024
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(3)
+    }
+
+    pub fn rotate_on_boot(&self) -> bool {
+        self.rotate_on_boot.unwrap_or(false)
+    }
+}
+
+pub const DISK_FORMAT: [&str; 2] = ["raw", "qcow2"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiskConf {
+    /// Path of disk image
+    pub path: String,
+    /// Format of disk image, raw or qcow2, raw by default
+    pub format: Option<String>,
+    /// Recreate a blank disk before each boot instead of using `path` directly
+    pub zero_per_boot: Option<bool>,
+    /// Size of recreated disk, e.g. "512M", required by zero_per_boot
+    pub size: Option<String>,
+}
+
+impl DiskConf {
+    pub fn check(&self) {
+        if !DISK_FORMAT.contains(&self.format()) {
+            eprintln!(
+                "Config Error: invalid format {} of disk {}, supported formats: {:?}",
+                self.format(),
+                self.path,
+                DISK_FORMAT
+            );
+            exit(exitcode::CONFIG)
+        }
+
+        if self.zero_per_boot() {
+            if self.size.is_none() {
+                eprintln!(
+                    "Config Error: size of disk {} is required by zero_per_boot",
+                    self.path
+                );
+                exit(exitcode::CONFIG)
+            }
+        } else if !Path::new(&self.path).is_file() {
+            eprintln!("Config Error: disk {} is invalid", self.path);
+            exit(exitcode::CONFIG)
+        }
+    }
+
+    pub fn format(&self) -> &str {
+        self.format.as_deref().unwrap_or("raw")
+    }
+
+    pub fn zero_per_boot(&self) -> bool {
+        self.zero_per_boot.unwrap_or(false)
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SSHConf {
     pub key_path: String,
+    /// Ciphers used by ssh and scp, e.g. aes128-gcm@openssh.com, ssh default if absent
+    pub ciphers: Option<String>,
+    /// Enable compression, ssh default (no) if absent
+    pub compression: Option<bool>,
+    /// Compression level 1-9, only honored by ssh implementations still supporting it
+    pub compression_level: Option<u8>,
+    /// Multiplex ssh and scp of each guest over one connection (ControlMaster), true by
+    /// default, disable it for ssh builds without multiplexing support
+    pub multiplex: Option<bool>,
 }
 
 impl SSHConf {
@@ -213,25 +637,180 @@ impl SSHConf {
             eprintln!("Config Error: ssh key file {} is invalid", self.key_path);
             exit(exitcode::CONFIG)
         }
+
+        if let Some(ciphers) = self.ciphers.as_ref() {
+            if ciphers.is_empty() || ciphers.contains(char::is_whitespace) {
+                eprintln!("Config Error: invalid ssh ciphers \"{}\"", ciphers);
+                exit(exitcode::CONFIG)
+            }
+        }
+
+        if let Some(level) = self.compression_level {
+            if !(1..=9).contains(&level) {
+                eprintln!(
+                    "Config Error: ssh compression level must between 1 and 9: {}",
+                    level
+                );
+                exit(exitcode::CONFIG)
+            }
+        }
+    }
+
+    pub fn multiplex(&self) -> bool {
+        self.multiplex.unwrap_or(true)
+    }
+
+    /// Add configured options to ssh or scp app.
+    pub fn apply(&self, mut app: App) -> App {
+        if let Some(ciphers) = self.ciphers.as_ref() {
+            app.arg(Arg::new_opt(
+                "-o",
+                OptVal::normal(&format!("Ciphers={}", ciphers)),
+            ));
+        }
+        if let Some(compression) = self.compression {
+            let compression = if compression { "yes" } else { "no" };
+            app.arg(Arg::new_opt(
+                "-o",
+                OptVal::normal(&format!("Compression={}", compression)),
+            ));
+        }
+        if let Some(level) = self.compression_level {
+            app.arg(Arg::new_opt(
+                "-o",
+                OptVal::normal(&format!("CompressionLevel={}", level)),
+            ));
+        }
+        app
+    }
+}
+
+/// Running machine reached over ssh, e.g. a physical board, used by platform real.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RealConf {
+    pub addr: String,
+    /// Ssh port, 22 by default
+    pub port: Option<u16>,
+    /// Ssh user, root by default
+    pub user: Option<String>,
+    /// Ssh key, `ssh.key_path` by default
+    pub key_path: Option<String>,
+    /// File on host that serial console of machine is logged to, e.g. by conserver,
+    /// crash log is read from it
+    pub serial_log: Option<String>,
+    /// Seconds waiting for machine to be reachable again, e.g. after crash, 300 by default
+    pub wait_boot_time: Option<u64>,
+}
+
+impl RealConf {
+    pub fn check(&self) {
+        if self.addr.trim().is_empty() {
+            eprintln!("Config Error: empty address of real machine");
+            exit(exitcode::CONFIG)
+        }
+        if self.port == Some(0) || self.wait_boot_time == Some(0) {
+            eprintln!(
+                "Config Error: port and wait boot time of real machine must be greater than 0"
+            );
+            exit(exitcode::CONFIG)
+        }
+        if let Some(key) = self.key_path.as_ref() {
+            if !Path::new(key).is_file() {
+                eprintln!("Config Error: ssh key file {} is invalid", key);
+                exit(exitcode::CONFIG)
+            }
+        }
+        if let Some(log) = self.serial_log.as_ref() {
+            if !Path::new(log).is_file() {
+                eprintln!(
+                    "Config Error: serial log {} of real machine is invalid",
+                    log
+                );
+                exit(exitcode::CONFIG)
+            }
+        }
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum Guest {
-    LinuxQemu(LinuxQemu),
+    LinuxQemu(Box<LinuxQemu>),
+    LinuxReal(Box<LinuxReal>),
+    LinuxNs(LinuxNs),
 }
 
 impl Guest {
     pub fn new(cfg: &Config) -> Self {
-        // only support linux/amd64 on qemu now.
-        Guest::LinuxQemu(LinuxQemu::new(cfg))
+        match cfg.guest.platform.as_str() {
+            "real" => Guest::LinuxReal(Box::new(LinuxReal::new(cfg))),
+            "ns" => Guest::LinuxNs(LinuxNs::new(cfg)),
+            _ => Guest::LinuxQemu(Box::new(LinuxQemu::new(cfg))),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum GuestError {
+    #[error("Fail to spawn qemu: {0}")]
+    SpawnFailed(io::Error),
+    /// Guest is unreachable after all boot attempts, with captured console output
+    #[error("Fail to boot kernel:\n{0}")]
+    BootTimeout(String),
+    #[error("Fail to kill guest: {0}")]
+    KillFailed(io::Error),
+    #[error("Fail to set up host forwarding after {retry} retries, last port: {port}")]
+    HostfwdFailed { retry: u8, port: u16 },
+    /// Qemu exited before guest is reachable, e.g. bad kernel path or no kvm
+    #[error("Qemu exited early ({status}):\n{output}")]
+    QemuExited { status: String, output: String },
+    /// Scp to guest failed, guest may be rebooted and copying retried
+    #[error("Fail to copy {path} to guest: {reason}")]
+    CopyFailed { path: String, reason: String },
+    #[error("Fail to create disk {path}: {reason}")]
+    DiskFailed { path: String, reason: String },
+    #[error("Fail to open {0} serial of guest")]
+    SerialFailed(String),
+    #[error("Fail to spawn {bin}: {err}")]
+    CmdSpawnFailed { bin: String, err: io::Error },
+}
+
+impl GuestError {
+    /// Exit code for callers that can't recover from the error
+    pub fn code(&self) -> i32 {
+        match self {
+            GuestError::SpawnFailed(_)
+            | GuestError::KillFailed(_)
+            | GuestError::DiskFailed { .. }
+            | GuestError::SerialFailed(_)
+            | GuestError::CmdSpawnFailed { .. } => exitcode::OSERR,
+            GuestError::BootTimeout(_) => exitcode::UNAVAILABLE,
+            GuestError::HostfwdFailed { .. } => exitcode::TEMPFAIL,
+            GuestError::QemuExited { .. } => exitcode::CONFIG,
+            GuestError::CopyFailed { .. } => exitcode::IOERR,
+        }
     }
 }
 
 impl Guest {
-    /// Boot guest or panic
-    pub async fn boot(&mut self) {
+    /// Boot guest, previous running guest is killed first
+    pub async fn boot(&mut self) -> Result<(), GuestError> {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.boot().await,
+            Guest::LinuxReal(ref mut guest) => guest.boot().await,
+            Guest::LinuxNs(ref mut guest) => guest.boot().await,
+        }
+    }
+
+    /// Stop guest for good, e.g. kill qemu, and close its ssh connection.
+    /// Machine of platform real is left running.
+    pub async fn shutdown(&mut self) {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => {
+                guest.shutdown().await;
+                guest.remote.close().await
+            }
+            Guest::LinuxReal(ref guest) => guest.remote.close().await,
+            Guest::LinuxNs(_) => (),
         }
     }
 
@@ -239,13 +818,63 @@ impl Guest {
     pub async fn is_alive(&self) -> bool {
         match self {
             Guest::LinuxQemu(ref guest) => guest.is_alive().await,
+            Guest::LinuxReal(ref guest) => guest.is_alive().await,
+            Guest::LinuxNs(ref guest) => guest.is_alive().await,
+        }
+    }
+
+    /// Kernel version of guest, available after first boot
+    pub fn kernel_version(&self) -> Option<String> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.kernel_version.clone(),
+            Guest::LinuxReal(ref guest) => guest.kernel_version.clone(),
+            Guest::LinuxNs(ref guest) => guest.kernel_version.clone(),
+        }
+    }
+
+    /// Probe if kcov is available in guest, return error if guest is unreachable
+    pub async fn probe_kcov(&self) -> Result<bool, String> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.probe_kcov().await,
+            Guest::LinuxReal(ref guest) => guest.probe_kcov().await,
+            Guest::LinuxNs(ref guest) => guest.probe_kcov().await,
         }
     }
 
-    /// Run command on guest,return handle or crash
-    pub async fn run_cmd(&self, app: &App) -> Child {
+    /// Run command on guest, return handle or error of copying command to guest
+    pub async fn run_cmd(&self, app: &App) -> Result<Child, GuestError> {
         match self {
             Guest::LinuxQemu(ref guest) => guest.run_cmd(app).await,
+            Guest::LinuxReal(ref guest) => guest.run_cmd(app).await,
+            Guest::LinuxNs(ref guest) => guest.run_cmd(app).await,
+        }
+    }
+
+    /// Run command on guest like `run_cmd`, wait its output within `dur`, ssh process is
+    /// killed and TimedOut error is returned once `dur` expires
+    pub async fn run_cmd_timeout(&self, app: &App, dur: Duration) -> io::Result<Output> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.run_cmd_timeout(app, dur).await,
+            Guest::LinuxReal(ref guest) => guest.run_cmd_timeout(app, dur).await,
+            Guest::LinuxNs(ref guest) => guest.run_cmd_timeout(app, dur).await,
+        }
+    }
+
+    /// Run command present in guest, wait its output within `wait`, error if timed out
+    pub async fn run_cmd_output(&self, app: App, wait: Duration) -> Result<CmdOutput, String> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.run_cmd_output(app, wait).await,
+            Guest::LinuxReal(ref guest) => guest.run_cmd_output(app, wait).await,
+            Guest::LinuxNs(ref guest) => guest.run_cmd_output(app, wait).await,
+        }
+    }
+
+    /// Judge if guest crashed according to configured crash signal
+    pub async fn is_crashed(&mut self) -> bool {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => guest.is_crashed().await,
+            Guest::LinuxReal(ref mut guest) => guest.is_crashed().await,
+            Guest::LinuxNs(ref mut guest) => guest.is_crashed().await,
         }
     }
 
@@ -253,19 +882,52 @@ impl Guest {
     pub async fn try_collect_crash(&mut self) -> Option<Crash> {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.try_collect_crash().await,
+            Guest::LinuxReal(ref mut guest) => guest.try_collect_crash().await,
+            Guest::LinuxNs(ref mut guest) => guest.try_collect_crash().await,
+        }
+    }
+
+    /// Whether hung guest is poked with NMI before reboot
+    pub fn nmi_on_hang(&self) -> bool {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.qemu.nmi_on_hang(),
+            Guest::LinuxReal(_) | Guest::LinuxNs(_) => false,
+        }
+    }
+
+    /// Inject NMI to hung guest, collect the dumped backtrace if guest panics on it
+    pub async fn dump_hang(&mut self) -> Option<Crash> {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => guest.dump_hang().await,
+            Guest::LinuxReal(_) | Guest::LinuxNs(_) => None,
         }
     }
 
-    pub async fn clear(&mut self) {
+    /// Drain guest serial output, return drained content
+    pub async fn clear(&mut self) -> String {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.clear().await,
+            Guest::LinuxReal(ref mut guest) => guest.clear().await,
+            Guest::LinuxNs(ref mut guest) => guest.clear().await,
         }
     }
 
-    /// Copy file from host to guest, return path in guest or crash
-    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> PathBuf {
+    /// Copy file from host to guest, return path in guest. File with the same
+    /// content is copied only once per boot.
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
         match self {
             Guest::LinuxQemu(ref guest) => guest.copy(path).await,
+            Guest::LinuxReal(ref guest) => guest.copy(path).await,
+            Guest::LinuxNs(ref guest) => guest.copy(path).await,
+        }
+    }
+
+    /// Copy file from host to guest even if it was copied before
+    pub async fn copy_force<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.copy_force(path).await,
+            Guest::LinuxReal(ref guest) => guest.copy_force(path).await,
+            Guest::LinuxNs(ref guest) => guest.copy_force(path).await,
         }
     }
 }
@@ -273,35 +935,104 @@ impl Guest {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Crash {
     pub inner: String,
+    /// Kernel version of crashed guest
+    #[serde(default)]
+    pub kernel_version: Option<String>,
+    /// Output of triage commands run on qemu monitor at crash time
+    #[serde(default)]
+    pub triage: Vec<TriageOutput>,
+}
+
+impl Crash {
+    /// Structured form of crash log, see `crash::parse`
+    pub fn parse(&self) -> CrashReport {
+        crash::parse(&self.inner)
+    }
+
+    /// Signature for grouping crashes, see `crash::signature_of`. Falls back to digest of
+    /// normalized log if it has neither title nor call trace.
+    pub fn signature(&self) -> String {
+        let sig = crash::signature_of(&self.inner);
+        if sig.is_empty() {
+            format!("{:x}", md5::compute(normalize_log(&self.inner)))
+        } else {
+            sig
+        }
+    }
 }
 
 impl Default for Crash {
     fn default() -> Self {
         Crash {
             inner: String::new(),
+            kernel_version: None,
+            triage: Vec::new(),
         }
     }
 }
 
-impl fmt::Display for Crash {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.inner)
+/// Output of command executed in guest
+#[derive(Debug, Clone)]
+pub struct CmdOutput {
+    /// Exit code, None if killed by signal. Ssh exits with 255 if connection failed.
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CmdOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TriageOutput {
+    pub cmd: String,
+    /// Output of cmd, or reason of failure
+    pub output: String,
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+/// Exit code of ssh itself on error, e.g. connection refused or timeout.
+const SSH_CONN_ERROR: i32 = 255;
+/// Attempts of ssh probe before guest is considered dead.
+const SSH_PROBE_TRIES: usize = 3;
+const SSH_PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
 pub const LINUX_QEMU_HOST_IP_ADDR: &str = "localhost";
 pub const LINUX_QEMU_USER_NET_HOST_IP_ADDR: &str = "10.0.2.10";
 pub const LINUX_QEMU_HOST_USER: &str = "root";
+pub const KCOV_PATH: &str = "/sys/kernel/debug/kcov";
+/// Error msg of qemu if forward port is not available
+const HOSTFWD_FAILED: &str = "ould not set up host forwarding rule";
+
+/// Reader of guest serial backend other than stdio
+type SerialReader = Box<dyn Read + Send + Sync>;
+
+/// Number of guests created, used for naming serial log of each guest
+static GUEST_NUM: AtomicUsize = AtomicUsize::new(0);
+/// Number of ssh remotes created, used for naming control dir of each remote
+static REMOTE_NUM: AtomicUsize = AtomicUsize::new(0);
 
 pub struct LinuxQemu {
     handle: Option<Child>,
+    /// Stdout and stderr of qemu, contains guest serial output with stdio backend
     rp: Option<PipeReader>,
+    serial: Option<SerialReader>,
+    kernel_version: Option<String>,
+    /// Disks recreated for current boot
+    scratch_disks: Vec<PathBuf>,
+    serial_log: Option<RotatingFile>,
+    image_guard: Option<Arc<ImageGuard>>,
 
     wait_boot_time: u8,
-    addr: String,
-    port: u16,
-    key: String,
-    user: String,
+    remote: SshRemote,
     guest: GuestConf,
     qemu: QemuConf,
 }
@@ -309,34 +1040,80 @@ pub struct LinuxQemu {
 impl LinuxQemu {
     pub fn new(cfg: &Config) -> Self {
         assert_eq!(cfg.guest.os, "linux");
+        // checked by Config::check
+        let qemu = cfg.qemu.as_ref().unwrap();
 
         Self {
             handle: Option::None,
             rp: Option::None,
-            wait_boot_time: cfg.qemu.wait_boot_time.unwrap_or(15),
-            addr: LINUX_QEMU_HOST_IP_ADDR.to_string(),
-            port: 0,
-            key: cfg.ssh.key_path.clone(),
-            user: LINUX_QEMU_HOST_USER.to_string(),
+            serial: Option::None,
+            kernel_version: Option::None,
+            scratch_disks: Vec::new(),
+            serial_log: qemu.serial_log.as_ref().map(open_serial_log),
+            image_guard: qemu
+                .image_backup
+                .as_ref()
+                .map(|backup| ImageGuard::of(&qemu.image, backup)),
+            wait_boot_time: qemu.wait_boot_time.unwrap_or(15),
+            remote: SshRemote::new(cfg, LINUX_QEMU_HOST_IP_ADDR, 0, LINUX_QEMU_HOST_USER),
             guest: cfg.guest.clone(),
-            qemu: cfg.qemu.clone(),
+            qemu: qemu.clone(),
         }
     }
 }
 
 impl LinuxQemu {
-    async fn boot(&mut self) {
-        if let Some(ref mut h) = self.handle {
-            h.kill()
-                .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to kill running guest:{}", e));
+    async fn boot(&mut self) -> Result<(), GuestError> {
+        if let Some(mut h) = self.handle.take() {
+            h.kill().map_err(GuestError::KillFailed)?;
+            kill_and_reap(h);
             self.rp = None;
+            self.serial = None;
+        }
+
+        self.remote.forget_copies();
+        if let Some(guard) = self.image_guard.clone() {
+            // copying or hashing image could take seconds, keep it off the runtime threads
+            let snapshot = self.qemu.snapshot();
+            let restored = spawn_blocking(move || {
+                if snapshot {
+                    guard.check()
+                } else {
+                    guard.restore();
+                    false
+                }
+            })
+            .await
+            .unwrap();
+            if restored {
+                warn!(
+                    "Image {} was mutated in snapshot mode, restored from backup",
+                    self.qemu.image
+                );
+            }
+        }
+
+        if let (Some(conf), Some(log)) = (self.qemu.serial_log.as_ref(), self.serial_log.as_mut()) {
+            if conf.rotate_on_boot() {
+                if let Err(e) = log.rotate() {
+                    warn!("Fail to rotate serial log: {}", e);
+                }
+            }
         }
 
-        const MAX_RETRY: u8 = 64;
+        let max_retry = self.qemu.hostfwd_retry.unwrap_or(64);
         let mut retry = 0;
+        let max_boot = self.qemu.boot_retry();
+        let mut boot = 1;
+        let max_wait_time = Duration::new(self.wait_boot_time as u64 * 3, 0);
         loop {
-            let (qemu, port) = build_qemu_cli(&self.guest, &self.qemu);
-            self.port = port;
+            if self.qemu.serial() == "file" || self.qemu.serial() == "unix" {
+                // serial file of last boot is useless now
+                remove_file(serial_path(self.remote.port)).await.ok();
+            }
+            let (mut qemu, port) = build_qemu_cli(&self.guest, &self.qemu);
+            self.remote.port = port;
+            self.prepare_extra_disks(&mut qemu).await?;
 
             let (mut handle, mut rp) = {
                 let mut cmd = qemu.clone().into_cmd();
@@ -353,21 +1130,53 @@ impl LinuxQemu {
                     .stderr(wp2)
                     .kill_on_drop(true)
                     .spawn()
-                    .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to spawn qemu:{}", e));
+                    .map_err(GuestError::SpawnFailed)?;
 
                 (handle, rp)
             };
 
             let mut waited = Duration::new(0, 0);
             let wait_duration = Duration::from_millis(500);
-            let max_wait_time = Duration::new(self.wait_boot_time as u64 * 3, 0);
             let mut started = false;
+            let mut hostfwd_failed = false;
             let mut failed_reason = String::new();
+            let mut serial = None;
             loop {
                 delay_for(wait_duration).await;
                 waited += wait_duration;
-                failed_reason
-                    .push_str(String::from_utf8_lossy(&read_all_nonblock(&mut rp)).as_ref());
+                if serial.is_none() {
+                    failed_reason
+                        .push_str(String::from_utf8_lossy(&read_console(&mut rp, None)).as_ref());
+                    serial = open_serial(self.qemu.serial(), port, &failed_reason);
+                }
+                failed_reason.push_str(
+                    String::from_utf8_lossy(&read_console(&mut rp, serial.as_mut())).as_ref(),
+                );
+
+                // forward port is grabbed by others, guest will never be reachable
+                if failed_reason.contains(HOSTFWD_FAILED) {
+                    hostfwd_failed = true;
+                    handle.kill().map_err(GuestError::KillFailed)?;
+                    break;
+                }
+
+                // qemu is dead, waiting for guest or retrying with the same cli is useless
+                if let Ok(status) = timeout(Duration::new(0, 0), &mut handle).await {
+                    failed_reason.push_str(
+                        String::from_utf8_lossy(&read_console(&mut rp, serial.as_mut())).as_ref(),
+                    );
+                    // qemu exits by itself when it fails to forward port, retry with another one
+                    if failed_reason.contains(HOSTFWD_FAILED) {
+                        hostfwd_failed = true;
+                        break;
+                    }
+                    self.log_console(failed_reason.as_bytes());
+                    warn!("Qemu exited early with command: {:?}", qemu);
+                    return Err(GuestError::QemuExited {
+                        status: status.map_or_else(|e| e.to_string(), |s| s.to_string()),
+                        output: failed_reason,
+                    });
+                }
 
                 if self.is_alive().await {
                     started = true;
@@ -375,87 +1184,737 @@ impl LinuxQemu {
                 }
 
                 if waited >= max_wait_time {
-                    handle.kill().unwrap_or_else(|e| {
-                        exits!(exitcode::OSERR, "Fail to kill failed guest:{}", e)
-                    });
-                    failed_reason
-                        .push_str(String::from_utf8_lossy(&read_all_nonblock(&mut rp)).as_ref());
+                    handle.kill().map_err(GuestError::KillFailed)?;
+                    failed_reason.push_str(
+                        String::from_utf8_lossy(&read_console(&mut rp, serial.as_mut())).as_ref(),
+                    );
                     break;
                 }
             }
+            self.log_console(failed_reason.as_bytes());
 
-            if !started {
-                if !failed_reason.contains("ould not set up host forwarding rule")
-                    || retry == MAX_RETRY
-                {
-                    eprintln!("Fail to boot kernel:");
-                    eprintln!("{}", failed_reason);
-                    eprintln!("======================= Command ===========================");
-                    eprintln!("{:?}", qemu);
-                    exit(1)
-                } else {
-                    retry += 1
+            if hostfwd_failed {
+                if retry == max_retry {
+                    return Err(GuestError::HostfwdFailed { retry, port });
                 }
+                retry += 1;
+                warn!(
+                    "Forward port {} is occupied, retrying with new port ({}/{})...",
+                    port, retry, max_retry
+                );
+            } else if !started && boot < max_boot {
+                boot += 1;
+                warn!(
+                    "Guest is not reachable after {}s, rebooting ({}/{})...",
+                    max_wait_time.as_secs(),
+                    boot,
+                    max_boot
+                );
+            } else if !started {
+                warn!("Fail to boot kernel with command: {:?}", qemu);
+                return Err(GuestError::BootTimeout(failed_reason));
             } else {
+                if self.qemu.serial() != "stdio" && serial.is_none() {
+                    kill_and_reap(handle);
+                    return Err(GuestError::SerialFailed(self.qemu.serial().to_string()));
+                }
                 // clear useless data in pipe
-                read_all_nonblock(&mut rp);
+                let out = read_console(&mut rp, serial.as_mut());
+                self.log_console(&out);
                 self.handle = Some(handle);
                 self.rp = Some(rp);
+                self.serial = serial;
                 break;
             }
         }
+
+        if self.kernel_version.is_none() {
+            self.check_kernel_version().await;
+        }
+        Ok(())
+    }
+
+    async fn check_kernel_version(&mut self) {
+        self.kernel_version = self
+            .remote
+            .check_kernel_version(self.guest.kernel_version.as_ref())
+            .await;
+    }
+
+    /// Attach extra disks to qemu, recreate blank ones if zero_per_boot is set.
+    async fn prepare_extra_disks(&mut self, qemu: &mut App) -> Result<(), GuestError> {
+        for old in self.scratch_disks.drain(..) {
+            if let Err(e) = remove_file(&old).await {
+                warn!("Fail to remove old disk {}: {}", old.display(), e);
+            }
+        }
+
+        let disks = match self.qemu.extra_disks.as_ref() {
+            Some(disks) => disks,
+            None => return Ok(()),
+        };
+        for (i, disk) in disks.iter().enumerate() {
+            let path = if disk.zero_per_boot() {
+                let path =
+                    temp_dir().join(format!("healer-disk-{}-{}-{}", id(), self.remote.port, i));
+                let mut qemu_img = App::new("qemu-img");
+                qemu_img
+                    .arg(Arg::new_flag("create"))
+                    .arg(Arg::new_opt("-f", OptVal::normal(disk.format())))
+                    .arg(Arg::new_flag(path.to_str().unwrap()))
+                    .arg(Arg::new_flag(disk.size.as_ref().unwrap()));
+                let reason = match qemu_img.into_cmd().output().await {
+                    Ok(output) if output.status.success() => None,
+                    Ok(output) => Some(String::from_utf8_lossy(&output.stderr).into_owned()),
+                    Err(e) => Some(format!("fail to spawn qemu-img: {}", e)),
+                };
+                if let Some(reason) = reason {
+                    return Err(GuestError::DiskFailed {
+                        path: path.display().to_string(),
+                        reason,
+                    });
+                }
+                self.scratch_disks.push(path.clone());
+                path
+            } else {
+                PathBuf::from(&disk.path)
+            };
+
+            qemu.arg(Arg::new_opt(
+                "-drive",
+                OptVal::multiple(
+                    vec![
+                        format!("file={}", path.display()),
+                        format!("format={}", disk.format()),
+                    ],
+                    Some(','),
+                ),
+            ));
+        }
+        Ok(())
     }
 
     async fn is_alive(&self) -> bool {
-        let mut pwd = ssh_app(
-            &self.key,
-            &self.user,
-            &self.addr,
-            self.port,
-            App::new("pwd"),
-        )
-        .into_cmd();
-        pwd.stdin(std::process::Stdio::piped())
+        self.remote.is_alive().await
+    }
+
+    async fn probe_kcov(&self) -> Result<bool, String> {
+        self.remote.probe_kcov().await
+    }
+
+    async fn run_cmd_output(&self, app: App, wait: Duration) -> Result<CmdOutput, String> {
+        self.remote.run_cmd_output(app, wait).await
+    }
+
+    async fn run_cmd(&self, app: &App) -> Result<Child, GuestError> {
+        assert!(self.handle.is_some());
+        self.remote.run_cmd(app).await
+    }
+
+    async fn run_cmd_timeout(&self, app: &App, dur: Duration) -> io::Result<Output> {
+        assert!(self.handle.is_some());
+        self.remote.run_cmd_timeout(app, dur).await
+    }
+
+    async fn clear(&mut self) -> String {
+        if let Some(r) = self.rp.as_mut() {
+            let out = read_console(r, self.serial.as_mut());
+            self.log_console(&out);
+            String::from_utf8_lossy(&out).into_owned()
+        } else {
+            String::new()
+        }
+    }
+
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        self.remote.copy(path).await
+    }
+
+    pub async fn copy_force<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        self.remote.copy_force(path).await
+    }
+
+    async fn wait_exit(&mut self, duration: Duration) -> bool {
+        match self.handle.as_mut() {
+            Some(handle) => timeout(duration, handle).await.is_ok(),
+            None => true,
+        }
+    }
+
+    async fn is_crashed(&mut self) -> bool {
+        if self.qemu.crash_signal() == "exit" {
+            // panic=1 reboots guest 1s after panic, give kernel some time to dump log.
+            return self.wait_exit(Duration::new(10, 0)).await;
+        }
+
+        self.remote.is_unreachable().await
+    }
+
+    /// Run triage commands on qemu monitor, state of frozen guest stays readable there.
+    async fn triage(&self) -> Vec<TriageOutput> {
+        let path = monitor_path(self.remote.port);
+        let mut outputs = Vec::new();
+        for cmd in self.guest.triage_cmds.iter().flatten() {
+            let output = match timeout(Duration::new(5, 0), monitor_cmd(&path, cmd)).await {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => format!("Fail to run on monitor {}: {}", path.display(), e),
+                Err(_) => String::from("Time out"),
+            };
+            outputs.push(TriageOutput {
+                cmd: cmd.clone(),
+                output,
+            });
+        }
+        outputs
+    }
+
+    async fn try_collect_crash(&mut self) -> Option<Crash> {
+        assert!(self.rp.is_some());
+        // Panicked guest is gone with qemu, only its console log is left.
+        if self.wait_exit(Duration::new(30, 0)).await {
+            return Some(self.collect_crash());
+        }
+        // Qemu is still running, guest may hang without panic.
+        if self.qemu.crash_signal() == "both" && !self.is_alive().await {
+            // before nmi changes its state
+            let triage = self.triage().await;
+            let mut crash = match self.dump_hang().await {
+                Some(crash) => crash,
+                None => self.collect_crash(),
+            };
+            crash.triage = triage;
+            Some(crash)
+        } else {
+            None
+        }
+    }
+
+    /// Send `nmi` to qemu monitor, kernel booted with unknown_nmi_panic panics on it and
+    /// dumps backtrace of the hung cpu. None if disabled or guest doesn't panic in time.
+    async fn dump_hang(&mut self) -> Option<Crash> {
+        if !self.qemu.nmi_on_hang() || self.handle.is_none() {
+            return None;
+        }
+        let path = monitor_path(self.remote.port);
+        let sent = async {
+            let mut monitor = UnixStream::connect(&path).await?;
+            monitor.write_all(b"nmi\n").await
+        };
+        if let Err(e) = timeout(Duration::new(5, 0), sent)
+            .await
+            .unwrap_or_else(|e| Err(e.into()))
+        {
+            warn!("Fail to inject nmi via {}: {}", path.display(), e);
+            return None;
+        }
+        info!("Guest hung, nmi injected, waiting for backtrace...");
+        if self.wait_exit(Duration::new(30, 0)).await {
+            Some(self.collect_crash())
+        } else {
+            warn!("Guest didn't panic on nmi");
+            None
+        }
+    }
+
+    async fn shutdown(&mut self) {
+        if let Some(mut handle) = self.handle.take() {
+            if let Some(r) = self.rp.as_mut() {
+                let out = read_console(r, self.serial.as_mut());
+                self.log_console(&out);
+            }
+            if let Err(e) = handle.kill() {
+                warn!("Fail to kill qemu: {}", e);
+            }
+            // reap it, so it's gone before fuzzer exits
+            handle.await.ok();
+            self.rp = None;
+            self.serial = None;
+        }
+    }
+
+    fn collect_crash(&mut self) -> Crash {
+        // drain before killing qemu, a dump may be still streaming
+        let mut crash = read_until_idle(self.rp.as_mut().unwrap(), CRASH_IDLE);
+        if let Some(serial) = self.serial.as_mut() {
+            crash.extend(read_until_idle(serial, CRASH_IDLE));
+        }
+        if let Some(handle) = self.handle.take() {
+            kill_and_reap(handle);
+        }
+        self.log_console(&crash);
+        let crash_info = String::from_utf8_lossy(&crash).to_string();
+        self.rp = None;
+        self.serial = None;
+        Crash {
+            inner: crash_info,
+            ..Default::default()
+        }
+    }
+}
+
+impl LinuxQemu {
+    /// Append console output to serial log, if enabled.
+    fn log_console(&mut self, out: &[u8]) {
+        if let Some(log) = self.serial_log.as_mut() {
+            if let Err(e) = log.write(out) {
+                warn!("Fail to write serial log: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for LinuxQemu {
+    /// Kill qemu left by error paths, handle is already taken if qemu crashed or was shut down.
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            kill_and_reap(handle);
+        }
+    }
+}
+
+/// Kill child and wait for it on the runtime, so no zombie is left. Errors are ignored,
+/// child may be gone already. Without a runtime, it's reaped by tokio via `kill_on_drop`.
+fn kill_and_reap(mut handle: Child) {
+    handle.kill().ok();
+    if let Ok(rt) = Handle::try_current() {
+        rt.spawn(async move {
+            handle.await.ok();
+        });
+    }
+}
+
+/// Time given to real machine to dump crash to serial log after it's found unreachable
+const REAL_CRASH_WAIT: Duration = Duration::from_secs(5);
+
+/// Running machine reached over ssh. It's never spawned or killed by healer, a crashed
+/// machine is expected to reboot by itself, e.g. with `panic=N` in kernel cmdline.
+pub struct LinuxReal {
+    /// Serial log of machine, positioned at end of content read so far
+    serial: Option<File>,
+    kernel_version: Option<String>,
+    wait_boot_time: Duration,
+    remote: SshRemote,
+    guest: GuestConf,
+    real: RealConf,
+}
+
+impl LinuxReal {
+    pub fn new(cfg: &Config) -> Self {
+        assert_eq!(cfg.guest.os, "linux");
+        // checked by Config::check
+        let real = cfg.real.as_ref().unwrap();
+        let user = real.user.as_deref().unwrap_or(LINUX_QEMU_HOST_USER);
+        let mut remote = SshRemote::new(cfg, &real.addr, real.port.unwrap_or(22), user);
+        if let Some(key) = real.key_path.as_ref() {
+            remote.key = key.clone();
+        }
+
+        Self {
+            serial: None,
+            kernel_version: None,
+            wait_boot_time: Duration::from_secs(real.wait_boot_time.unwrap_or(300)),
+            remote,
+            guest: cfg.guest.clone(),
+            real: real.clone(),
+        }
+    }
+}
+
+impl LinuxReal {
+    /// Wait until machine is reachable by ssh, nothing is spawned.
+    async fn boot(&mut self) -> Result<(), GuestError> {
+        self.remote.forget_copies();
+        let wait = Duration::from_secs(1);
+        let mut waited = Duration::new(0, 0);
+        while !self.is_alive().await {
+            if waited >= self.wait_boot_time {
+                return Err(GuestError::BootTimeout(format!(
+                    "{}:{} is not reachable by ssh after {}s",
+                    self.remote.addr,
+                    self.remote.port,
+                    waited.as_secs()
+                )));
+            }
+            delay_for(wait).await;
+            waited += wait;
+        }
+
+        if self.serial.is_none() {
+            if let Some(path) = self.real.serial_log.as_ref() {
+                match File::open(path) {
+                    Ok(f) => self.serial = Some(f),
+                    Err(e) => warn!("Fail to open serial log {}: {}", path, e),
+                }
+            }
+        }
+        // console output of previous boots is useless now
+        self.read_serial();
+
+        if self.kernel_version.is_none() {
+            self.kernel_version = self
+                .remote
+                .check_kernel_version(self.guest.kernel_version.as_ref())
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn is_alive(&self) -> bool {
+        self.remote.is_alive().await
+    }
+
+    async fn probe_kcov(&self) -> Result<bool, String> {
+        self.remote.probe_kcov().await
+    }
+
+    async fn run_cmd_output(&self, app: App, wait: Duration) -> Result<CmdOutput, String> {
+        self.remote.run_cmd_output(app, wait).await
+    }
+
+    async fn run_cmd(&self, app: &App) -> Result<Child, GuestError> {
+        self.remote.run_cmd(app).await
+    }
+
+    async fn run_cmd_timeout(&self, app: &App, dur: Duration) -> io::Result<Output> {
+        self.remote.run_cmd_timeout(app, dur).await
+    }
+
+    async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        self.remote.copy(path).await
+    }
+
+    async fn copy_force<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        self.remote.copy_force(path).await
+    }
+
+    async fn clear(&mut self) -> String {
+        String::from_utf8_lossy(&self.read_serial()).into_owned()
+    }
+
+    async fn is_crashed(&mut self) -> bool {
+        self.remote.is_unreachable().await
+    }
+
+    async fn try_collect_crash(&mut self) -> Option<Crash> {
+        if !self.remote.is_unreachable().await {
+            return None;
+        }
+        delay_for(REAL_CRASH_WAIT).await;
+        Some(Crash {
+            inner: String::from_utf8_lossy(&self.read_serial()).into_owned(),
+            ..Default::default()
+        })
+    }
+
+    /// Read new content of serial log, start over if log is truncated, e.g. by rotation.
+    fn read_serial(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(f) = self.serial.as_mut() {
+            let truncated = match (f.metadata(), f.seek(SeekFrom::Current(0))) {
+                (Ok(meta), Ok(pos)) => meta.len() < pos,
+                _ => false,
+            };
+            if truncated {
+                f.seek(SeekFrom::Start(0)).ok();
+            }
+            if let Err(e) = f.read_to_end(&mut out) {
+                warn!("Fail to read serial log: {}", e);
+            }
+        }
+        out
+    }
+}
+
+/// Executor runs directly on host kernel, isolated from host only by namespaces that
+/// executor unshares for each prog (see `executor.sandbox`). Crash of kernel is crash of
+/// host, so crashes are never detected, it's only for quick iteration on disposable hosts.
+pub struct LinuxNs {
+    kernel_version: Option<String>,
+    guest: GuestConf,
+}
+
+impl LinuxNs {
+    pub fn new(cfg: &Config) -> Self {
+        assert_eq!(cfg.guest.os, "linux");
+        Self {
+            kernel_version: None,
+            guest: cfg.guest.clone(),
+        }
+    }
+}
+
+impl LinuxNs {
+    async fn boot(&mut self) -> Result<(), GuestError> {
+        if self.kernel_version.is_none() {
+            let output = self.run_cmd_output(uname_r(), Duration::new(10, 0)).await;
+            self.kernel_version = check_kernel_version(output, self.guest.kernel_version.as_ref());
+        }
+        Ok(())
+    }
+
+    async fn is_alive(&self) -> bool {
+        true
+    }
+
+    async fn probe_kcov(&self) -> Result<bool, String> {
+        Ok(Path::new(KCOV_PATH).exists())
+    }
+
+    async fn run_cmd_output(&self, app: App, wait: Duration) -> Result<CmdOutput, String> {
+        let bin = app.bin.clone();
+        let mut cmd = app.into_cmd();
+        cmd.kill_on_drop(true);
+        match timeout(wait, cmd.output()).await {
+            Err(_) => Err(String::from("Time out")),
+            Ok(Err(e)) => Err(format!("Fail to spawn {}: {}", bin, e)),
+            Ok(Ok(output)) => Ok(CmdOutput {
+                code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }),
+        }
+    }
+
+    async fn run_cmd(&self, app: &App) -> Result<Child, GuestError> {
+        app.clone()
+            .into_cmd()
+            .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped());
-        match timeout(Duration::new(10, 0), pwd.status()).await {
-            Err(_) => false,
-            Ok(status) => match status {
-                Ok(status) => status.success(),
-                Err(e) => exits!(exitcode::OSERR, "Fail to spawn detector(ssh:pwd):{}", e),
-            },
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| GuestError::CmdSpawnFailed {
+                bin: app.bin.clone(),
+                err,
+            })
+    }
+
+    async fn run_cmd_timeout(&self, app: &App, dur: Duration) -> io::Result<Output> {
+        output_timeout(app.clone().into_cmd(), dur).await
+    }
+
+    /// Files of host are visible as is.
+    async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        Ok(path.as_ref().to_path_buf())
+    }
+
+    async fn copy_force<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        self.copy(path).await
+    }
+
+    async fn clear(&mut self) -> String {
+        String::new()
+    }
+
+    async fn is_crashed(&mut self) -> bool {
+        false
+    }
+
+    async fn try_collect_crash(&mut self) -> Option<Crash> {
+        None
+    }
+}
+
+/// Ssh endpoint of guest, shared by platforms that are reached over ssh.
+struct SshRemote {
+    addr: String,
+    port: u16,
+    key: String,
+    user: String,
+    /// Ssh and scp with configured options
+    ssh: App,
+    scp: App,
+    /// Ssh of liveness probes, never multiplexed: master connection to a crashed or
+    /// rebooted guest would hang the probe until timeout
+    probe: App,
+    /// Content hash of files copied to guest since last boot
+    copied: Mutex<HashMap<PathBuf, u64>>,
+    /// Dir of control sockets if connections are multiplexed
+    control_dir: Option<PathBuf>,
+}
+
+impl Drop for SshRemote {
+    fn drop(&mut self) {
+        if let Some(dir) = self.control_dir.as_ref() {
+            std::fs::remove_dir_all(dir).ok();
         }
     }
+}
 
-    async fn run_cmd(&self, app: &App) -> Child {
-        assert!(self.handle.is_some());
+impl SshRemote {
+    fn new(cfg: &Config, addr: &str, port: u16, user: &str) -> Self {
+        let mut ssh = cfg.ssh.apply(SSH.clone());
+        let mut scp = cfg.ssh.apply(SCP.clone());
+        let probe = ssh.clone();
+        let control_dir = if cfg.ssh.multiplex() {
+            let dir = temp_dir().join(format!(
+                "healer-ssh-{}-{}",
+                id(),
+                REMOTE_NUM.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir_all(&dir).unwrap_or_else(|e| {
+                exits!(
+                    exitcode::IOERR,
+                    "Fail to create ssh control dir {}: {}",
+                    dir.display(),
+                    e
+                )
+            });
+            ssh = multiplexed(ssh, &dir);
+            scp = multiplexed(scp, &dir);
+            Some(dir)
+        } else {
+            None
+        };
+        Self {
+            addr: addr.to_string(),
+            port,
+            key: cfg.ssh.key_path.clone(),
+            user: user.to_string(),
+            ssh,
+            scp,
+            probe,
+            copied: Mutex::new(HashMap::new()),
+            control_dir,
+        }
+    }
+
+    /// Stop master connection of multiplexed ssh, control dir is removed on drop.
+    async fn close(&self) {
+        if self.control_dir.is_some() {
+            let mut exit = self.ssh.clone();
+            exit.arg(Arg::new_opt("-O", OptVal::normal("exit")))
+                .arg(Arg::new_opt("-p", OptVal::normal(&self.port.to_string())))
+                .arg(Arg::Flag(format!("{}@{}", self.user, self.addr)));
+            // master may be gone already with guest
+            output_timeout(exit.into_cmd(), Duration::new(5, 0))
+                .await
+                .ok();
+        }
+    }
+
+    /// Ssh command running `app` in guest.
+    fn app(&self, app: App) -> App {
+        ssh_app(&self.ssh, &self.key, &self.user, &self.addr, self.port, app)
+    }
+
+    /// Guest is alive if ssh probe succeeds. Failures of connection (refused, timeout) are
+    /// retried a few times, guest may refuse ssh for a moment even if it's fine.
+    async fn is_alive(&self) -> bool {
+        for i in 0..SSH_PROBE_TRIES {
+            if i != 0 {
+                delay_for(SSH_PROBE_INTERVAL).await;
+            }
+            let mut pwd = ssh_app(
+                &self.probe,
+                &self.key,
+                &self.user,
+                &self.addr,
+                self.port,
+                App::new("pwd"),
+            )
+            .into_cmd();
+            pwd.stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true);
+            match timeout(Duration::new(10, 0), pwd.status()).await {
+                Err(_) => continue,
+                Ok(Ok(status)) if status.code() == Some(SSH_CONN_ERROR) => continue,
+                // ssh connected, pwd failed for reason other than connection
+                Ok(Ok(status)) => return status.success(),
+                Ok(Err(e)) => exits!(exitcode::OSERR, "Fail to spawn detector(ssh:pwd):{}", e),
+            }
+        }
+        false
+    }
+
+    /// Guest is considered unreachable if it fails ssh probe for about 5s.
+    async fn is_unreachable(&self) -> bool {
+        let mut retry: u8 = 0;
+        loop {
+            if !self.is_alive().await {
+                return true;
+            }
+            if retry == 10 {
+                return false;
+            }
+            retry += 1;
+            delay_for(Duration::from_millis(500)).await;
+        }
+    }
 
+    async fn probe_kcov(&self) -> Result<bool, String> {
+        let mut test = App::new("test");
+        test.arg(Arg::new_flag("-e")).arg(Arg::new_flag(KCOV_PATH));
+        let output = self.run_cmd_output(test, Duration::new(10, 0)).await?;
+        match output.code {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(output.stderr),
+        }
+    }
+
+    async fn run_cmd_output(&self, app: App, wait: Duration) -> Result<CmdOutput, String> {
+        let bin = app.bin.clone();
+        let mut app = self.app(app).into_cmd();
+        app.kill_on_drop(true);
+        match timeout(wait, app.output()).await {
+            Err(_) => Err(String::from("Time out")),
+            Ok(Err(e)) => exits!(exitcode::OSERR, "Fail to spawn ssh:{}: {}", bin, e),
+            Ok(Ok(output)) => Ok(CmdOutput {
+                code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }),
+        }
+    }
+
+    async fn run_cmd(&self, app: &App) -> Result<Child, GuestError> {
         let mut app = app.clone();
-        let bin = self.copy(PathBuf::from(&app.bin)).await;
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
         app.bin = String::from(bin.to_str().unwrap());
-        let mut app = ssh_app(&self.key, &self.user, &self.addr, self.port, app).into_cmd();
-        app.stdin(std::process::Stdio::piped())
+        let mut app = self.app(app).into_cmd();
+        Ok(app
+            .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
             .kill_on_drop(true)
             .spawn()
-            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to spawn:{}", e))
+            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to spawn:{}", e)))
     }
 
-    async fn clear(&mut self) {
-        if let Some(r) = self.rp.as_mut() {
-            read_all_nonblock(r);
+    async fn run_cmd_timeout(&self, app: &App, dur: Duration) -> io::Result<Output> {
+        let mut app = app.clone();
+        let bin = self
+            .copy(PathBuf::from(&app.bin))
+            .await
+            .map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))?;
+        app.bin = String::from(bin.to_str().unwrap());
+        let app = self.app(app).into_cmd();
+        output_timeout(app, dur).await
+    }
+
+    /// Copy file to guest, skipped if the same content was copied since last boot.
+    async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
+        let path = path.as_ref();
+        let hash = content_hash(path)
+            .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to read {}: {}", path.display(), e));
+        if self.copied.lock().unwrap().get(path) == Some(&hash) {
+            return Ok(guest_path_of(path));
         }
+        let guest_path = self.copy_force(path).await?;
+        self.copied.lock().unwrap().insert(path.to_path_buf(), hash);
+        Ok(guest_path)
     }
 
-    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> PathBuf {
+    async fn copy_force<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf, GuestError> {
         let path = path.as_ref();
         assert!(path.is_file());
 
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-        let guest_path = PathBuf::from(format!("~/{}", file_name));
+        let guest_path = guest_path_of(path);
 
-        let mut scp = SCP.clone();
+        let mut scp = self.scp.clone();
         scp.arg(Arg::new_opt("-P", OptVal::normal(&self.port.to_string())))
             .arg(Arg::new_opt("-i", OptVal::normal(&self.key)))
             .arg(Arg::new_flag(path.to_str().unwrap()))
@@ -466,39 +1925,107 @@ impl LinuxQemu {
                 guest_path.display()
             )));
 
+        let failed = |reason: String| GuestError::CopyFailed {
+            path: path.display().to_string(),
+            reason,
+        };
         let output = scp
             .into_cmd()
             .output()
             .await
-            .unwrap_or_else(|e| panic!("Failed to spawn:{}", e));
-
+            .map_err(|e| failed(format!("fail to spawn scp: {}", e)))?;
         if !output.status.success() {
-            panic!(String::from_utf8(output.stderr).unwrap());
+            return Err(failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
         }
-        guest_path
+        Ok(guest_path)
     }
 
-    async fn try_collect_crash(&mut self) -> Option<Crash> {
-        assert!(self.rp.is_some());
-        match timeout(Duration::new(30, 0), self.handle.as_mut().unwrap()).await {
-            Err(_e) => {
-                if !self.is_alive().await {
-                    Some(self.collect_crash())
-                } else {
-                    None
-                }
+    /// Files copied before are gone or stale after guest reboots.
+    fn forget_copies(&self) {
+        self.copied.lock().unwrap().clear();
+    }
+
+    /// Read kernel version of guest, check it against versions supported by target.
+    async fn check_kernel_version(&self, conf: Option<&KernelVersionConf>) -> Option<String> {
+        let output = self.run_cmd_output(uname_r(), Duration::new(10, 0)).await;
+        check_kernel_version(output, conf)
+    }
+}
+
+/// Path of file in home dir of guest user.
+fn guest_path_of(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    PathBuf::from(format!("~/{}", file_name))
+}
+
+fn content_hash(path: &Path) -> io::Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    std::fs::read(path)?.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+fn uname_r() -> App {
+    let mut uname = App::new("uname");
+    uname.arg(Arg::new_flag("-r"));
+    uname
+}
+
+/// Check kernel version in output of `uname -r` against versions supported by target.
+fn check_kernel_version(
+    output: Result<CmdOutput, String>,
+    conf: Option<&KernelVersionConf>,
+) -> Option<String> {
+    let version = match output {
+        Ok(output) if output.success() => output.stdout.trim().to_string(),
+        _ => {
+            warn!("Fail to read kernel version of guest");
+            return None;
+        }
+    };
+
+    if let Some(conf) = conf {
+        if !conf.matches(&version) {
+            if conf.strict() {
+                exits!(
+                    exitcode::CONFIG,
+                    "Guest kernel version {} mismatches target, expected: {}",
+                    version,
+                    conf
+                );
             }
-            Ok(_) => Some(self.collect_crash()),
+            warn!(
+                "Guest kernel version {} mismatches target, expected: {}",
+                version, conf
+            );
         }
     }
+    Some(version)
+}
 
-    fn collect_crash(&mut self) -> Crash {
-        self.handle = None;
-        let crash = read_all_nonblock(self.rp.as_mut().unwrap());
-        let crash_info = String::from_utf8_lossy(&crash).to_string();
-        self.rp = None;
-        Crash { inner: crash_info }
-    }
+fn open_serial_log(conf: &SerialLogConf) -> RotatingFile {
+    let dir = Path::new(conf.dir());
+    std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+        exits!(
+            exitcode::IOERR,
+            "Fail to create serial log dir {}: {}",
+            dir.display(),
+            e
+        )
+    });
+    let path = dir.join(format!(
+        "guest-{}.log",
+        GUEST_NUM.fetch_add(1, Ordering::SeqCst)
+    ));
+    RotatingFile::open(&path, conf.max_size(), conf.keep()).unwrap_or_else(|e| {
+        exits!(
+            exitcode::IOERR,
+            "Fail to open serial log {}: {}",
+            path.display(),
+            e
+        )
+    })
 }
 
 fn build_qemu_cli(g: &GuestConf, q: &QemuConf) -> (App, u16) {
@@ -514,7 +2041,14 @@ fn build_qemu_cli(g: &GuestConf, q: &QemuConf) -> (App, u16) {
         free_ipv4_port().unwrap_or_else(|| exits!(exitcode::TEMPFAIL, "No Free port to forword"));
     let cfg = q;
 
-    qemu.arg(Arg::new_opt("-m", OptVal::Normal(cfg.mem_size.to_string())))
+    let serial = match cfg.serial() {
+        "file" => format!("file:{}", serial_path(port).display()),
+        "unix" => format!("unix:{},server,nowait", serial_path(port).display()),
+        backend => backend.to_string(),
+    };
+
+    qemu.arg(Arg::new_opt("-serial", OptVal::Normal(serial)))
+        .arg(Arg::new_opt("-m", OptVal::Normal(cfg.mem_size.to_string())))
         .arg(Arg::new_opt(
             "-smp",
             OptVal::Normal(cfg.cpu_num.to_string()),
@@ -530,13 +2064,205 @@ fn build_qemu_cli(g: &GuestConf, q: &QemuConf) -> (App, u16) {
                 sp: Some(','),
             },
         ))
-        .arg(Arg::new_opt("-hda", OptVal::Normal(cfg.image.clone())))
         .arg(Arg::new_opt("-kernel", OptVal::Normal(cfg.kernel.clone())));
+    if VIRTIO_DISK_TARGETS.contains(&target.as_str()) {
+        qemu.arg(Arg::new_opt(
+            "-drive",
+            OptVal::multiple(
+                vec![
+                    format!("file={}", cfg.image),
+                    "if=none".to_string(),
+                    "id=hd0".to_string(),
+                ],
+                Some(','),
+            ),
+        ))
+        .arg(Arg::new_opt(
+            "-device",
+            OptVal::multiple(vec!["virtio-blk-device", "drive=hd0"], Some(',')),
+        ));
+    } else {
+        qemu.arg(Arg::new_opt("-hda", OptVal::Normal(cfg.image.clone())));
+    }
+    if cfg.snapshot() {
+        qemu.arg(Arg::new_flag("-snapshot"));
+    }
+    if KVM_TARGETS.contains(&target.as_str()) {
+        if cfg.no_kvm() {
+            // host cpu is only available with kvm, max enables all features TCG supports
+            qemu.arg(Arg::new_opt("-cpu", OptVal::normal("max")));
+        } else {
+            qemu.arg(Arg::new_flag("-enable-kvm")).arg(Arg::new_opt(
+                "-cpu",
+                OptVal::multiple(vec!["host", "migratable=off"], Some(',')),
+            ));
+        }
+    }
+    if let Some(machine) = cfg.machine.as_ref().or_else(|| MACHINES.get(&target)) {
+        qemu.arg(Arg::new_opt("-machine", OptVal::Normal(machine.clone())));
+    }
+
+    let mut cmdline = CMDLINES[&target].clone();
+    if cfg.nmi_on_hang() || g.triage_cmds.is_some() {
+        qemu.arg(Arg::new_opt(
+            "-monitor",
+            OptVal::Normal(format!(
+                "unix:{},server,nowait",
+                monitor_path(port).display()
+            )),
+        ));
+    }
+    if cfg.nmi_on_hang() {
+        cmdline.set("unknown_nmi_panic", Some("1"));
+    }
+    if cfg.symbolize() {
+        cmdline.set("nokaslr", None);
+    }
+    if let Some(extra) = cfg.cmdline.as_ref() {
+        // checked by QemuConf::check
+        cmdline.merge(&Cmdline::parse(extra).unwrap());
+    }
+    qemu.arg(Arg::new_opt("-append", OptVal::Normal(cmdline.to_string())));
+    for extra in cfg.extra_args.iter().flatten() {
+        qemu.arg(Arg::new_flag(extra));
+    }
     (qemu, port)
 }
 
-fn ssh_app(key: &str, user: &str, addr: &str, port: u16, app: App) -> App {
-    let mut ssh = SSH.clone();
+fn serial_path(port: u16) -> PathBuf {
+    temp_dir().join(format!("healer-serial-{}-{}", id(), port))
+}
+
+/// Spawn cmd and collect its output, kill it if it doesn't exit within `dur`.
+async fn output_timeout(mut cmd: Command, dur: Duration) -> io::Result<Output> {
+    let child = cmd
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    // dropping the timed out future drops child, which kills it
+    match timeout(dur, child.wait_with_output()).await {
+        Ok(output) => output,
+        Err(_) => Err(io::Error::new(
+            ErrorKind::TimedOut,
+            format!("no exit within {}s", dur.as_secs()),
+        )),
+    }
+}
+
+fn monitor_path(port: u16) -> PathBuf {
+    temp_dir().join(format!("healer-monitor-{}-{}", id(), port))
+}
+
+/// Prompt of qemu human monitor, printed once it's ready for next command
+const MONITOR_PROMPT: &[u8] = b"(qemu) ";
+
+/// Run `cmd` on qemu human monitor listening on unix socket `path`, return its output.
+async fn monitor_cmd(path: &Path, cmd: &str) -> io::Result<String> {
+    let mut monitor = UnixStream::connect(path).await?;
+    // banner
+    read_prompt(&mut monitor).await?;
+    monitor.write_all(format!("{}\n", cmd).as_bytes()).await?;
+    let out = read_prompt(&mut monitor).await?;
+    Ok(monitor_output(&out))
+}
+
+/// Read monitor output up to next prompt.
+async fn read_prompt(monitor: &mut UnixStream) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buf = [0; 4096];
+    while !out.ends_with(MONITOR_PROMPT) {
+        let n = monitor.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "monitor closed"));
+        }
+        out.extend_from_slice(&buf[..n]);
+    }
+    out.truncate(out.len() - MONITOR_PROMPT.len());
+    Ok(out)
+}
+
+/// Output of monitor command without terminal escapes and echo of the command.
+fn monitor_output(raw: &[u8]) -> String {
+    let out = String::from_utf8_lossy(raw);
+    let out = TERM_ESCAPE.replace_all(&out, "").replace('\r', "");
+    match out.find('\n') {
+        Some(i) => out[i + 1..].to_string(),
+        None => String::new(),
+    }
+}
+
+/// Open serial backend of guest, return None if backend is stdio or not ready yet.
+fn open_serial(backend: &str, port: u16, qemu_out: &str) -> Option<SerialReader> {
+    use std::fs::{File, OpenOptions};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::net::UnixStream;
+
+    match backend {
+        "file" => File::open(serial_path(port))
+            .ok()
+            .map(|f| Box::new(f) as SerialReader),
+        "unix" => {
+            let s = UnixStream::connect(serial_path(port)).ok()?;
+            s.set_nonblocking(true).ok()?;
+            Some(Box::new(s))
+        }
+        "pty" => {
+            // qemu reports pty as: char device redirected to /dev/pts/N (label serial0)
+            const PTY_MSG: &str = "char device redirected to ";
+            let pty = qemu_out[qemu_out.find(PTY_MSG)? + PTY_MSG.len()..]
+                .split_whitespace()
+                .next()?;
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(nix::libc::O_NONBLOCK | nix::libc::O_NOCTTY)
+                .open(pty)
+                .ok()
+                .map(|f| Box::new(f) as SerialReader)
+        }
+        _ => None,
+    }
+}
+
+/// Read output of qemu and guest serial.
+fn read_console(rp: &mut PipeReader, serial: Option<&mut SerialReader>) -> Vec<u8> {
+    let mut out = read_all_nonblock(rp).unwrap_or_else(|e| {
+        warn!("Fail to read qemu output: {}", e);
+        Vec::new()
+    });
+    if let Some(serial) = serial {
+        match read_all_nonblock(serial) {
+            Ok(o) => out.extend(o),
+            Err(e) => warn!("Fail to read guest serial: {}", e),
+        }
+    }
+    out
+}
+
+/// Run shell command in guest, quoted for remote shell of ssh.
+pub fn shell(cmd: &str) -> App {
+    let mut app = App::new("sh");
+    app.arg(Arg::new_flag("-c"))
+        .arg(Arg::Flag(format!("'{}'", cmd.replace('\'', "'\\''"))));
+    app
+}
+
+/// Share one master connection per guest between ssh or scp commands with sockets in
+/// `control_dir`. Master of a dead guest is dropped after missing 2 keepalives 5s apart.
+fn multiplexed(mut app: App, control_dir: &Path) -> App {
+    // %C is hash of (host, port, user), guest on new port gets new master
+    let control_path = format!("ControlPath={}/%C", control_dir.display());
+    app.arg(Arg::new_opt("-o", OptVal::normal("ControlMaster=auto")))
+        .arg(Arg::new_opt("-o", OptVal::normal(&control_path)))
+        .arg(Arg::new_opt("-o", OptVal::normal("ControlPersist=60s")))
+        .arg(Arg::new_opt("-o", OptVal::normal("ServerAliveInterval=5")))
+        .arg(Arg::new_opt("-o", OptVal::normal("ServerAliveCountMax=2")));
+    app
+}
+
+fn ssh_app(ssh: &App, key: &str, user: &str, addr: &str, port: u16, app: App) -> App {
+    let mut ssh = ssh.clone();
     ssh.arg(Arg::new_opt("-p", OptVal::normal(&port.to_string())))
         .arg(Arg::new_opt("-i", OptVal::normal(key)))
         .arg(Arg::Flag(format!("{}@{}", user, addr)))
@@ -551,30 +2277,367 @@ fn ssh_app(key: &str, user: &str, addr: &str, port: u16, app: App) -> App {
 fn long_pipe() -> (PipeReader, PipeWriter) {
     let (rp, wp) = pipe().unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to creat pipe:{}", e));
 
+    // Larger pipe is only an optimization, keep default size if it's not supported.
     let mut sz = 128 << 10;
     while sz <= 2 << 20 {
-        fcntl(wp.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(sz));
+        if let Err(e) = fcntl(wp.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(sz)) {
+            static WARN: Once = Once::new();
+            WARN.call_once(|| warn!("Fail to enlarge pipe to {} bytes: {}", sz, e));
+            break;
+        }
         sz *= 2;
     }
 
     (rp, wp)
 }
 
-fn read_all_nonblock(rp: &mut PipeReader) -> Vec<u8> {
-    const BUF_LEN: usize = 1024 * 1024;
-    let mut result = Vec::with_capacity(BUF_LEN);
-    unsafe {
-        result.set_len(BUF_LEN);
-    }
-    match rp.read(&mut result[..]) {
-        Ok(n) => unsafe {
-            result.set_len(n);
-        },
-        Err(e) => match e.kind() {
-            ErrorKind::WouldBlock => (),
-            _ => panic!(e),
-        },
+/// Console is considered drained after no new output for this long.
+const CRASH_IDLE: Duration = Duration::from_millis(300);
+/// Upper bound of time spent on draining one console.
+const CRASH_DRAIN_MAX: Duration = Duration::from_secs(10);
+
+/// Read nonblocking reader until EOF or no new bytes for `idle`, blocking current thread.
+fn read_until_idle<R: Read + ?Sized>(rp: &mut R, idle: Duration) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    let start = std::time::Instant::now();
+    let mut last_read = start;
+    while start.elapsed() < CRASH_DRAIN_MAX {
+        match rp.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                result.extend_from_slice(&buf[..n]);
+                last_read = std::time::Instant::now();
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if last_read.elapsed() >= idle {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => {
+                warn!("Fail to read console: {}", e);
+                break;
+            }
+        }
     }
-    result.shrink_to_fit();
     result
 }
+
+/// Read what is available now, at most `MAX_DRAIN` bytes, the rest is left for next read so a
+/// flooding guest can't stall the caller. EOF and EIO (pty of exited qemu) end the stream,
+/// other errors are returned only if nothing is read, otherwise they show up again on next read.
+fn read_all_nonblock<R: Read + ?Sized>(rp: &mut R) -> io::Result<Vec<u8>> {
+    const BUF_LEN: usize = 64 * 1024;
+    const MAX_DRAIN: usize = 1024 * 1024;
+    let mut result = Vec::new();
+    let mut buf = vec![0; BUF_LEN];
+    while result.len() < MAX_DRAIN {
+        let len = BUF_LEN.min(MAX_DRAIN - result.len());
+        match rp.read(&mut buf[..len]) {
+            Ok(0) => break,
+            Ok(n) => result.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) if e.raw_os_error() == Some(nix::libc::EIO) => break,
+            Err(e) if result.is_empty() => return Err(e),
+            Err(_) => break,
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_multiplex_args() {
+        let ssh = multiplexed(SSH.clone(), Path::new("/tmp/healer-ssh"));
+        let args = ssh.iter_arg().collect::<Vec<_>>();
+        for opt in &[
+            "ControlMaster=auto",
+            "ControlPath=/tmp/healer-ssh/%C",
+            "ControlPersist=60s",
+            "ServerAliveInterval=5",
+            "ServerAliveCountMax=2",
+        ] {
+            assert!(
+                args.windows(2).any(|w| w[0] == "-o" && w[1] == *opt),
+                "{}",
+                opt
+            );
+        }
+        // options added before multiplexing, e.g. ConnectTimeout, are kept
+        assert!(args.iter().any(|a| a == "ConnectTimeout=10s"));
+
+        let probe = ssh_app(&SSH, "key", "root", "localhost", 22, App::new("pwd"));
+        assert!(!probe.iter_arg().any(|a| a.starts_with("Control")));
+    }
+
+    #[test]
+    fn read_nonblock() {
+        use std::io::Write;
+
+        let (mut rp, mut wp) = os_pipe::pipe().unwrap();
+        wp.write_all(b"crash").unwrap();
+        drop(wp);
+        assert_eq!(read_all_nonblock(&mut rp).unwrap(), b"crash");
+        assert!(read_all_nonblock(&mut rp).unwrap().is_empty());
+
+        /// Reads `data` once, then fails with `errno`.
+        struct Failing(&'static [u8], i32);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::from_raw_os_error(self.1));
+                }
+                let n = self.0.len();
+                buf[..n].copy_from_slice(self.0);
+                self.0 = &[];
+                Ok(n)
+            }
+        }
+        let eio = nix::libc::EIO;
+        let ebadf = nix::libc::EBADF;
+        assert_eq!(
+            read_all_nonblock(&mut Failing(b"panic", eio)).unwrap(),
+            b"panic"
+        );
+        assert!(read_all_nonblock(&mut Failing(b"", eio))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            read_all_nonblock(&mut Failing(b"oops", ebadf)).unwrap(),
+            b"oops"
+        );
+        assert!(read_all_nonblock(&mut Failing(b"", ebadf)).is_err());
+
+        // endless output is drained in bounded chunks
+        let mut flood = io::repeat(b'x');
+        assert_eq!(read_all_nonblock(&mut flood).unwrap().len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn kernel_version() {
+        assert_eq!(parse_kernel_version("5.8.0-rc3+"), Some(vec![5, 8, 0]));
+        assert_eq!(parse_kernel_version("4.19"), Some(vec![4, 19]));
+        assert_eq!(parse_kernel_version("linux"), None);
+
+        let conf = KernelVersionConf {
+            min: Some("5.4".to_string()),
+            max: Some("5.8".to_string()),
+            strict: None,
+        };
+        assert!(conf.matches("5.4.0-42-generic"));
+        assert!(conf.matches("5.8.0-rc3+"));
+        assert!(!conf.matches("5.9.1"));
+        assert!(!conf.matches("4.19.0"));
+    }
+
+    #[test]
+    fn qemu_cli() {
+        let qemu: QemuConf = toml::from_str(
+            r#"
+            cpu_num = 1
+            mem_size = 1024
+            image = "stretch.img"
+            kernel = "bzImage"
+            "#,
+        )
+        .unwrap();
+        for arch in ARCH.iter() {
+            let guest = GuestConf {
+                os: "linux".to_string(),
+                arch: arch.to_string(),
+                platform: "qemu".to_string(),
+                kernel_version: None,
+                triage_cmds: None,
+            };
+            let (app, _) = build_qemu_cli(&guest, &qemu);
+            let args = app.iter_arg().collect::<Vec<_>>();
+            assert!(args.iter().any(|a| a == "-append"));
+            if *arch == "arm64" {
+                assert!(args.iter().any(|a| a.contains("console=ttyAMA0")));
+                assert!(!args.iter().any(|a| a == "-hda"));
+                assert!(args
+                    .windows(2)
+                    .any(|w| w[0] == "-drive" && w[1] == "file=stretch.img,if=none,id=hd0"));
+                assert!(args
+                    .windows(2)
+                    .any(|w| w[0] == "-device" && w[1] == "virtio-blk-device,drive=hd0"));
+            }
+            if *arch == "amd64" {
+                assert!(args.iter().any(|a| a == "-enable-kvm"));
+                assert!(args
+                    .windows(2)
+                    .any(|w| w[0] == "-hda" && w[1] == "stretch.img"));
+            }
+        }
+
+        let no_kvm = QemuConf {
+            no_kvm: Some(true),
+            ..qemu
+        };
+        let guest = GuestConf {
+            os: "linux".to_string(),
+            arch: "amd64".to_string(),
+            platform: "qemu".to_string(),
+            kernel_version: None,
+            triage_cmds: None,
+        };
+        let (app, _) = build_qemu_cli(&guest, &no_kvm);
+        let args = app.iter_arg().collect::<Vec<_>>();
+        assert!(!args
+            .iter()
+            .any(|a| a == "-enable-kvm" || a.contains("host,")));
+        assert!(args.windows(2).any(|w| w[0] == "-cpu" && w[1] == "max"));
+        assert!(!args.iter().any(|a| a.contains("nokaslr")));
+
+        let symbolize = QemuConf {
+            vmlinux: Some(std::env::current_exe().unwrap().display().to_string()),
+            ..no_kvm
+        };
+        let (app, _) = build_qemu_cli(&guest, &symbolize);
+        assert!(app.iter_arg().any(|a| a.contains("nokaslr")));
+    }
+
+    #[test]
+    fn extra_args() {
+        let qemu: QemuConf = toml::from_str(
+            r#"
+            cpu_num = 1
+            mem_size = 1024
+            image = "stretch.img"
+            kernel = "bzImage"
+            extra_args = ["-device", "virtio-rng-pci", "-drive", "file=data.img,if=virtio"]
+            "#,
+        )
+        .unwrap();
+        let guest = GuestConf {
+            os: "linux".to_string(),
+            arch: "amd64".to_string(),
+            platform: "qemu".to_string(),
+            kernel_version: None,
+            triage_cmds: None,
+        };
+        let (app, _) = build_qemu_cli(&guest, &qemu);
+        let args = app.iter_arg().collect::<Vec<_>>();
+        assert_eq!(
+            args[args.len() - 4..],
+            [
+                "-device",
+                "virtio-rng-pci",
+                "-drive",
+                "file=data.img,if=virtio"
+            ]
+        );
+    }
+
+    #[test]
+    fn mem_size() {
+        let qemu: QemuConf = toml::from_str(
+            r#"
+            cpu_num = 1
+            mem_size = 512
+            image = "stretch.img"
+            kernel = "bzImage"
+            "#,
+        )
+        .unwrap();
+        assert!(qemu.check_mem(Some(512)).is_ok());
+        assert!(qemu.check_mem(None).is_ok());
+        let e = qemu.check_mem(Some(511)).unwrap_err();
+        assert!(e.contains("must not exceed 511 MB"));
+
+        let small = QemuConf {
+            mem_size: 511,
+            ..qemu
+        };
+        let e = small.check_mem(None).unwrap_err();
+        assert!(e.contains("at least 512 MB"));
+    }
+
+    #[test]
+    fn drain_console() {
+        use std::io::Write;
+
+        let (mut rp, mut wp) = pipe().unwrap();
+        fcntl(rp.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+        let writer = std::thread::spawn(move || {
+            for i in 0..3 {
+                writeln!(wp, "frame {}", i).unwrap();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            // keep pipe open, so only idle timeout stops reading
+            std::thread::sleep(Duration::from_millis(500));
+        });
+        let out = read_until_idle(&mut rp, CRASH_IDLE);
+        assert_eq!(String::from_utf8_lossy(&out), "frame 0\nframe 1\nframe 2\n");
+        writer.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn monitor_triage() {
+        use tokio::net::UnixListener;
+
+        let path = temp_dir().join(format!("healer-test-monitor-{}", id()));
+        std::fs::remove_file(&path).ok();
+        let mut listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            conn.write_all(b"QEMU 5.0.0 monitor - type 'help' for more information\r\n(qemu) ")
+                .await
+                .unwrap();
+            let mut cmd = [0; 16];
+            let n = conn.read(&mut cmd).await.unwrap();
+            assert_eq!(&cmd[..n], b"info cpus\n");
+            conn.write_all(b"i\x1b[K\x1b[Dinfo cpus\r\n* CPU #0: thread_id=42\r\n(qemu) ")
+                .await
+                .unwrap();
+        });
+        let out = monitor_cmd(&path, "info cpus").await.unwrap();
+        assert_eq!(out, "* CPU #0: thread_id=42\n");
+        server.await.unwrap();
+        assert!(monitor_cmd(&path, "info cpus").await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reap_child() {
+        let sleep = Command::new("sleep")
+            .arg("100")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let proc_path = PathBuf::from(format!("/proc/{}", sleep.id()));
+        assert!(proc_path.exists());
+        kill_and_reap(sleep);
+        // neither running nor zombie
+        for _ in 0..50 {
+            if !proc_path.exists() {
+                return;
+            }
+            delay_for(Duration::from_millis(100)).await;
+        }
+        panic!("child is not reaped");
+    }
+
+    #[tokio::test]
+    async fn cmd_timeout() {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("100");
+        let start = std::time::Instant::now();
+        let err = output_timeout(sleep, Duration::new(1, 0))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::new(5, 0));
+
+        let mut echo = Command::new("echo");
+        echo.arg("healer");
+        let output = output_timeout(echo, Duration::new(5, 0)).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "healer\n");
+    }
+}
@@ -0,0 +1,82 @@
+//! Log of every prog that first covered something new, with the coverage it found.
+//! Unlike corpus, entries are never pruned, so the whole history of discovery is kept.
+use crate::feedback::{Block, Branch};
+use crate::journal::{encode_entry, load_entries};
+use core::prog::Prog;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub const INTERESTING_LOG_PATH: &str = "./interesting";
+
+/// Prog with new coverage it found.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interesting {
+    pub p: Prog,
+    /// Seconds since unix epoch when it was found
+    pub time: u64,
+    /// Pcs of new blocks
+    pub new_blocks: Vec<usize>,
+    /// Hashes of new branches
+    pub new_branches: Vec<usize>,
+}
+
+/// Append only log of interesting progs, entries are encoded the same way as journal.
+pub struct InterestingLog {
+    file: Mutex<File>,
+}
+
+impl InterestingLog {
+    pub fn create(path: &str) -> Self {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| {
+                exits!(
+                    exitcode::IOERR,
+                    "Fail to open interesting log {}: {}",
+                    path,
+                    e
+                )
+            });
+        Self {
+            file: Mutex::new(File::from_std(file)),
+        }
+    }
+
+    pub async fn append(
+        &self,
+        p: &Prog,
+        new_blocks: &HashSet<Block>,
+        new_branches: &HashSet<Branch>,
+    ) {
+        let mut new_blocks = new_blocks.iter().map(Block::pc).collect::<Vec<_>>();
+        new_blocks.sort_unstable();
+        let mut new_branches = new_branches.iter().map(Branch::raw).collect::<Vec<_>>();
+        new_branches.sort_unstable();
+        let entry = Interesting {
+            p: p.clone(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            new_blocks,
+            new_branches,
+        };
+        let entry = encode_entry(&entry);
+
+        let mut file = self.file.lock().await;
+        file.write_all(&entry)
+            .await
+            .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to append interesting log: {}", e));
+    }
+}
+
+/// Load entries of interesting log in order.
+pub fn load(data: &[u8]) -> bincode::Result<Vec<Interesting>> {
+    load_entries(data)
+}
@@ -0,0 +1,66 @@
+//! Journal of every executed prog in order, for replaying the exact execution sequence.
+use core::prog::Prog;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub const JOURNAL_PATH: &str = "./journal";
+
+/// Append only journal, each entry is a little endian u32 length followed by bincode of prog.
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn create(path: &str) -> Self {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to open journal {}: {}", path, e));
+        Self {
+            file: Mutex::new(File::from_std(file)),
+        }
+    }
+
+    pub async fn append(&self, p: &Prog) {
+        let entry = encode_entry(p);
+        let mut file = self.file.lock().await;
+        file.write_all(&entry)
+            .await
+            .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to append journal: {}", e));
+    }
+}
+
+/// Encode value as entry of append only file, little endian u32 length followed by bincode.
+pub fn encode_entry<T: Serialize>(v: &T) -> Vec<u8> {
+    let v = bincode::serialize(v).unwrap();
+    let mut entry = Vec::with_capacity(v.len() + 4);
+    entry.extend_from_slice(&(v.len() as u32).to_le_bytes());
+    entry.extend(v);
+    entry
+}
+
+/// Load progs of journal in order. Incomplete last entry, left by killed fuzzer, is ignored.
+pub fn load(data: &[u8]) -> bincode::Result<Vec<Prog>> {
+    load_entries(data)
+}
+
+/// Load entries encoded by [`encode_entry`] in order, incomplete last entry is ignored.
+pub fn load_entries<T: DeserializeOwned>(mut data: &[u8]) -> bincode::Result<Vec<T>> {
+    let mut progs = Vec::new();
+    while data.len() >= 4 {
+        let mut len = [0; 4];
+        len.copy_from_slice(&data[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+        if data.len() < 4 + len {
+            break;
+        }
+        progs.push(bincode::deserialize(&data[4..4 + len])?);
+        data = &data[4 + len..];
+    }
+    Ok(progs)
+}
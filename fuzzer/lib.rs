@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::process::{exit, id};
 use std::sync::Arc;
 
@@ -10,7 +13,7 @@ extern crate serde;
 extern crate log;
 
 use regex::Regex;
-use tokio::fs::{create_dir_all, read};
+use tokio::fs::{create_dir_all, read, write};
 use tokio::signal::ctrl_c;
 use tokio::sync::{broadcast, Barrier};
 use tokio::time::{delay_for, Duration, Instant};
@@ -19,25 +22,39 @@ use core::prog::Prog;
 use core::target::Target;
 use fots::types::Items;
 
+use crate::control::ControlConf;
+use crate::corpus::CorpusConf;
 use crate::exec::{Executor, ExecutorConf};
-use crate::fuzzer::Fuzzer;
-use crate::guest::{GuestConf, QemuConf, SSHConf};
+use crate::fuzzer::{persisted_path, CrashMinimization, FuzzConf, Fuzzer, ReproConf};
+use crate::guest::{GuestConf, QemuConf, RealConf, SSHConf};
 #[cfg(feature = "mail")]
 use crate::mail::MailConf;
 use crate::stats::SamplerConf;
+use crate::store::{CrashStoreConf, CRASH_DIR};
 
 #[macro_use]
 #[allow(dead_code)]
 mod utils;
+mod bisect;
+mod control;
 pub mod corpus;
+pub mod crash;
 mod exec;
 pub mod feedback;
 mod fuzzer;
 mod guest;
+pub mod interesting;
+pub mod journal;
 #[cfg(feature = "mail")]
 mod mail;
+mod metrics;
+pub mod prog_io;
 pub mod report;
+mod reporter;
+mod repro;
 mod stats;
+pub mod store;
+mod symbolize;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -47,15 +64,61 @@ pub struct Config {
     pub suppressions: Option<Vec<String>>,
     pub ignores: Option<Vec<String>>,
     pub guest: GuestConf,
-    pub qemu: QemuConf,
+    /// Required by platform qemu
+    pub qemu: Option<QemuConf>,
+    /// Required by platform real
+    pub real: Option<RealConf>,
     pub ssh: SSHConf,
     pub executor: ExecutorConf,
     pub sampler: Option<SamplerConf>,
+    /// Labels of this campaign, embedded in stats and crash reports
+    pub labels: Option<HashMap<String, String>>,
+    /// Object store for crash artifacts, local crash dir is used if absent
+    pub crash_store: Option<CrashStoreConf>,
+    /// Share one corpus between all guests, true by default.
+    /// Otherwise each guest maintains its own corpus, feedback and stats.
+    pub shared_corpus: Option<bool>,
+    /// Record every executed prog to journal for replaying, false by default
+    pub journal: Option<bool>,
+    /// Archive every prog that first covers new blocks or branches, with its new coverage,
+    /// to `interesting`, false by default
+    pub interesting_log: Option<bool>,
+    /// Repeated runs of reproducer for measuring its reproduction rate
+    pub repro: Option<ReproConf>,
+    /// Size cap of corpus
+    pub corpus: Option<CorpusConf>,
+    /// Cap of candidate queue
+    pub fuzz: Option<FuzzConf>,
+    /// Format of log
+    pub log: Option<LogConf>,
+    /// Http/json api for querying stats and pausing fuzzing, disabled by default
+    pub control: Option<ControlConf>,
+    /// Minimize reproducers of saved crashes when no candidate is waiting, false by default
+    pub minimize_crashes: Option<bool>,
+    /// Store coverage fingerprint (edges before the last call) with reproduced crashes,
+    /// for clustering crashes by execution path, false by default
+    pub crash_fingerprint: Option<bool>,
+    /// Syscalls chosen more often in generation, declared or called names
+    pub priority_syscalls: Option<Vec<String>>,
+    /// Max crashes handled (reproduced and persisted) concurrently, vm num by default
+    pub crash_handling_limit: Option<usize>,
+    /// Which failed executions are recorded as failed cases, see `FAILED_POLICY`
+    pub failed_policy: Option<String>,
 
     #[cfg(feature = "mail")]
     pub mail: Option<MailConf>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogConf {
+    /// Write one json object per line instead of human readable text, false by default
+    pub json: Option<bool>,
+}
+
+/// "executor": only failures of executor itself, e.g. compile or kcov errors.
+/// "all": also progs that timed out or exited before finishing first call.
+pub const FAILED_POLICY: [&str; 2] = ["executor", "all"];
+
 impl Config {
     pub fn check(&self) {
         if !self.fots_bin.is_file() {
@@ -106,52 +169,247 @@ impl Config {
             );
             exit(exitcode::CONFIG)
         }
+        if let Some(qemu) = self.qemu.as_ref() {
+            let vcpu_num = self.vm_num * qemu.cpu_num as usize;
+            if vcpu_num > cpu_num {
+                eprintln!(
+                    "Config Warning: {} vms with {} cpus each oversubscribe {} host cpus, guests may be slow",
+                    self.vm_num, qemu.cpu_num, cpu_num
+                );
+            }
+        }
 
         if let Some(sampler) = self.sampler.as_ref() {
             sampler.check()
         }
 
+        if let Some(store) = self.crash_store.as_ref() {
+            store.check()
+        }
+
+        if let Some(repro) = self.repro.as_ref() {
+            repro.check()
+        }
+
+        if let Some(corpus) = self.corpus.as_ref() {
+            corpus.check()
+        }
+
+        if let Some(fuzz) = self.fuzz.as_ref() {
+            fuzz.check()
+        }
+
+        if let Some(control) = self.control.as_ref() {
+            control.check()
+        }
+
+        if !FAILED_POLICY.contains(&self.failed_policy()) {
+            eprintln!(
+                "Config Error: invalid failed policy {}, supported policies: {:?}",
+                self.failed_policy(),
+                FAILED_POLICY
+            );
+            exit(exitcode::CONFIG)
+        }
+
+        if self.crash_handling_limit == Some(0) {
+            eprintln!("Config Error: crash handling limit must be greater than 0");
+            exit(exitcode::CONFIG)
+        }
+
         #[cfg(feature = "mail")]
-        if let Some(mail) = mail.as_ref() {
+        if let Some(mail) = self.mail.as_ref() {
             mail.check()
         }
 
         self.guest.check();
         self.executor.check();
-        self.qemu.check();
+        match self.guest.platform.as_str() {
+            "real" => self.check_real(),
+            "ns" => (),
+            _ => self.check_qemu(),
+        }
         self.ssh.check();
     }
+
+    fn check_qemu(&self) {
+        let qemu = self.qemu.as_ref().unwrap_or_else(|| {
+            eprintln!("Config Error: qemu config is required by platform qemu");
+            exit(exitcode::CONFIG)
+        });
+        if !qemu.snapshot() && self.vm_num > 1 {
+            eprintln!(
+                "Config Error: qemu.snapshot = false requires vm num 1, otherwise image is written by multiple guests"
+            );
+            exit(exitcode::CONFIG)
+        }
+        qemu.check();
+        qemu.check_kvm(&self.guest);
+    }
+
+    fn check_real(&self) {
+        let real = self.real.as_ref().unwrap_or_else(|| {
+            eprintln!("Config Error: real config is required by platform real");
+            exit(exitcode::CONFIG)
+        });
+        if self.vm_num != 1 {
+            eprintln!("Config Error: platform real requires vm num 1, there is only one machine");
+            exit(exitcode::CONFIG)
+        }
+        if self.executor.host_ip.is_none() {
+            eprintln!(
+                "Config Error: platform real requires executor.host_ip, the address of host that machine connects to"
+            );
+            exit(exitcode::CONFIG)
+        }
+        real.check();
+    }
+
+    pub fn failed_policy(&self) -> &str {
+        self.failed_policy.as_deref().unwrap_or("executor")
+    }
+
+    pub fn crash_handling_limit(&self) -> usize {
+        self.crash_handling_limit.unwrap_or(self.vm_num)
+    }
+
+    pub fn shared_corpus(&self) -> bool {
+        self.shared_corpus.unwrap_or(true)
+    }
 }
 
 pub async fn fuzz(cfg: Config) {
     let cfg = Arc::new(cfg);
-    let (target, corpus) = tokio::join!(load_target(&cfg), load_corpus(&cfg.curpus));
-    check_corpus(&target, &corpus);
-    info!("Corpus: {}", corpus.len());
+    let target = load_target(&cfg).await;
+    let corpus = cfg.curpus.clone();
+    let corpus_len = corpus.as_ref().map_or(0, |path| {
+        scan_corpus(&target, path).unwrap_or_else(|e| {
+            exits!(
+                exitcode::CONFIG,
+                "Config Error: fail to load corpus {}: {}",
+                path.display(),
+                e
+            )
+        })
+    });
+    info!("Corpus: {}", corpus_len);
     info!(
         "Syscalls: {}  Groups: {}",
         target.fns.len(),
         target.groups.len()
     );
 
-    let fuzzer = Fuzzer::new(target, corpus, &cfg);
+    let fuzzers = if cfg.shared_corpus() {
+        let mut corpus = corpus.into_iter().collect::<Vec<_>>();
+        corpus.extend(restore_corpus(&target, &persisted_path("corpus", None)));
+        vec![Fuzzer::new(target, corpus, &cfg)]
+    } else {
+        info!("Corpus is not shared, each guest fuzzes independently");
+        let mut fuzzers = Vec::with_capacity(cfg.vm_num);
+        let fuzzer = Fuzzer::new(target, Vec::new(), &cfg);
+        for i in 0..cfg.vm_num {
+            let mut corpus = corpus.iter().cloned().collect::<Vec<_>>();
+            corpus.extend(restore_corpus(
+                &fuzzer.target,
+                &persisted_path("corpus", Some(i)),
+            ));
+            fuzzers.push(fuzzer.isolate(i, corpus));
+        }
+        fuzzers
+    };
     info!(
         "Booting {} {}/{} on {} ...",
         cfg.vm_num, cfg.guest.os, cfg.guest.arch, cfg.guest.platform
     );
+    if cfg.minimize_crashes.unwrap_or(false) {
+        restore_crash_backlog(&fuzzers[0]).await;
+    }
     let now = std::time::Instant::now();
-    let shutdown = start_fuzz(fuzzer.clone(), cfg.clone()).await;
+    let shutdown = start_fuzz(&fuzzers, cfg.clone()).await;
     info!("Boot finished, cost {}s.", now.elapsed().as_secs());
 
-    wait_exit_signal(fuzzer, shutdown).await
+    wait_exit_signal(fuzzers, shutdown).await
+}
+
+/// Find the call of crashing prog that triggers crash, by reproducing its prefixes.
+pub async fn bisect(cfg: Config, prog: PathBuf) {
+    let target = load_target(&cfg).await;
+    let p = load_prog(&target, &prog).await;
+    bisect::bisect(&cfg, &target, &p).await
+}
+
+/// Check whether crashing prog reproduces on fresh guest, crash log is saved next to the prog.
+pub async fn reproduce(cfg: Config, prog: PathBuf) {
+    let target = load_target(&cfg).await;
+    let p = load_prog(&target, &prog).await;
+    let crash = repro::reproduce(&cfg, &target, &p)
+        .await
+        .unwrap_or_else(|| exits!(exitcode::DATAERR, "Prog doesn't crash"));
+
+    let log = prog.with_extension("log");
+    write(&log, &crash.inner)
+        .await
+        .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to write {}: {}", log.display(), e));
+    println!("Crashed: {}", crash.parse().title);
+    println!("Crash log saved to {}", log.display());
+}
+
+/// Minimize crashing prog by removing calls, minimized prog is saved next to the original one.
+pub async fn minimize(cfg: Config, prog: PathBuf) {
+    let target = load_target(&cfg).await;
+    let p = load_prog(&target, &prog).await;
+    let (crash, min_p) = repro::minimize(&cfg, &target, &p)
+        .await
+        .unwrap_or_else(|| exits!(exitcode::DATAERR, "Prog doesn't crash, nothing to minimize"));
+
+    let min_prog = prog.with_extension("min.prog");
+    write(&min_prog, prog_io::dump(&min_p).unwrap())
+        .await
+        .unwrap_or_else(|e| {
+            exits!(
+                exitcode::IOERR,
+                "Fail to write {}: {}",
+                min_prog.display(),
+                e
+            )
+        });
+    println!("Crashed: {}", crash.parse().title);
+    println!("Minimized: {} -> {} calls", p.len(), min_p.len());
+    println!("{}", core::c::to_script(&min_p, &target));
+    println!("Minimized prog saved to {}", min_prog.display());
+}
+
+/// Print C statements each call of prog is translated to, nothing is executed.
+pub async fn translate(cfg: Config, prog: PathBuf) {
+    let target = load_target(&cfg).await;
+    let p = load_prog(&target, &prog).await;
+    for stmts in core::c::iter_trans(&p, &target) {
+        print!("{}", stmts);
+    }
+}
+
+async fn load_prog(target: &Target, prog: &PathBuf) -> Prog {
+    let p = read(prog)
+        .await
+        .unwrap_or_else(|e| exits!(exitcode::NOINPUT, "Fail to read {}: {}", prog.display(), e));
+    let p: Prog = prog_io::load(&p).unwrap_or_else(|e| {
+        exits!(
+            exitcode::DATAERR,
+            "Fail to deserialize {}: {}",
+            prog.display(),
+            e
+        )
+    });
+    check_corpus(target, std::slice::from_ref(&p));
+    p
 }
 
-async fn start_fuzz(fuzzer: Fuzzer, cfg: Arc<Config>) -> broadcast::Sender<()> {
-    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+async fn start_fuzz(fuzzers: &[Fuzzer], cfg: Arc<Config>) -> broadcast::Sender<()> {
+    let (shutdown_tx, _) = broadcast::channel(1);
     let barrier = Arc::new(Barrier::new(cfg.vm_num + 1));
-    for _ in 0..cfg.vm_num {
+    for i in 0..cfg.vm_num {
         let cfg = cfg.clone();
-        let fuzzer = fuzzer.clone();
+        let fuzzer = fuzzers[i % fuzzers.len()].clone();
         let barrier = barrier.clone();
         let shutdown = shutdown_tx.subscribe();
 
@@ -164,15 +422,37 @@ async fn start_fuzz(fuzzer: Fuzzer, cfg: Arc<Config>) -> broadcast::Sender<()> {
     }
     barrier.wait().await;
 
-    let stats_source = fuzzer.stats();
-    tokio::spawn(async move {
-        let mut sampler = stats::Sampler::new(stats_source);
-        sampler.sample(&cfg.sampler, shutdown_rx).await;
-    });
+    let labels = cfg.labels.clone().unwrap_or_default();
+    let mut latest = Vec::with_capacity(fuzzers.len());
+    for fuzzer in fuzzers {
+        let reporters =
+            reporter::reporters(&cfg.sampler.as_ref().and_then(|s| s.reporters.clone()));
+        let mut sampler = stats::Sampler::new(fuzzer.stats(), labels.clone(), reporters);
+        latest.push(sampler.latest());
+        let cfg = cfg.clone();
+        let shutdown = shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            sampler.sample(&cfg.sampler, shutdown).await;
+        });
+    }
+    if let Some(port) = cfg.sampler.as_ref().and_then(|s| s.prometheus_port) {
+        let shutdown = shutdown_tx.subscribe();
+        tokio::spawn(metrics::serve(port, latest.clone(), labels, shutdown));
+    }
+    if let Some(control) = cfg.control.as_ref() {
+        let shutdown = shutdown_tx.subscribe();
+        tokio::spawn(control::serve(
+            control.listen.clone(),
+            latest,
+            fuzzers[0].record.clone(),
+            fuzzers[0].paused.clone(),
+            shutdown,
+        ));
+    }
     shutdown_tx
 }
 
-async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
+async fn wait_exit_signal(fuzzers: Vec<Fuzzer>, shutdown: broadcast::Sender<()>) {
     if cfg!(unix) {
         use tokio::signal::unix::{signal, SignalKind};
         let mut sig_ir =
@@ -198,7 +478,9 @@ async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
 
     warn!("Stopping, persisting data...");
     shutdown.send(()).unwrap();
-    fuzzer.persist().await;
+    for fuzzer in fuzzers {
+        fuzzer.persist().await;
+    }
 
     let now = Instant::now();
     let wait_time = Duration::new(5, 0);
@@ -210,31 +492,71 @@ async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
         }
     }
     info!("All done");
-    // TODO clear resources when exiting, e.g. qemu process.
     exit(exitcode::OK);
 }
 
 fn check_corpus(t: &Target, corpus: &[Prog]) {
-    for p in corpus.iter() {
-        if !t.groups.contains_key(&p.gid) {
-            eprintln!("Config Error: fots_bin/corpus not match: corpus contains unknown groups");
-            exit(1);
-        }
-        for c in p.calls.iter() {
-            if !t.fns.contains_key(&c.fid) {
-                eprintln!("Config Error: fots_bin/corpus not match: corpus contains unknown fn");
-                exit(1);
-            }
+    if let Err(e) = corpus.iter().try_for_each(|p| match_prog(t, p)) {
+        eprintln!("Config Error: fots_bin/corpus not match: {}", e);
+        exit(1);
+    }
+}
+
+fn match_prog(t: &Target, p: &Prog) -> Result<(), &'static str> {
+    if !t.groups.contains_key(&p.gid) {
+        return Err("corpus contains unknown groups");
+    }
+    if p.calls.iter().any(|c| !t.fns.contains_key(&c.fid)) {
+        return Err("corpus contains unknown fn");
+    }
+    Ok(())
+}
+
+/// Count progs of corpus file, checking that each of them is decodable and matches target.
+/// Progs are decoded one at a time, fuzzer streams them again as candidates.
+fn scan_corpus(t: &Target, path: &Path) -> Result<usize, String> {
+    let f = File::open(path).map_err(|e| e.to_string())?;
+    let mut n = 0;
+    for p in prog_io::stream(BufReader::new(f)).map_err(|e| e.to_string())? {
+        match_prog(t, &p.map_err(|e| e.to_string())?)?;
+        n += 1;
+    }
+    Ok(n)
+}
+
+/// Queue saved crashes whose reproducer is not minimized yet for minimization,
+/// backlog is shared by fuzzers of all guests.
+async fn restore_crash_backlog(fuzzer: &Fuzzer) {
+    let mut n = 0;
+    for (p, case) in report::unminimized_crashes(Path::new(CRASH_DIR)) {
+        if p.len() > 1 && match_prog(&fuzzer.target, &p).is_ok() {
+            fuzzer
+                .crash_backlog
+                .push(CrashMinimization::new(p, case))
+                .await;
+            n += 1;
         }
     }
+    if n != 0 {
+        info!("{} saved crashes are waiting for minimization", n);
+    }
 }
 
-async fn load_corpus(path: &Option<PathBuf>) -> Vec<Prog> {
-    if let Some(path) = path.as_ref() {
-        let data = read(path).await.unwrap();
-        bincode::deserialize(&data).unwrap()
-    } else {
-        Vec::new()
+/// Corpus persisted in work dir by previous run, ignored with a warning if it doesn't fit
+/// this run, i.e. it's of another format version or doesn't match fots_bin.
+fn restore_corpus(target: &Target, path: &str) -> Option<PathBuf> {
+    if !Path::new(path).exists() {
+        return None;
+    }
+    match scan_corpus(target, Path::new(path)) {
+        Ok(n) => {
+            info!("Restored {} progs from {}", n, path);
+            Some(PathBuf::from(path))
+        }
+        Err(e) => {
+            warn!("Ignoring persisted corpus {}: {}", path, e);
+            None
+        }
     }
 }
 
@@ -247,39 +569,47 @@ async fn load_target(cfg: &Config) -> Target {
     Target::from(items)
 }
 
-pub async fn prepare_env() {
-    init_logger();
+pub async fn prepare_env(cfg: &Config) {
+    init_logger(cfg.log.as_ref().and_then(|l| l.json).unwrap_or(false));
     let pid = id(); // pid
     std::env::set_var("HEALER_FUZZER_PID", format!("{}", pid));
     info!("Pid: {}", pid);
 
     use tokio::io::ErrorKind::*;
-    if let Err(e) = create_dir_all("./crashes").await {
+    if let Err(e) = create_dir_all(CRASH_DIR).await {
         if e.kind() != AlreadyExists {
             exits!(exitcode::IOERR, "Fail to create crash dir: {}", e);
         }
     }
 }
 
-fn init_logger() {
+/// Init logger, with `json` every record is a json object of time, level, target, message
+/// and fields in `mdc`, one per line.
+fn init_logger(json: bool) {
     use log::LevelFilter;
     use log4rs::append::console::ConsoleAppender;
     use log4rs::append::file::FileAppender;
     use log4rs::append::rolling_file::policy::compound::{roll, trigger, CompoundPolicy};
     use log4rs::append::rolling_file::RollingFileAppender;
     use log4rs::config::{Appender, Config, Logger, Root};
+    use log4rs::encode::json::JsonEncoder;
     use log4rs::encode::pattern::PatternEncoder;
+    use log4rs::encode::Encode;
+
+    let encoder = |pattern: &str| -> Box<dyn Encode> {
+        if json {
+            Box::new(JsonEncoder::new())
+        } else {
+            Box::new(PatternEncoder::new(pattern))
+        }
+    };
 
     let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} {t} - {m}{n}",
-        )))
+        .encoder(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} {t} - {m}{n}"))
         .build();
 
     let fuzzer_appender = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}",
-        )))
+        .encoder(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}"))
         .build("log/fuzzer.log")
         .unwrap();
 
@@ -290,9 +620,7 @@ fn init_logger() {
     let stats_policy = CompoundPolicy::new(Box::new(stats_trigger), Box::new(stats_roll));
     let stats_appender = RollingFileAppender::builder()
         .append(false)
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}",
-        )))
+        .encoder(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}"))
         .build("log/stats.log", Box::new(stats_policy))
         .unwrap();
 
@@ -5,6 +5,7 @@ use lettre_email::EmailBuilder;
 use native_tls::TlsConnector;
 use serde::Deserialize;
 use std::env;
+use std::process::exit;
 use std::sync::Once;
 use tokio::sync::Mutex;
 
@@ -12,50 +13,36 @@ static mut MAILER: Option<Mutex<SmtpTransport>> = None;
 static mut ENVELOPE: Option<Envelope> = None;
 static ONCE: Once = Once::new();
 
+/// Port of smtps, tls is set up before smtp instead of by STARTTLS
+const SMTPS_PORT: u16 = 465;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct MailConf {
-    pub sender: String,
-    pub receivers: Vec<String>,
+    /// Host of smtp server
+    pub server: String,
+    /// 587 by default with tls, 25 otherwise
+    pub port: Option<u16>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Login without auth if absent, e.g. for local relay
+    pub username: Option<String>,
+    /// Password of username, `${VAR}` is read from env var VAR
+    pub password: Option<String>,
+    /// Require tls, STARTTLS or smtps on port 465, true by default
+    pub tls: Option<bool>,
 }
 
 impl MailConf {
     pub fn check(&self) {
         ONCE.call_once(|| {
-            let passwd = env::var("HEALER_MAIL_PASSWD").unwrap_or_else(|_| {
-                eprintln!("Config Error: HEALER_MAIL_PASSWD env not found");
+            let mailer = self.transport().unwrap_or_else(|e| {
+                eprintln!("Config Error: invalid mail conf: {}", e);
                 exit(exitcode::CONFIG)
             });
-
-            let creds = Credentials::new(self.sender.clone(), passwd);
-            let tls = TlsConnector::builder();
-            let param =
-                ClientTlsParameters::new("smtp-mail.outlook.com".into(), tls.build().unwrap());
-            let mailer = SmtpClient::new(
-                ("smtp-mail.outlook.com", 587),
-                ClientSecurity::Required(param),
-            )
-            .unwrap()
-            .credentials(creds)
-            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
-            .smtp_utf8(true)
-            .transport();
-
-            let sender_addr = EmailAddress::new(self.sender.clone()).unwrap_or_else(|e| {
-                eprintln!("Config Error: invalid sender addr {}: {}", self.sender, e);
+            let envelope = self.envelope().unwrap_or_else(|e| {
+                eprintln!("Config Error: invalid mail conf: {}", e);
                 exit(exitcode::CONFIG)
             });
-            let recivers = self
-                .receivers
-                .iter()
-                .map(|r| {
-                    EmailAddress::new(r.clone()).unwrap_or_else(|e| {
-                        eprintln!("Config Error: invalid reciver addr {}: {}", self.sender, e);
-                        exit(exitcode::CONFIG)
-                    })
-                })
-                .collect();
-
-            let envelope = Envelope::new(Some(sender_addr), recivers).unwrap();
 
             unsafe {
                 MAILER = Some(Mutex::new(mailer));
@@ -63,16 +50,101 @@ impl MailConf {
             }
         })
     }
+
+    pub fn tls(&self) -> bool {
+        self.tls.unwrap_or(true)
+    }
+
+    pub fn port(&self) -> u16 {
+        match self.port {
+            Some(port) => port,
+            None if self.tls() => 587,
+            None => 25,
+        }
+    }
+
+    fn transport(&self) -> Result<SmtpTransport, String> {
+        if self.server.trim().is_empty() {
+            return Err(String::from("empty smtp server"));
+        }
+        let security = if self.tls() {
+            let tls = TlsConnector::builder()
+                .build()
+                .map_err(|e| format!("tls: {}", e))?;
+            let param = ClientTlsParameters::new(self.server.clone(), tls);
+            if self.port() == SMTPS_PORT {
+                ClientSecurity::Wrapper(param)
+            } else {
+                ClientSecurity::Required(param)
+            }
+        } else {
+            ClientSecurity::None
+        };
+        let mut client = SmtpClient::new((self.server.as_str(), self.port()), security)
+            .map_err(|e| format!("{}:{}: {}", self.server, self.port(), e))?
+            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
+            .smtp_utf8(true);
+        if let Some(username) = self.username.as_ref() {
+            let password = self
+                .password
+                .as_deref()
+                .ok_or_else(|| format!("no password of {}", username))
+                .and_then(expand_env)?;
+            client = client.credentials(Credentials::new(username.clone(), password));
+        } else if self.password.is_some() {
+            return Err(String::from("password without username"));
+        }
+        Ok(client.transport())
+    }
+
+    fn envelope(&self) -> Result<Envelope, String> {
+        let from = EmailAddress::new(self.from.clone())
+            .map_err(|e| format!("invalid from addr {}: {}", self.from, e))?;
+        let to = self
+            .to
+            .iter()
+            .map(|r| {
+                EmailAddress::new(r.clone()).map_err(|e| format!("invalid to addr {}: {}", r, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Envelope::new(Some(from), to).map_err(|e| e.to_string())
+    }
 }
 
-pub async fn send(mail: EmailBuilder) {
+/// Value of env var VAR if `val` is `${VAR}`, `val` itself otherwise.
+fn expand_env(val: &str) -> Result<String, String> {
+    if val.starts_with("${") && val.ends_with('}') {
+        let var = &val[2..val.len() - 1];
+        env::var(var).map_err(|_| format!("{} env not found", var))
+    } else {
+        Ok(val.to_string())
+    }
+}
+
+/// Send mail, nothing is sent if mail is not configured.
+pub async fn try_send(mail: EmailBuilder) -> Result<(), String> {
     unsafe {
         if let (Some(mailer), Some(envelope)) = (MAILER.as_ref(), ENVELOPE.as_ref()) {
-            let mail = mail.envelope(envelope.clone()).build().unwrap();
-            {
-                let mut mailer = mailer.lock().await;
-                mailer.send(mail.into()).unwrap();
-            }
+            let mail = mail
+                .envelope(envelope.clone())
+                .build()
+                .map_err(|e| e.to_string())?;
+            let mut mailer = mailer.lock().await;
+            mailer.send(mail.into()).map_err(|e| e.to_string())?;
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_password() {
+        env::set_var("HEALER_TEST_MAIL_PASSWD", "secret");
+        assert_eq!(expand_env("${HEALER_TEST_MAIL_PASSWD}").unwrap(), "secret");
+        assert_eq!(expand_env("plain").unwrap(), "plain");
+        assert!(expand_env("${HEALER_TEST_NO_SUCH_VAR}").is_err());
+    }
 }
@@ -1,4 +1,4 @@
-use fuzzer::{fuzz, prepare_env, show_info, Config};
+use fuzzer::{bisect, fuzz, minimize, prepare_env, reproduce, show_info, translate, Config};
 use std::path::PathBuf;
 use std::process::exit;
 use structopt::StructOpt;
@@ -9,6 +9,35 @@ use tokio::fs::read_to_string;
 struct Settings {
     #[structopt(short = "c", long = "config", default_value = "healer-fuzzer.toml")]
     config: PathBuf,
+    /// Allow platform ns, which runs progs on kernel of this host and may crash it
+    #[structopt(long)]
+    unsafe_host: bool,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Find the call of crashing prog that triggers crash, by reproducing its prefixes
+    Bisect {
+        /// Prog serialized with bincode
+        prog: PathBuf,
+    },
+    /// Run crashing prog on fresh guest a few times, save crash log next to it on first crash
+    Reproduce {
+        /// Prog serialized with bincode
+        prog: PathBuf,
+    },
+    /// Remove calls of crashing prog that are not needed for crash, save result next to it
+    Minimize {
+        /// Prog serialized with bincode
+        prog: PathBuf,
+    },
+    /// Print C statements prog is translated to without executing it, no guest is booted
+    Translate {
+        /// Prog serialized with bincode
+        prog: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -28,8 +57,23 @@ async fn main() {
         exit(exitcode::CONFIG);
     });
 
+    if let Some(Command::Translate { prog }) = settings.cmd {
+        return translate(conf, prog).await;
+    }
+    if conf.guest.platform == "ns" && !settings.unsafe_host {
+        eprintln!(
+            "Config Error: platform ns runs progs on host kernel, pass --unsafe-host to allow it"
+        );
+        exit(exitcode::CONFIG);
+    }
     conf.check();
     show_info();
-    prepare_env().await;
-    fuzz(conf).await
+    prepare_env(&conf).await;
+    match settings.cmd {
+        Some(Command::Bisect { prog }) => bisect(conf, prog).await,
+        Some(Command::Reproduce { prog }) => reproduce(conf, prog).await,
+        Some(Command::Minimize { prog }) => minimize(conf, prog).await,
+        Some(Command::Translate { .. }) => unreachable!(),
+        None => fuzz(conf).await,
+    }
 }
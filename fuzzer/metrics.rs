@@ -0,0 +1,215 @@
+//! Prometheus endpoint exposing latest stats of each sampler.
+use crate::stats::Stats;
+use crate::utils::wait_stop;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::{timeout, Duration};
+
+/// Latest stats of one sampler, with id of its guest if corpus is not shared.
+pub type SharedStats = (Option<usize>, Arc<RwLock<Stats>>);
+
+/// (name, type, help) of exported metrics, in order of `values`.
+const METRICS: [(&str, &str, &str); 13] = [
+    ("healer_corpus", "gauge", "Progs in corpus"),
+    ("healer_blocks", "gauge", "Covered blocks"),
+    ("healer_branches", "gauge", "Covered branches"),
+    (
+        "healer_new_blocks",
+        "gauge",
+        "Blocks first covered since last sample",
+    ),
+    (
+        "healer_new_branches",
+        "gauge",
+        "Branches first covered since last sample",
+    ),
+    ("healer_candidates", "gauge", "Progs waiting for execution"),
+    ("healer_exec_total", "counter", "Executed progs"),
+    ("healer_normal_cases_total", "counter", "Normal test cases"),
+    ("healer_failed_cases_total", "counter", "Failed test cases"),
+    ("healer_crashed_cases_total", "counter", "Crashes"),
+    (
+        "healer_unique_crashes_total",
+        "counter",
+        "Crashes with distinct signatures",
+    ),
+    (
+        "healer_oom_total",
+        "counter",
+        "Executions killed by OOM killer",
+    ),
+    (
+        "healer_truncated_total",
+        "counter",
+        "Executions with truncated coverage",
+    ),
+];
+
+/// Max time of reading request and writing response of one connection
+const CONN_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn values(s: &Stats) -> [usize; 13] {
+    [
+        s.corpus,
+        s.blocks,
+        s.branches,
+        s.new_blocks,
+        s.new_branches,
+        s.candidates,
+        s.exec,
+        s.normal_case,
+        s.failed_case,
+        s.crashed_case,
+        s.unique_crash,
+        s.oom,
+        s.truncated,
+    ]
+}
+
+/// Render stats in Prometheus text format, campaign labels are attached to every sample.
+pub fn render(stats: &[(Option<usize>, Stats)], labels: &HashMap<String, String>) -> String {
+    let mut labels = labels
+        .iter()
+        .map(|(k, v)| (label_name(k), escape(v)))
+        .collect::<Vec<_>>();
+    labels.sort();
+
+    let mut out = String::new();
+    for (i, (name, kind, help)) in METRICS.iter().enumerate() {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        for (guest, s) in stats {
+            let mut l = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, v))
+                .collect::<Vec<_>>();
+            if let Some(id) = guest {
+                l.push(format!("guest=\"{}\"", id));
+            }
+            if l.is_empty() {
+                writeln!(out, "{} {}", name, values(s)[i]).unwrap();
+            } else {
+                writeln!(out, "{}{{{}}} {}", name, l.join(","), values(s)[i]).unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// Serve `/metrics` on `port` until shutdown.
+pub async fn serve(
+    port: u16,
+    stats: Vec<SharedStats>,
+    labels: HashMap<String, String>,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let mut listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .unwrap_or_else(|e| {
+            exits!(
+                exitcode::UNAVAILABLE,
+                "Fail to serve metrics on port {}: {}",
+                port,
+                e
+            )
+        });
+    info!("Serving metrics on port {}", port);
+    let accept = async {
+        loop {
+            let conn = match listener.accept().await {
+                Ok((conn, _)) => conn,
+                Err(e) => {
+                    warn!("Fail to accept metrics connection: {}", e);
+                    continue;
+                }
+            };
+            let mut latest = Vec::with_capacity(stats.len());
+            for (guest, s) in stats.iter() {
+                latest.push((*guest, s.read().await.clone()));
+            }
+            let body = render(&latest, &labels);
+            tokio::spawn(async move {
+                if let Err(e) = timeout(CONN_TIMEOUT, respond(conn, body)).await {
+                    warn!("Metrics connection: {}", e);
+                }
+            });
+        }
+    };
+    tokio::select! {
+        _ = wait_stop(&mut shutdown) => (),
+        _ = accept => (),
+    }
+}
+
+async fn respond(mut conn: TcpStream, body: String) {
+    let mut req = vec![0; 1024];
+    let n = match conn.read(&mut req).await {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let req = String::from_utf8_lossy(&req[..n]);
+    let path = req.lines().next().and_then(|l| {
+        let mut parts = l.split_whitespace();
+        match parts.next() {
+            Some("GET") => parts.next(),
+            _ => None,
+        }
+    });
+    let resp = match path {
+        Some("/metrics") => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+        _ => String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    };
+    let _ = conn.write_all(resp.as_bytes()).await;
+}
+
+/// Label name with chars other than [a-zA-Z0-9_] replaced by '_'.
+fn label_name(name: &str) -> String {
+    let mut name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_stats() {
+        let stats = Stats {
+            corpus: 3,
+            exec: 42,
+            ..Default::default()
+        };
+        let mut labels = HashMap::new();
+        labels.insert(String::from("kernel-version"), String::from("5.8 \"rc\""));
+        let out = render(&[(Some(1), stats)], &labels);
+        assert!(out.contains("# TYPE healer_exec_total counter\n"));
+        assert!(out.contains("healer_corpus{kernel_version=\"5.8 \\\"rc\\\"\",guest=\"1\"} 3\n"));
+        assert!(
+            out.contains("healer_exec_total{kernel_version=\"5.8 \\\"rc\\\"\",guest=\"1\"} 42\n")
+        );
+
+        let out = render(&[(None, Stats::default())], &HashMap::new());
+        assert!(out.contains("healer_blocks 0\n"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Versioned encoding of progs persisted to files, e.g. corpus and crash reproducers.
+//! Files without version are from before versioning and decoded as version 0.
+use core::prog::Prog;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::BufRead;
+use thiserror::Error;
+
+/// Tag of versioned file, followed by version and encoded progs.
+const MAGIC: [u8; 4] = *b"HLPG";
+/// Current version, bump it when encoding of `Prog` changes and add a migration to `decode`.
+pub const PROG_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ProgIoError {
+    #[error(
+        "Unknown prog version {0}, latest supported version is {}",
+        PROG_VERSION
+    )]
+    UnknownVersion(u32),
+    #[error("Malformed prog (version {0}): {1}")]
+    Malformed(u32, bincode::Error),
+}
+
+/// Encode `Prog` or progs with current version.
+pub fn dump<T: Serialize>(v: &T) -> bincode::Result<Vec<u8>> {
+    let mut data = bincode::serialize(&(MAGIC, PROG_VERSION))?;
+    bincode::serialize_into(&mut data, v)?;
+    Ok(data)
+}
+
+/// Decode `Prog` or progs of any known version.
+pub fn load<T: DeserializeOwned>(data: &[u8]) -> Result<T, ProgIoError> {
+    match data.get(..MAGIC.len()) {
+        Some(magic) if magic == MAGIC => {
+            let header = MAGIC.len() + std::mem::size_of::<u32>();
+            let version: u32 = bincode::deserialize(&data[MAGIC.len()..])
+                .map_err(|e| ProgIoError::Malformed(0, e))?;
+            decode(version, &data[header..])
+        }
+        _ => decode(0, data),
+    }
+}
+
+/// Decode dumped progs one at a time from `r`, so that they needn't be in memory together.
+/// Error of header is returned at once, error of a prog ends the stream.
+pub fn stream<R: BufRead>(mut r: R) -> Result<ProgStream<R>, ProgIoError> {
+    let versioned = matches!(r.fill_buf(), Ok(buf) if buf.starts_with(&MAGIC));
+    let version = if versioned {
+        r.consume(MAGIC.len());
+        bincode::deserialize_from(&mut r).map_err(|e| ProgIoError::Malformed(0, e))?
+    } else {
+        0
+    };
+    match version {
+        0 | 1 => (),
+        _ => return Err(ProgIoError::UnknownVersion(version)),
+    }
+    let left: u64 =
+        bincode::deserialize_from(&mut r).map_err(|e| ProgIoError::Malformed(version, e))?;
+    Ok(ProgStream { r, version, left })
+}
+
+/// Progs decoded lazily, see `stream`.
+pub struct ProgStream<R> {
+    r: R,
+    version: u32,
+    /// Progs not decoded yet
+    left: u64,
+}
+
+impl<R: BufRead> Iterator for ProgStream<R> {
+    type Item = Result<Prog, ProgIoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let p = bincode::deserialize_from(&mut self.r)
+            .map_err(|e| ProgIoError::Malformed(self.version, e));
+        if p.is_err() {
+            self.left = 0;
+        }
+        Some(p)
+    }
+}
+
+fn decode<T: DeserializeOwned>(version: u32, body: &[u8]) -> Result<T, ProgIoError> {
+    match version {
+        // version 0 is the unversioned encoding, same as version 1
+        0 | 1 => bincode::deserialize(body).map_err(|e| ProgIoError::Malformed(version, e)),
+        _ => Err(ProgIoError::UnknownVersion(version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::prog::Call;
+
+    #[test]
+    fn versions() {
+        let mut p = Prog::new(0);
+        p.add_call(Call::new(1));
+
+        let data = dump(&p).unwrap();
+        assert_eq!(load::<Prog>(&data).unwrap(), p);
+        // unversioned prog
+        assert_eq!(load::<Prog>(&bincode::serialize(&p).unwrap()).unwrap(), p);
+
+        let mut newer = data;
+        newer[4..8].copy_from_slice(&(PROG_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            load::<Prog>(&newer),
+            Err(ProgIoError::UnknownVersion(v)) if v == PROG_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn stream_progs() {
+        let progs = (1..4)
+            .map(|fid| {
+                let mut p = Prog::new(0);
+                p.add_call(Call::new(fid));
+                p
+            })
+            .collect::<Vec<_>>();
+        for data in [dump(&progs).unwrap(), bincode::serialize(&progs).unwrap()].iter() {
+            let streamed = stream(data.as_slice())
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(streamed, progs);
+        }
+
+        let data = dump(&progs).unwrap();
+        let truncated = stream(&data[..data.len() - 1]).unwrap().collect::<Vec<_>>();
+        assert_eq!(truncated.len(), 3);
+        assert!(truncated[2].is_err());
+    }
+}
@@ -1,7 +1,10 @@
+use crate::crash::{CrashDedup, CrashMeta, CrashReport};
 use crate::feedback::{Block, Branch};
 use crate::guest::Crash;
-#[cfg(feature = "mail")]
-use crate::mail;
+use crate::prog_io;
+use crate::reporter::{notify_all, Reporter};
+use crate::store::{CrashStore, LocalStore, CRASH_DIR};
+use crate::symbolize::Symbolizer;
 use chrono::prelude::*;
 use chrono::DateTime;
 use circular_queue::CircularQueue;
@@ -9,25 +12,39 @@ use core::c::to_script;
 use core::prog::Prog;
 use core::target::Target;
 use executor::Reason;
-#[cfg(feature = "mail")]
-use lettre_email::EmailBuilder;
 use serde::Serialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs::{read, read_dir};
+use std::path::Path;
 use std::sync::Arc;
 use tokio::fs::write;
 use tokio::sync::Mutex;
 
+/// Name of meta of crash signature, see `CrashMeta`.
+pub const CRASH_META: &str = "meta.json";
+/// Name of raw log of crash in dir of its signature.
+pub const CRASH_LOG: &str = "report.txt";
+/// Name of prog in json in dir of crash signature.
+pub const CRASH_PROG_JSON: &str = "prog.json";
+
 pub struct TestCaseRecord {
     normal: Mutex<CircularQueue<ExecutedCase>>,
     failed: Mutex<CircularQueue<FailedCase>>,
     crash: Mutex<CircularQueue<CrashedCase>>,
 
     target: Arc<Target>,
+    labels: HashMap<String, String>,
+    store: Arc<dyn CrashStore>,
+    /// Channels new crashes are notified to
+    reporters: Vec<Box<dyn Reporter>>,
+    /// Resolves raw addresses of crash reports, None if not running on qemu
+    symbolizer: Option<Arc<Symbolizer>>,
     id_n: Mutex<usize>,
 
     normal_num: Mutex<usize>,
     failed_num: Mutex<usize>,
     crashed_num: Mutex<usize>,
+    dedup: Mutex<CrashDedup>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -65,21 +82,127 @@ pub struct CrashedCase {
     pub p: String,
     pub repo: bool,
     pub crash: Crash,
+    /// Parsed form of crash, None in reports saved before it was introduced
+    #[serde(default)]
+    pub report: Option<CrashReport>,
+    /// Labels of campaign
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Whether crash reproduces on verify kernel, None if not verified
+    #[serde(default)]
+    pub verified: Option<bool>,
+    /// Reproduction rate of prog, None if not reproduced
+    #[serde(default)]
+    pub repro_rate: Option<ReproRate>,
+    /// Prog crashes sometimes, but its reproduction rate is below threshold
+    #[serde(default)]
+    pub flaky: bool,
+    /// Prog has been minimized after being saved
+    #[serde(default)]
+    pub minimized: bool,
+    /// Sorted edges covered by calls before the last one, None if not computed
+    #[serde(default)]
+    pub fingerprint: Option<Vec<usize>>,
+}
+
+impl CrashedCase {
+    /// Jaccard similarity of coverage fingerprints, None if either is absent.
+    pub fn similarity(&self, other: &CrashedCase) -> Option<f64> {
+        let (a, b) = (self.fingerprint.as_ref()?, other.fingerprint.as_ref()?);
+        if a.is_empty() && b.is_empty() {
+            return Some(1.0);
+        }
+        let a = a.iter().collect::<HashSet<_>>();
+        let common = b.iter().filter(|e| a.contains(e)).count();
+        Some(common as f64 / (a.len() + b.len() - common) as f64)
+    }
+}
+
+/// Reproduced crashes saved in local crash dir `dir` (by this or previous runs), whose
+/// reproducer is not minimized yet. Unreadable reports are skipped.
+pub fn unminimized_crashes(dir: &Path) -> Vec<(Prog, CrashedCase)> {
+    let mut crashes = Vec::new();
+    let sig_dirs = match read_dir(dir) {
+        Ok(dirs) => dirs,
+        Err(_) => return crashes,
+    };
+    for entry in sig_dirs.flatten() {
+        let files = match read_dir(entry.path()) {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+        for prog_path in files.flatten().map(|f| f.path()) {
+            if prog_path.extension().map_or(true, |e| e != "prog") {
+                continue;
+            }
+            let loaded = read(prog_path.with_extension(""))
+                .map_err(|e| e.to_string())
+                .and_then(|c| serde_json::from_slice::<CrashedCase>(&c).map_err(|e| e.to_string()))
+                .and_then(|case| {
+                    let p = read(&prog_path).map_err(|e| e.to_string())?;
+                    Ok((prog_io::load(&p).map_err(|e| e.to_string())?, case))
+                });
+            match loaded {
+                Ok((p, case)) if case.repo && !case.minimized => crashes.push((p, case)),
+                Ok(_) => (),
+                Err(e) => warn!("Fail to load saved crash {}: {}", prog_path.display(), e),
+            }
+        }
+    }
+    crashes
+}
+
+/// Meta of signature saved in store by previous runs, None if absent or unreadable.
+fn saved_meta(store: &dyn CrashStore, signature: &str) -> Option<CrashMeta> {
+    let meta = match store.load(signature, CRASH_META) {
+        Ok(meta) => meta?,
+        Err(e) => {
+            warn!("Fail to load crash meta: {}", e);
+            return None;
+        }
+    };
+    serde_json::from_slice(&meta)
+        .map_err(|e| warn!("Fail to parse crash meta: {}", e))
+        .ok()
+}
+
+/// Crashes of repeated runs of reproducer
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct ReproRate {
+    pub runs: usize,
+    pub crashes: usize,
+}
+
+impl ReproRate {
+    pub fn rate(&self) -> f64 {
+        self.crashes as f64 / self.runs as f64
+    }
 }
 
 #[allow(clippy::len_without_is_empty)]
 impl TestCaseRecord {
-    pub fn new(t: Arc<Target>) -> Self {
+    pub fn new(
+        t: Arc<Target>,
+        labels: HashMap<String, String>,
+        store: Arc<dyn CrashStore>,
+        reporters: Vec<Box<dyn Reporter>>,
+        symbolizer: Option<Symbolizer>,
+    ) -> Self {
         Self {
             normal: Mutex::new(CircularQueue::with_capacity(1024 * 64)),
             failed: Mutex::new(CircularQueue::with_capacity(1024 * 64)),
             crash: Mutex::new(CircularQueue::with_capacity(1024)),
             target: t,
+            labels,
+            store,
+            reporters,
+            symbolizer: symbolizer.map(Arc::new),
 
             id_n: Mutex::new(0),
             normal_num: Mutex::new(0),
             failed_num: Mutex::new(0),
             crashed_num: Mutex::new(0),
+            dedup: Mutex::new(CrashDedup::default()),
         }
     }
 
@@ -119,9 +242,34 @@ impl TestCaseRecord {
         }
     }
 
-    pub async fn insert_crash(&self, p: Prog, crash: Crash, repo: bool) {
+    /// Record crash, only first crash of a signature is saved, unless it's not reproduced
+    /// and this one is, whose case then replaces it in dir of the signature.
+    pub async fn insert_crash(
+        &self,
+        p: Prog,
+        crash: Crash,
+        repo: bool,
+        repro_rate: Option<ReproRate>,
+        verified: Option<bool>,
+        fingerprint: Option<Vec<usize>>,
+    ) -> Option<CrashedCase> {
+        {
+            let mut crashed_num = self.crashed_num.lock().await;
+            *crashed_num += 1;
+        }
+        let signature = crash.signature();
+        let title = signature.lines().next().unwrap_or_default().to_string();
+        let meta = self.count_crash(&signature).await;
+        let save = self.dedup.lock().await.claim(&signature, repo);
+        if !save {
+            info!("Crash seen {} times, skip recording: {}", meta.hits, title);
+            self.persist_crash_meta(signature, &meta).await;
+            return None;
+        }
+
         let id = self.next_id().await;
         let stmts = to_script(&p, &self.target);
+        let report = self.report_of(&crash).await;
         let case = CrashedCase {
             meta: TestCase {
                 id,
@@ -129,20 +277,31 @@ impl TestCaseRecord {
                 test_time: Local::now(),
             },
             p: stmts.to_string(),
+            report: Some(report),
             crash,
             repo,
+            labels: self.labels.clone(),
+            verified,
+            repro_rate,
+            flaky: !repo && matches!(repro_rate, Some(r) if r.crashes != 0),
+            minimized: false,
+            fingerprint,
         };
 
-        self.persist_crash_case(&case).await;
+        self.persist_crash_case(&case, &p).await;
 
         {
             let mut crashes = self.crash.lock().await;
-            crashes.push(case);
-        }
-        {
-            let mut crashed_num = self.crashed_num.lock().await;
-            *crashed_num += 1;
+            crashes.push(case.clone());
         }
+        Some(case)
+    }
+
+    /// Replace prog of saved crash with minimized one, saved report is overwritten.
+    pub async fn update_minimized(&self, mut case: CrashedCase, p: &Prog) {
+        case.p = to_script(p, &self.target).to_string();
+        case.minimized = true;
+        self.persist_crash_case(&case, p).await;
     }
 
     pub async fn insert_failed(&self, p: Prog, reason: Reason) {
@@ -189,6 +348,23 @@ impl TestCaseRecord {
         )
     }
 
+    /// Number of distinct crash signatures recorded.
+    pub async fn unique_crashes(&self) -> usize {
+        let dedup = self.dedup.lock().await;
+        dedup.unique()
+    }
+
+    /// Recorded crash signatures with their meta, most hit first.
+    pub async fn crashes(&self) -> Vec<(String, CrashMeta)> {
+        let dedup = self.dedup.lock().await;
+        let mut crashes = dedup
+            .iter()
+            .map(|(sig, meta)| (sig.clone(), meta.clone()))
+            .collect::<Vec<_>>();
+        crashes.sort_by(|a, b| b.1.hits.cmp(&a.1.hits).then(a.0.cmp(&b.0)));
+        crashes
+    }
+
     async fn persist_normal_case(&self) {
         let cases = self.normal.lock().await;
         if cases.is_empty() {
@@ -227,26 +403,107 @@ impl TestCaseRecord {
         })
     }
 
-    async fn persist_crash_case(&self, case: &CrashedCase) {
-        let path = format!("./crashes/{}", &case.meta.title);
+    /// Save report of crash with its prog serialized by bincode (for `fuzzer reproduce`),
+    /// raw log, prog in json and meta of its signature, under dir of the signature.
+    async fn persist_crash_case(&self, case: &CrashedCase, p: &Prog) {
         let crash = serde_json::to_string_pretty(case).unwrap();
 
-        #[cfg(feature = "mail")]
-        mail::send(
-            EmailBuilder::new()
-                .subject("Healer-Reporter: CRASH REPORT")
-                .body(&crash),
-        )
-        .await;
+        notify_all(&self.reporters, "Healer-Reporter: CRASH REPORT", &crash).await;
 
-        write(&path, crash).await.unwrap_or_else(|e| {
-            exits!(
-                exitcode::IOERR,
-                "Fail to persist failed test case to {} : {}",
-                path,
-                e
-            )
+        let signature = case.crash.signature();
+        let mut artifacts = vec![
+            (case.meta.title.clone(), crash.into_bytes()),
+            (
+                format!("{}.prog", case.meta.title),
+                prog_io::dump(p).unwrap(),
+            ),
+            (
+                String::from(CRASH_LOG),
+                case.crash.inner.clone().into_bytes(),
+            ),
+            (
+                String::from(CRASH_PROG_JSON),
+                serde_json::to_vec_pretty(p).unwrap(),
+            ),
+        ];
+        if let Some(meta) = self.dedup.lock().await.meta(&signature) {
+            artifacts.push((
+                String::from(CRASH_META),
+                serde_json::to_vec_pretty(meta).unwrap(),
+            ));
+        }
+        let (artifacts, result) = self.save_crash(signature.clone(), artifacts).await;
+
+        if let Err(e) = result {
+            warn!(
+                "Fail to save crash to store: {}, saving to {}",
+                e, CRASH_DIR
+            );
+            LocalStore::new(CRASH_DIR)
+                .save(&signature, &artifacts)
+                .unwrap_or_else(|e| {
+                    exits!(exitcode::IOERR, "Fail to persist crashed test case: {}", e)
+                })
+        }
+    }
+
+    /// Parsed report of crash, with raw addresses symbolized if possible.
+    async fn report_of(&self, crash: &Crash) -> CrashReport {
+        let mut report = crash.parse();
+        if let Some(symbolizer) = self.symbolizer.clone() {
+            // loading symbols runs `nm` on vmlinux, keep it off the runtime threads
+            let raw = report.clone();
+            report = tokio::task::spawn_blocking(move || {
+                symbolizer.symbolize(&mut report);
+                report
+            })
+            .await
+            .unwrap_or(raw);
+        }
+        report
+    }
+
+    /// Count one hit of signature. On its first hit in this run, hits and first_seen saved
+    /// by previous runs are merged in, so saved meta keeps counting across restarts.
+    async fn count_crash(&self, signature: &str) -> CrashMeta {
+        let meta = self.dedup.lock().await.insert(signature.to_string());
+        if meta.hits != 1 {
+            return meta;
+        }
+        let store = self.store.clone();
+        let sig = signature.to_string();
+        let saved = tokio::task::spawn_blocking(move || saved_meta(&*store, &sig))
+            .await
+            .unwrap();
+        match saved {
+            Some(saved) => self.dedup.lock().await.merge(signature, &saved),
+            None => meta,
+        }
+    }
+
+    /// Count repeated crash in meta of its signature, nothing else of it is saved.
+    async fn persist_crash_meta(&self, signature: String, meta: &CrashMeta) {
+        let artifacts = vec![(
+            String::from(CRASH_META),
+            serde_json::to_vec_pretty(meta).unwrap(),
+        )];
+        if let (_, Err(e)) = self.save_crash(signature, artifacts).await {
+            warn!("Fail to save crash meta to store: {}", e);
+        }
+    }
+
+    async fn save_crash(
+        &self,
+        signature: String,
+        artifacts: Vec<(String, Vec<u8>)>,
+    ) -> (Vec<(String, Vec<u8>)>, Result<(), String>) {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = store.save(&signature, &artifacts);
+            (artifacts, result)
         })
+        .await
+        .unwrap()
     }
 
     fn title_of(&self, p: &Prog, id: usize) -> String {
@@ -262,3 +519,41 @@ impl TestCaseRecord {
         next
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn crash_meta_across_restart() {
+        let dir = temp_dir().join(format!("healer-meta-{}", std::process::id()));
+        let store = LocalStore::new(&dir);
+        let sig = "BUG: unable to handle page fault\nfoo_bar";
+        let save = |meta: &CrashMeta| {
+            let meta = serde_json::to_vec_pretty(meta).unwrap();
+            store
+                .save(sig, &[(String::from(CRASH_META), meta)])
+                .unwrap();
+        };
+
+        // first run
+        let mut dedup = CrashDedup::default();
+        assert!(saved_meta(&store, sig).is_none());
+        dedup.insert(sig.to_string());
+        let first = dedup.insert(sig.to_string());
+        save(&first);
+
+        // restarted run, hits go on from saved meta
+        let mut dedup = CrashDedup::default();
+        assert_eq!(dedup.insert(sig.to_string()).hits, 1);
+        let meta = dedup.merge(sig, &saved_meta(&store, sig).unwrap());
+        assert_eq!(meta.hits, 3);
+        assert_eq!(meta.first_seen, first.first_seen);
+        save(&meta);
+        assert_eq!(dedup.insert(sig.to_string()).hits, 4);
+        assert_eq!(saved_meta(&store, sig).unwrap().hits, 3);
+        remove_dir_all(&dir).unwrap();
+    }
+}
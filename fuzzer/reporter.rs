@@ -0,0 +1,218 @@
+//! Channels that regular stats reports and crash notifications are sent to.
+#[cfg(feature = "mail")]
+use crate::mail;
+use crate::stats::Stats;
+use chrono::prelude::*;
+#[cfg(feature = "mail")]
+use lettre_email::EmailBuilder;
+use serde::Deserialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::{exit, Stdio};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+
+pub type ReportFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// Subject of regular stats report
+pub const STATS_SUBJECT: &str = "Healer-Stats Regular Report";
+
+/// Max time of sending report to one channel
+pub const REPORT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Destination of stats report and notifications, failure of one channel doesn't affect others.
+pub trait Reporter: Send + Sync {
+    /// Name of channel shown in warnings
+    fn name(&self) -> String;
+
+    /// Send notification, e.g. of a new crash.
+    fn notify<'a>(&'a self, subject: &'a str, body: &'a str) -> ReportFuture<'a>;
+
+    /// Send regular stats report, pretty json of stats is notified by default.
+    fn report<'a>(&'a self, stats: &'a Stats) -> ReportFuture<'a> {
+        Box::pin(async move {
+            let body = serde_json::to_string_pretty(stats).unwrap();
+            self.notify(STATS_SUBJECT, &body).await
+        })
+    }
+}
+
+/// Send notification to every channel, a failed or hanging channel doesn't block others.
+pub async fn notify_all(reporters: &[Box<dyn Reporter>], subject: &str, body: &str) {
+    for reporter in reporters.iter() {
+        match timeout(REPORT_TIMEOUT, reporter.notify(subject, body)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => warn!("Fail to notify {}: {}", reporter.name(), e),
+            Err(_) => warn!("Notify {}: time out", reporter.name()),
+        }
+    }
+}
+
+/// Configuration of report channel, e.g. `{ kind = "webhook", url = "https://..." }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ReporterConf {
+    /// Mail to receivers of mail config
+    #[cfg(feature = "mail")]
+    Mail,
+    /// Post `{"text": report}` to url, accepted by Slack incoming webhook
+    Webhook { url: String },
+    /// Append report as one json line to file
+    File { path: String },
+}
+
+impl ReporterConf {
+    pub fn check(&self) {
+        match self {
+            ReporterConf::Webhook { url } => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    eprintln!("Config Error: webhook url must be http(s) url: {}", url);
+                    exit(exitcode::CONFIG)
+                }
+            }
+            ReporterConf::File { path } => {
+                if path.trim().is_empty() {
+                    eprintln!("Config Error: empty report file path");
+                    exit(exitcode::CONFIG)
+                }
+            }
+            #[cfg(feature = "mail")]
+            ReporterConf::Mail => (),
+        }
+    }
+}
+
+/// Reporters of configured channels. Without config, stats are mailed if mail is enabled.
+pub fn reporters(conf: &Option<Vec<ReporterConf>>) -> Vec<Box<dyn Reporter>> {
+    let conf = match conf {
+        Some(conf) => conf.clone(),
+        #[cfg(feature = "mail")]
+        None => vec![ReporterConf::Mail],
+        #[cfg(not(feature = "mail"))]
+        None => Vec::new(),
+    };
+    conf.into_iter()
+        .map(|c| -> Box<dyn Reporter> {
+            match c {
+                #[cfg(feature = "mail")]
+                ReporterConf::Mail => Box::new(MailReporter),
+                ReporterConf::Webhook { url } => Box::new(WebhookReporter { url }),
+                ReporterConf::File { path } => Box::new(FileReporter { path }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "mail")]
+pub struct MailReporter;
+
+#[cfg(feature = "mail")]
+impl Reporter for MailReporter {
+    fn name(&self) -> String {
+        String::from("mail")
+    }
+
+    fn notify<'a>(&'a self, subject: &'a str, body: &'a str) -> ReportFuture<'a> {
+        Box::pin(async move {
+            let email = EmailBuilder::new().subject(subject).body(body);
+            mail::try_send(email).await
+        })
+    }
+}
+
+/// Post report to webhook with curl.
+pub struct WebhookReporter {
+    url: String,
+}
+
+impl Reporter for WebhookReporter {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn notify<'a>(&'a self, subject: &'a str, body: &'a str) -> ReportFuture<'a> {
+        Box::pin(async move {
+            let text = format!("{}\n```\n{}\n```", subject, body);
+            let body = serde_json::json!({ "text": text }).to_string();
+            let mut curl = Command::new("curl")
+                .args(&["-sS", "--fail", "-X", "POST"])
+                .args(&[
+                    "-H",
+                    "Content-Type: application/json",
+                    "--data-binary",
+                    "@-",
+                ])
+                .arg(&self.url)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("Fail to spawn curl: {}", e))?;
+            curl.stdin
+                .take()
+                .unwrap()
+                .write_all(body.as_bytes())
+                .await
+                .map_err(|e| format!("Fail to send report: {}", e))?;
+            let output = curl
+                .wait_with_output()
+                .await
+                .map_err(|e| format!("Fail to wait curl: {}", e))?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+            }
+        })
+    }
+}
+
+/// Append report to local file, one json object per line.
+pub struct FileReporter {
+    path: String,
+}
+
+impl Reporter for FileReporter {
+    fn name(&self) -> String {
+        format!("file {}", self.path)
+    }
+
+    fn notify<'a>(&'a self, subject: &'a str, body: &'a str) -> ReportFuture<'a> {
+        Box::pin(async move {
+            self.append(serde_json::json!({
+                "time": Local::now().to_rfc3339(),
+                "subject": subject,
+                "body": body,
+            }))
+            .await
+        })
+    }
+
+    fn report<'a>(&'a self, stats: &'a Stats) -> ReportFuture<'a> {
+        Box::pin(async move {
+            self.append(serde_json::json!({
+                "time": Local::now().to_rfc3339(),
+                "stats": stats,
+            }))
+            .await
+        })
+    }
+}
+
+impl FileReporter {
+    async fn append(&self, line: serde_json::Value) -> Result<(), String> {
+        let mut line = line.to_string();
+        line.push('\n');
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| format!("Fail to open: {}", e))?;
+        f.write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("Fail to write: {}", e))
+    }
+}
@@ -0,0 +1,92 @@
+//! Reproduce crash of single prog on fresh guest.
+use crate::exec::Executor;
+use crate::guest::Crash;
+use crate::Config;
+use core::minimize::remove;
+use core::prog::Prog;
+use core::target::Target;
+
+/// Tries of reproducing if *repro.runs* is smaller, kernel bugs are often flaky.
+pub const REPRO_TRIES: usize = 3;
+/// Max executions of candidates when minimizing, so flaky progs can't run forever.
+pub const MINIMIZE_EXECS: usize = 100;
+
+/// Run `p` on fresh guest until it crashes, return the first crash.
+pub async fn reproduce(cfg: &Config, t: &Target, p: &Prog) -> Option<Crash> {
+    let mut executor = Executor::new(cfg);
+    executor.start().await;
+    let mut budget = usize::MAX;
+    reproduce_on(&mut executor, t, p, tries(cfg), None, &mut budget).await
+}
+
+/// Remove calls of crashing `p` one by one, a removal is kept if the rest still crashes
+/// with the same signature. Return the crash of `p` and minimized prog, or None if `p`
+/// doesn't crash. Candidates are executed at most `MINIMIZE_EXECS` times.
+pub async fn minimize(cfg: &Config, t: &Target, p: &Prog) -> Option<(Crash, Prog)> {
+    let tries = tries(cfg);
+    let mut executor = Executor::new(cfg);
+    executor.start().await;
+    let mut budget = usize::MAX;
+    let crash = reproduce_on(&mut executor, t, p, tries, None, &mut budget).await?;
+    let signature = crash.signature();
+
+    let mut p = p.clone();
+    let mut budget = MINIMIZE_EXECS;
+    let mut i = 0;
+    while i + 1 < p.len() && budget != 0 {
+        let mut q = p.clone();
+        if remove(&mut q, i) {
+            info!("Trying without call {}, {} calls left ...", i, q.len());
+            if reproduce_on(&mut executor, t, &q, tries, Some(&signature), &mut budget)
+                .await
+                .is_some()
+            {
+                p = q;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    if budget == 0 {
+        warn!("Execution budget of minimization exhausted, prog may be minimized partly");
+    }
+    Some((crash, p))
+}
+
+fn tries(cfg: &Config) -> usize {
+    let repro = cfg.repro.clone().unwrap_or_default();
+    repro.runs().max(REPRO_TRIES)
+}
+
+/// Run `p` at most `tries` times, guest is restarted after each crash. Only crashes with
+/// `expected` signature count if given. Each run consumes one of `budget`.
+async fn reproduce_on(
+    executor: &mut Executor,
+    t: &Target,
+    p: &Prog,
+    tries: usize,
+    expected: Option<&str>,
+    budget: &mut usize,
+) -> Option<Crash> {
+    for i in 0..tries {
+        if *budget == 0 {
+            break;
+        }
+        *budget -= 1;
+        info!(
+            "Reproducing prog with {} calls ({}/{}) ...",
+            p.len(),
+            i + 1,
+            tries
+        );
+        if let Err(crash) = executor.exec(p, t).await {
+            executor.start().await;
+            let crash = crash.unwrap_or_default();
+            match expected {
+                Some(sig) if sig != crash.signature() => continue,
+                _ => return Some(crash),
+            }
+        }
+    }
+    None
+}
@@ -1,21 +1,26 @@
 use crate::corpus::Corpus;
 use crate::feedback::FeedBack;
-#[cfg(feature = "mail")]
-use crate::mail;
+use crate::guest::Crash;
+use crate::metrics::SharedStats;
 use crate::report::TestCaseRecord;
+use crate::reporter::{Reporter, ReporterConf, REPORT_TIMEOUT};
 use crate::utils::queue::CQueue;
-#[cfg(feature = "mail")]
-use lettre_email::EmailBuilder;
+use crate::utils::wait_stop;
 
+use chrono::prelude::*;
 use circular_queue::CircularQueue;
 use core::prog::Prog;
+use core::target::Target;
+use executor::ExecResult;
+use fots::types::FnId;
+use std::collections::{HashMap, HashSet};
 use std::process::exit;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::fs::write;
-use tokio::sync::broadcast;
+use tokio::fs::{create_dir_all, read_dir, remove_file, rename, write};
+use tokio::sync::{broadcast, Mutex, RwLock};
 use tokio::time;
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 
 pub struct StatSource {
     pub corpus: Arc<Corpus>,
@@ -23,20 +28,137 @@ pub struct StatSource {
     pub candidates: Arc<CQueue<Prog>>,
     pub record: Arc<TestCaseRecord>,
     pub exec: Arc<AtomicUsize>,
+    pub oom: Arc<AtomicUsize>,
+    pub truncated: Arc<AtomicUsize>,
+    pub calls: Arc<CallStats>,
+    /// Id of guest if corpus is not shared
+    pub guest: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Stats {
     pub corpus: usize,
     pub blocks: usize,
     pub branches: usize,
+    /// Blocks first covered since last sample
+    pub new_blocks: usize,
+    /// Branches first covered since last sample
+    pub new_branches: usize,
     pub exec: usize,
+    /// Executions per second since last sample
+    pub exec_per_sec: f64,
     // pub gen:usize,
     // pub minimized:usize,
     pub candidates: usize,
     pub normal_case: usize,
     pub failed_case: usize,
     pub crashed_case: usize,
+    /// Crashes with distinct signatures
+    pub unique_crash: usize,
+    pub oom: usize,
+    /// Executions whose coverage exceeded limit and was truncated
+    pub truncated: usize,
+    /// Most executed syscalls
+    pub top_calls: Vec<CallStat>,
+    /// Ratio of executed priority syscalls to all executed syscalls, if priority is configured
+    pub priority_calls: Option<f64>,
+}
+
+/// Execution statistics of one syscall.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CallStat {
+    pub name: String,
+    pub exec: usize,
+    pub failed: usize,
+    /// Average coverage of successful executions
+    pub avg_cov: f64,
+}
+
+const TOP_CALLS: usize = 10;
+
+/// Per-syscall execution statistics.
+/// A call succeeded if its coverage is received, the first call without coverage
+/// of an untruncated execution failed, calls after it were not executed.
+pub struct CallStats {
+    target: Arc<Target>,
+    priority: HashSet<FnId>,
+    // fid -> (exec, failed, coverage of succeeded executions)
+    calls: Mutex<HashMap<FnId, (usize, usize, usize)>>,
+}
+
+impl CallStats {
+    pub fn new(target: Arc<Target>, priority: HashSet<FnId>) -> Self {
+        Self {
+            target,
+            priority,
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn record(&self, p: &Prog, ret: &Result<ExecResult, Option<Crash>>) {
+        let (covs, truncated): (&[Vec<usize>], bool) = match ret {
+            Ok(ExecResult::Ok(covs)) | Ok(ExecResult::OkCmp { covs, .. }) => (covs, false),
+            Ok(ExecResult::Truncated(covs)) => (covs, true),
+            // call after covered ones faulted
+            Ok(ExecResult::Partial { covs, .. }) => (covs, false),
+            Ok(ExecResult::Failed(_)) => (&[], false),
+            // unknown which call is responsible
+            _ => return,
+        };
+        let mut calls = self.calls.lock().await;
+        for (c, cov) in p.calls.iter().zip(covs.iter()) {
+            let stat = calls.entry(c.fid).or_default();
+            stat.0 += 1;
+            stat.2 += cov.len();
+        }
+        if !truncated {
+            if let Some(c) = p.calls.get(covs.len()) {
+                let stat = calls.entry(c.fid).or_default();
+                stat.0 += 1;
+                stat.1 += 1;
+            }
+        }
+    }
+
+    /// Ratio of executed priority syscalls, None if no priority syscall.
+    pub async fn priority_ratio(&self) -> Option<f64> {
+        if self.priority.is_empty() {
+            return None;
+        }
+        let calls = self.calls.lock().await;
+        let total = calls.values().map(|s| s.0).sum::<usize>();
+        let priority = calls
+            .iter()
+            .filter(|(fid, _)| self.priority.contains(fid))
+            .map(|(_, s)| s.0)
+            .sum::<usize>();
+        Some(if total == 0 {
+            0.0
+        } else {
+            priority as f64 / total as f64
+        })
+    }
+
+    /// Most executed `n` syscalls.
+    pub async fn top(&self, n: usize) -> Vec<CallStat> {
+        let calls = self.calls.lock().await;
+        let mut top = calls
+            .iter()
+            .map(|(&fid, &(exec, failed, cov))| CallStat {
+                name: self.target.fn_of(fid).dec_name.clone(),
+                exec,
+                failed,
+                avg_cov: if exec == failed {
+                    0.0
+                } else {
+                    cov as f64 / (exec - failed) as f64
+                },
+            })
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.exec.cmp(&a.exec).then_with(|| a.name.cmp(&b.name)));
+        top.truncate(n);
+        top
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -45,6 +167,14 @@ pub struct SamplerConf {
     pub sample_interval: u64,
     /// Duration for report, per minites
     pub report_interval: u64,
+    /// Duration for saving corpus snapshot, per minites
+    pub corpus_save_interval: Option<u64>,
+    /// Number of corpus snapshots to keep, 3 by default
+    pub corpus_snapshots: Option<usize>,
+    /// Channels stats are reported to, mail only by default if mail is enabled
+    pub reporters: Option<Vec<ReporterConf>>,
+    /// Port of http server serving `/metrics` in Prometheus format, no server by default
+    pub prometheus_port: Option<u16>,
 }
 
 impl Default for SamplerConf {
@@ -52,36 +182,99 @@ impl Default for SamplerConf {
         Self {
             sample_interval: 15,
             report_interval: 60,
+            corpus_save_interval: None,
+            corpus_snapshots: None,
+            reporters: None,
+            prometheus_port: None,
         }
     }
 }
 
 impl SamplerConf {
     pub fn check(&self) {
-        if self.sample_interval < 10
-            || self.report_interval <= 10
-            || self.sample_interval * 60 < self.report_interval
-        {
-            eprintln!("Config Error: invalid sample conf: sample interval should longger than 10s, \
-                                    report internval should long than 10m and sample interval should \
-                                    not longger than report interval");
+        if let Err(e) = self.check_intervals() {
+            eprintln!("Config Error: invalid sample conf: {}", e);
             exit(exitcode::CONFIG)
         }
+
+        if self.corpus_save_interval == Some(0) || self.corpus_snapshots == Some(0) {
+            eprintln!(
+                "Config Error: invalid sample conf: corpus save interval and corpus snapshots should bigger than 0"
+            );
+            exit(exitcode::CONFIG)
+        }
+
+        if self.prometheus_port == Some(0) {
+            eprintln!("Config Error: invalid sample conf: prometheus port should bigger than 0");
+            exit(exitcode::CONFIG)
+        }
+
+        for reporter in self.reporters.iter().flatten() {
+            reporter.check()
+        }
     }
+
+    /// Sample interval (seconds) should be at least 10s and shorter than report interval (minutes).
+    fn check_intervals(&self) -> Result<(), String> {
+        if self.sample_interval < 10 {
+            return Err(format!(
+                "sample interval should be at least 10s, got {}s",
+                self.sample_interval
+            ));
+        }
+        if self.report_interval < 1 {
+            return Err(String::from(
+                "report interval should be at least 1m, got 0m",
+            ));
+        }
+        if self.sample_interval >= self.report_interval * 60 {
+            return Err(format!(
+                "sample interval ({}s) should be shorter than report interval ({}m)",
+                self.sample_interval, self.report_interval
+            ));
+        }
+        Ok(())
+    }
+}
+
+const CORPUS_SNAPSHOT_DIR: &str = "./corpus_snapshots";
+
+/// Content of stats.json
+#[derive(Debug, Serialize)]
+pub struct StatsReport<'a> {
+    pub labels: &'a HashMap<String, String>,
+    pub stats: Vec<Stats>,
 }
 
 pub struct Sampler {
     pub source: StatSource,
     pub stats: CircularQueue<Stats>,
+    pub labels: HashMap<String, String>,
+    pub reporters: Vec<Box<dyn Reporter>>,
+    /// Latest sampled stats, read by metrics server
+    pub latest: Arc<RwLock<Stats>>,
 }
 
 impl Sampler {
-    pub fn new(source: StatSource) -> Self {
+    pub fn new(
+        source: StatSource,
+        labels: HashMap<String, String>,
+        reporters: Vec<Box<dyn Reporter>>,
+    ) -> Self {
         Self {
             source,
             stats: CircularQueue::with_capacity(1024),
+            labels,
+            reporters,
+            latest: Arc::new(RwLock::new(Stats::default())),
         }
     }
+
+    /// Latest stats with id of guest if corpus is not shared.
+    pub fn latest(&self) -> SharedStats {
+        (self.source.guest, self.latest.clone())
+    }
+
     pub async fn sample(
         &mut self,
         conf: &Option<SamplerConf>,
@@ -91,77 +284,257 @@ impl Sampler {
             Some(SamplerConf {
                 sample_interval,
                 report_interval,
+                ..
             }) => (
                 Duration::new(*sample_interval, 0),
                 Duration::new(report_interval * 60, 0),
             ),
             None => (Duration::new(15, 0), Duration::new(60 * 60, 0)),
         };
+        let corpus_save = conf.as_ref().and_then(|c| {
+            c.corpus_save_interval
+                .map(|i| (Duration::new(i * 60, 0), c.corpus_snapshots.unwrap_or(3)))
+        });
         tokio::select! {
-            _ = shutdown.recv() => (),
-            _ = self.do_sample(interval) => (),
+            _ = wait_stop(&mut shutdown) => (),
+            _ = self.do_sample(interval, corpus_save) => (),
         }
         self.persist().await;
     }
 
-    async fn do_sample(&mut self, (sample_interval, report_interval): (Duration, Duration)) {
+    async fn do_sample(
+        &mut self,
+        (sample_interval, report_interval): (Duration, Duration),
+        corpus_save: Option<(Duration, usize)>,
+    ) {
         let mut last_report = Duration::new(0, 0);
+        let mut last_save = Duration::new(0, 0);
+        let mut last_exec = (self.source.exec.load(Ordering::SeqCst), Instant::now());
+        let mut last_cov = self.source.feedback.len().await;
         loop {
             time::delay_for(sample_interval).await;
             last_report += sample_interval;
+            last_save += sample_interval;
+
+            if let Some((save_interval, keep)) = corpus_save {
+                if save_interval <= last_save {
+                    self.save_corpus(keep).await;
+                    last_save = Duration::new(0, 0);
+                }
+            }
 
-            let (corpus, (blocks, branches), candidates, (normal_case, failed_case, crashed_case)) = tokio::join!(
+            let (
+                corpus,
+                (blocks, branches),
+                candidates,
+                (normal_case, failed_case, crashed_case),
+                unique_crash,
+                top_calls,
+                priority_calls,
+            ) = tokio::join!(
                 self.source.corpus.len(),
                 self.source.feedback.len(),
                 self.source.candidates.len(),
-                self.source.record.len()
+                self.source.record.len(),
+                self.source.record.unique_crashes(),
+                self.source.calls.top(TOP_CALLS),
+                self.source.calls.priority_ratio()
             );
             let exec = self.source.exec.load(Ordering::SeqCst);
+            let now = Instant::now();
+            let elapsed = now.duration_since(last_exec.1).as_secs_f64();
+            let exec_per_sec = if elapsed > 0.0 {
+                (exec - last_exec.0) as f64 / elapsed
+            } else {
+                0.0
+            };
+            last_exec = (exec, now);
+            // feedback only grows, growth between samples is the newly discovered coverage
+            let (new_blocks, new_branches) = (
+                blocks.saturating_sub(last_cov.0),
+                branches.saturating_sub(last_cov.1),
+            );
+            last_cov = (blocks, branches);
+            let oom = self.source.oom.load(Ordering::SeqCst);
+            let truncated = self.source.truncated.load(Ordering::SeqCst);
 
             let stat = Stats {
                 exec,
+                exec_per_sec,
                 corpus,
                 blocks,
                 branches,
+                new_blocks,
+                new_branches,
                 candidates,
                 normal_case,
                 failed_case,
                 crashed_case,
+                unique_crash,
+                oom,
+                truncated,
+                top_calls,
+                priority_calls,
             };
 
-            if report_interval <= last_report {
-                #[cfg(feature = "mail")]
+            let report = report_interval <= last_report;
+            if report {
                 self.report(&stat).await;
                 last_report = Duration::new(0, 0);
             }
 
+            *self.latest.write().await = stat.clone();
             self.stats.push(stat);
+            if report {
+                if let Err((path, e)) = self.try_persist().await {
+                    warn!("Fail to persist stats to {}: {}", path, e);
+                }
+            }
+            let guest = match self.source.guest {
+                Some(id) => format!("guest {}: ", id),
+                None => String::new(),
+            };
+            // fields of json log, human readable format only prints message
+            let mut fields = vec![
+                ("exec", exec.to_string()),
+                ("exec_per_sec", format!("{:.1}", exec_per_sec)),
+                ("blocks", blocks.to_string()),
+                ("new_blocks", new_blocks.to_string()),
+                ("branches", branches.to_string()),
+                ("new_branches", new_branches.to_string()),
+                ("failed", failed_case.to_string()),
+                ("crashed", crashed_case.to_string()),
+                ("unique_crash", unique_crash.to_string()),
+                ("oom", oom.to_string()),
+                ("truncated", truncated.to_string()),
+            ];
+            if let Some(id) = self.source.guest {
+                fields.push(("guest", id.to_string()));
+            }
+            let _fields = log_mdc::extend_scoped(fields);
             info!(
-                "exec {}, blocks {}, branches {}, failed {}, crashed {}",
-                exec, blocks, branches, failed_case, crashed_case
+                "{}exec {} ({:.1}/s), blocks {} (+{}), branches {} (+{}), failed {}, crashed {} ({} unique), oom {}, truncated {}",
+                guest,
+                exec,
+                exec_per_sec,
+                blocks,
+                new_blocks,
+                branches,
+                new_branches,
+                failed_case,
+                crashed_case,
+                unique_crash,
+                oom,
+                truncated
             );
         }
     }
 
+    /// Write timestamped corpus snapshot, remove old snapshots except latest `keep` ones.
+    async fn save_corpus(&self, keep: usize) {
+        if let Err(e) = create_dir_all(CORPUS_SNAPSHOT_DIR).await {
+            warn!("Fail to create {}: {}", CORPUS_SNAPSHOT_DIR, e);
+            return;
+        }
+        let corpus = match self.source.corpus.dump().await {
+            Ok(corpus) => corpus,
+            Err(e) => {
+                warn!("Fail to dump corpus: {}", e);
+                return;
+            }
+        };
+        let prefix = format!("{}-", self.name_of("corpus"));
+        let path = format!(
+            "{}/{}{}",
+            CORPUS_SNAPSHOT_DIR,
+            prefix,
+            Local::now().format("%Y%m%d-%H%M%S")
+        );
+        if let Err(e) = write(&path, corpus).await {
+            warn!("Fail to save corpus snapshot to {}: {}", path, e);
+            return;
+        }
+
+        let mut snapshots = Vec::new();
+        if let Ok(mut entries) = read_dir(CORPUS_SNAPSHOT_DIR).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    snapshots.push(entry.path());
+                }
+            }
+        }
+        snapshots.sort();
+        let old = snapshots.len().saturating_sub(keep);
+        for snapshot in snapshots.into_iter().take(old) {
+            if let Err(e) = remove_file(&snapshot).await {
+                warn!("Fail to remove old snapshot {}: {}", snapshot.display(), e);
+            }
+        }
+    }
+
     async fn persist(&self) {
+        if let Err((path, e)) = self.try_persist().await {
+            exits!(exitcode::IOERR, "Fail to persist stats to {} : {}", path, e)
+        }
+    }
+
+    /// Write stats to temp file and rename it, so a kill mid-write can't corrupt saved stats.
+    async fn try_persist(&self) -> Result<(), (String, std::io::Error)> {
         if self.stats.is_empty() {
-            return;
+            return Ok(());
         }
 
-        let stats = self.stats.asc_iter().cloned().collect::<Vec<_>>();
-        let path = "./stats.json";
+        let stats = StatsReport {
+            labels: &self.labels,
+            stats: self.stats.asc_iter().cloned().collect::<Vec<_>>(),
+        };
+        let path = format!("./{}.json", self.name_of("stats"));
+        let tmp = format!("{}.tmp", path);
         let stats = serde_json::to_string_pretty(&stats).unwrap();
-        write(&path, stats).await.unwrap_or_else(|e| {
-            exits!(exitcode::IOERR, "Fail to persist stats to {} : {}", path, e)
-        })
+        write(&tmp, stats).await.map_err(|e| (tmp.clone(), e))?;
+        rename(&tmp, &path).await.map_err(|e| (path, e))
     }
 
-    #[cfg(feature = "mail")]
+    /// Name of persisted data, suffixed with guest id if corpus is not shared.
+    fn name_of(&self, name: &str) -> String {
+        match self.source.guest {
+            Some(id) => format!("{}-{}", name, id),
+            None => name.to_string(),
+        }
+    }
+
+    /// Send report to every channel, a failed or hanging channel doesn't block others.
     async fn report(&self, stat: &Stats) {
-        let stat = serde_json::to_string_pretty(&stat).unwrap();
-        let email = EmailBuilder::new()
-            .subject("Healer-Stats Regular Report")
-            .body(stat);
-        mail::send(email).await
+        for reporter in self.reporters.iter() {
+            match time::timeout(REPORT_TIMEOUT, reporter.report(stat)).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => warn!("Fail to report stats to {}: {}", reporter.name(), e),
+                Err(_) => warn!("Report stats to {}: time out", reporter.name()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conf(sample_interval: u64, report_interval: u64) -> SamplerConf {
+        SamplerConf {
+            sample_interval,
+            report_interval,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sampler_intervals() {
+        assert!(conf(10, 1).check_intervals().is_ok());
+        assert!(conf(59, 1).check_intervals().is_ok());
+        assert!(SamplerConf::default().check_intervals().is_ok());
+
+        assert!(conf(9, 1).check_intervals().is_err());
+        assert!(conf(10, 0).check_intervals().is_err());
+        assert!(conf(60, 1).check_intervals().is_err());
     }
 }
@@ -0,0 +1,241 @@
+//! Backends for persisting crash artifacts.
+use serde::Deserialize;
+use std::env;
+use std::fs::{create_dir_all, read, remove_file, write, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, exit, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Directory of crashes of local store.
+pub const CRASH_DIR: &str = "./crashes";
+
+/// Persistence of crash artifacts, dedup and counting of crashes are not its business.
+pub trait CrashStore: Send + Sync {
+    /// Save artifacts (name, bytes) of a crash with given signature. Artifacts of same
+    /// signature are grouped under `signature_dir`, saving same name again overwrites it.
+    fn save(&self, signature: &str, artifacts: &[(String, Vec<u8>)]) -> Result<(), String>;
+
+    /// Artifact `name` of signature saved before, None if absent. Stores that can't be
+    /// read back return None.
+    fn load(&self, _signature: &str, _name: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(None)
+    }
+}
+
+/// Dir (or key prefix) of artifacts of crashes with given signature, stable across runs.
+pub fn signature_dir(signature: &str) -> String {
+    format!("{:x}", md5::compute(signature))
+}
+
+/// Save crash artifacts to local dir, in a sub dir per signature.
+pub struct LocalStore {
+    dir: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl CrashStore for LocalStore {
+    fn save(&self, signature: &str, artifacts: &[(String, Vec<u8>)]) -> Result<(), String> {
+        let dir = self.dir.join(signature_dir(signature));
+        create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        for (name, data) in artifacts {
+            let path = dir.join(name);
+            write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    fn load(&self, signature: &str, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let path = self.dir.join(signature_dir(signature)).join(name);
+        match read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Configuration of S3-compatible object store.
+/// Credentials are read from HEALER_STORE_ACCESS_KEY and HEALER_STORE_SECRET_KEY env.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CrashStoreConf {
+    /// Url of bucket with optional prefix, e.g. https://s3.example.com/bucket/healer
+    pub url: String,
+    /// Region used for signing request, us-east-1 by default
+    pub region: Option<String>,
+}
+
+impl CrashStoreConf {
+    pub fn check(&self) {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            eprintln!(
+                "Config Error: crash store url must be http(s) url: {}",
+                self.url
+            );
+            exit(exitcode::CONFIG)
+        }
+        for var in &[ACCESS_KEY_ENV, SECRET_KEY_ENV] {
+            if env::var(var).is_err() {
+                eprintln!("Config Error: {} env not found", var);
+                exit(exitcode::CONFIG)
+            }
+        }
+    }
+
+    pub fn region(&self) -> &str {
+        self.region.as_deref().unwrap_or("us-east-1")
+    }
+}
+
+const ACCESS_KEY_ENV: &str = "HEALER_STORE_ACCESS_KEY";
+const SECRET_KEY_ENV: &str = "HEALER_STORE_SECRET_KEY";
+
+/// Id of temp file of next upload
+static UPLOAD_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Upload crash artifacts to S3-compatible object store with curl.
+/// Artifacts of crashes with same signature are grouped under same key prefix.
+pub struct ObjectStore {
+    url: String,
+    region: String,
+    user: String,
+}
+
+impl ObjectStore {
+    pub fn new(conf: &CrashStoreConf) -> Self {
+        let access_key = env::var(ACCESS_KEY_ENV).unwrap();
+        let secret_key = env::var(SECRET_KEY_ENV).unwrap();
+        Self {
+            url: conf.url.trim_end_matches('/').to_string(),
+            region: conf.region().to_string(),
+            user: format!("{}:{}", access_key, secret_key),
+        }
+    }
+
+    /// Credentials are passed in curl config through stdin, other users could read them
+    /// in argv, so body is uploaded from a temp file only readable by this user.
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let body = loop {
+            let body = env::temp_dir().join(format!(
+                "healer-upload-{}-{}",
+                process::id(),
+                UPLOAD_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            // create it exclusively, never write through a file or symlink planted there
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&body)
+            {
+                Ok(mut f) => {
+                    if let Err(e) = f.write_all(data) {
+                        let _ = remove_file(&body);
+                        return Err(format!("Fail to write {}: {}", body.display(), e));
+                    }
+                    break body;
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("Fail to create {}: {}", body.display(), e)),
+            }
+        };
+        let result = self.upload(key, &body);
+        let _ = remove_file(&body);
+        result
+    }
+
+    fn upload(&self, key: &str, body: &Path) -> Result<(), String> {
+        let mut curl = Command::new("curl")
+            .args(&["-sS", "--fail", "-K", "-", "--upload-file"])
+            .arg(body)
+            .arg("--aws-sigv4")
+            .arg(format!("aws:amz:{}:s3", self.region))
+            .arg(format!("{}/{}", self.url, key))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Fail to spawn curl: {}", e))?;
+        curl.stdin
+            .take()
+            .unwrap()
+            .write_all(curl_config(&self.user).as_bytes())
+            .map_err(|e| format!("Fail to send curl config: {}", e))?;
+        let output = curl
+            .wait_with_output()
+            .map_err(|e| format!("Fail to wait curl: {}", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Fail to upload {}: {}",
+                key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+impl CrashStore for ObjectStore {
+    fn save(&self, signature: &str, artifacts: &[(String, Vec<u8>)]) -> Result<(), String> {
+        let prefix = signature_dir(signature);
+        for (name, data) in artifacts {
+            self.put(&format!("{}/{}", prefix, name), data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Curl config passing `user` (ACCESS:SECRET), quoted and escaped.
+fn curl_config(user: &str) -> String {
+    format!(
+        "user = \"{}\"\n",
+        user.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Object store if configured, local crash dir otherwise.
+pub fn crash_store(conf: &Option<CrashStoreConf>) -> Arc<dyn CrashStore> {
+    match conf {
+        Some(conf) => Arc::new(ObjectStore::new(conf)),
+        None => Arc::new(LocalStore::new(CRASH_DIR)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_store_layout() {
+        let dir = env::temp_dir().join(format!("healer-store-{}", std::process::id()));
+        let store = LocalStore::new(&dir);
+        let sig = "BUG: unable to handle page fault\nfoo_bar";
+        store
+            .save(sig, &[(String::from("meta.json"), b"1".to_vec())])
+            .unwrap();
+        store
+            .save(sig, &[(String::from("meta.json"), b"2".to_vec())])
+            .unwrap();
+
+        let path = dir.join(signature_dir(sig)).join("meta.json");
+        assert_eq!(read(&path).unwrap(), b"2");
+        assert_eq!(store.load(sig, "meta.json").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.load(sig, "report.txt").unwrap(), None);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn curl_config_escape() {
+        assert_eq!(curl_config("ak:sk"), "user = \"ak:sk\"\n");
+        assert_eq!(curl_config(r#"a"k:s\k"#), "user = \"a\\\"k:s\\\\k\"\n");
+    }
+}
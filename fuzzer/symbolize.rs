@@ -0,0 +1,178 @@
+//! Resolve raw kernel addresses in crash reports to symbols of vmlinux.
+
+use crate::crash::{frames_of, CrashReport};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Frame with address only, e.g. " [<ffffffff81234567>]" or " ? ffffffff81234567"
+    static ref RAW_FRAME: Regex =
+        Regex::new(r"(?m)^(\[\s*\d+\.\d+\]\s?\s*(?:\? )?|\s*(?:\? )?)(?:\[<)?(?:0x)?([0-9a-f]{16})(?:>\])?\s*$")
+            .unwrap();
+}
+
+/// Text symbol of vmlinux.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    start: u64,
+    size: u64,
+    name: String,
+}
+
+/// Symbolizes call traces with `nm` of the configured toolchain.
+/// Addresses are looked up as is, kernel must be booted with `nokaslr`.
+pub struct Symbolizer {
+    vmlinux: PathBuf,
+    nm: String,
+    /// Symbols sorted by start, loaded on first use, empty if loading failed
+    symbols: Mutex<Option<Vec<Symbol>>>,
+    /// Resolved addresses, shared across crashes
+    cache: Mutex<HashMap<u64, Option<String>>>,
+}
+
+impl Symbolizer {
+    pub fn new(vmlinux: PathBuf, nm: String) -> Self {
+        Self {
+            vmlinux,
+            nm,
+            symbols: Mutex::new(None),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Rewrite address-only frames of report as `function+offset/size` and rebuild its call trace.
+    /// Report is left as is if vmlinux can't be symbolized.
+    pub fn symbolize(&self, report: &mut CrashReport) {
+        if !RAW_FRAME.is_match(&report.raw) {
+            return;
+        }
+        let raw = RAW_FRAME.replace_all(&report.raw, |cap: &Captures| {
+            let addr = u64::from_str_radix(&cap[2], 16).unwrap();
+            match self.resolve(addr) {
+                Some(sym) => format!("{}[<{}>] {}", &cap[1], &cap[2], sym),
+                None => cap[0].to_string(),
+            }
+        });
+        report.call_trace = frames_of(&raw);
+        report.raw = raw.into_owned();
+    }
+
+    fn resolve(&self, addr: u64) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(sym) = cache.get(&addr) {
+            return sym.clone();
+        }
+
+        let mut symbols = self.symbols.lock().unwrap();
+        let symbols = symbols.get_or_insert_with(|| self.load());
+        let i = symbols.partition_point(|s| s.start <= addr);
+        let sym = symbols[..i]
+            .last()
+            .filter(|s| addr < s.start + s.size)
+            .map(|s| format!("{}+{:#x}/{:#x}", s.name, addr - s.start, s.size));
+        cache.insert(addr, sym.clone());
+        sym
+    }
+
+    fn load(&self) -> Vec<Symbol> {
+        if !self.vmlinux.is_file() {
+            warn!(
+                "Failed to symbolize crash: {} not found",
+                self.vmlinux.display()
+            );
+            return Vec::new();
+        }
+        let output = Command::new(&self.nm)
+            .args(&["-n", "-S"])
+            .arg(&self.vmlinux)
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                parse_nm(&String::from_utf8_lossy(&output.stdout))
+            }
+            Ok(output) => {
+                warn!(
+                    "Failed to symbolize crash: {} exited with {}: {}",
+                    self.nm,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                Vec::new()
+            }
+            Err(e) => {
+                warn!("Failed to symbolize crash: {}: {}", self.nm, e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Text symbols with size in output of `nm -n -S`, sorted by start.
+fn parse_nm(out: &str) -> Vec<Symbol> {
+    let mut symbols = out
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            let start = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let size = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let ty = fields.next()?;
+            let name = fields.next()?;
+            if size != 0 && matches!(ty, "t" | "T" | "w" | "W") {
+                Some(Symbol {
+                    start,
+                    size,
+                    name: name.to_string(),
+                })
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_by_key(|s| s.start);
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crash::parse;
+
+    #[test]
+    fn symbolize_raw_frames() {
+        let nm = "ffffffff81000000 T _text\n\
+                  ffffffff81001000 0000000000000040 T foo_bar\n\
+                  ffffffff81001040 0000000000000100 t baz\n\
+                  ffffffff82000000 0000000000000010 D some_data\n";
+        let s = Symbolizer::new(PathBuf::from("/nonexistent/vmlinux"), "nm".to_string());
+        *s.symbols.lock().unwrap() = Some(parse_nm(nm));
+
+        let log = "[   1.0] BUG: KASAN: use-after-free in foo_bar\n\
+                   [   1.0] Call Trace:\n\
+                   [   1.0]  [<ffffffff8100101a>]\n\
+                   [   1.0]  ? ffffffff81001050\n\
+                   [   1.0]  [<ffffffff81001080>]\n\
+                   [   1.0]  [<ffffffff83000000>]\n";
+        let mut report = parse(log);
+        assert!(report.call_trace.is_empty());
+        s.symbolize(&mut report);
+        assert_eq!(report.call_trace, vec!["foo_bar", "baz"]);
+        assert!(report
+            .raw
+            .contains("[<ffffffff8100101a>] foo_bar+0x1a/0x40"));
+        assert!(report.raw.contains("[<ffffffff83000000>]\n"));
+        assert_eq!(s.cache.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn missing_vmlinux() {
+        let s = Symbolizer::new(PathBuf::from("/nonexistent/vmlinux"), "nm".to_string());
+        let log = "Call Trace:\n [<ffffffff8100101a>]\n";
+        let mut report = parse(log);
+        s.symbolize(&mut report);
+        assert_eq!(report.raw, log);
+        assert!(report.call_trace.is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+//! Kernel command line as ordered key -> optional value map.
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cmdline {
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl Cmdline {
+    /// Parse space separated `key=value` or `flag` tokens, fail on conflicting tokens.
+    pub fn parse(cmdline: &str) -> Result<Self, String> {
+        let mut c = Self::default();
+        for token in cmdline.split_whitespace() {
+            let (k, v) = split_token(token);
+            c.add(k, v)?;
+        }
+        Ok(c)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_deref())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Add new key, fail if key already exists with different value.
+    pub fn add(&mut self, key: &str, val: Option<&str>) -> Result<(), String> {
+        match self.get(key) {
+            Some(old) if old == val => Ok(()),
+            Some(old) => Err(format!(
+                "conflicting kernel cmdline: {} vs {}",
+                render(key, old),
+                render(key, val)
+            )),
+            None => {
+                self.entries.push((key.to_string(), val.map(String::from)));
+                Ok(())
+            }
+        }
+    }
+
+    /// Set value of key, position of existing key is kept.
+    pub fn set(&mut self, key: &str, val: Option<&str>) -> &mut Self {
+        let val = val.map(String::from);
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = val,
+            None => self.entries.push((key.to_string(), val)),
+        }
+        self
+    }
+
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.entries.retain(|(k, _)| k != key);
+        self
+    }
+
+    /// Merge other cmdline into self, values of other override.
+    pub fn merge(&mut self, other: &Cmdline) -> &mut Self {
+        for (k, v) in other.entries.iter() {
+            self.set(k, v.as_deref());
+        }
+        self
+    }
+}
+
+/// Build from tokens, later token overrides earlier one.
+impl<'a, T: IntoIterator<Item = &'a str>> From<T> for Cmdline {
+    fn from(tokens: T) -> Self {
+        let mut c = Self::default();
+        for token in tokens {
+            let (k, v) = split_token(token);
+            c.set(k, v);
+        }
+        c
+    }
+}
+
+impl fmt::Display for Cmdline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens = self
+            .entries
+            .iter()
+            .map(|(k, v)| render(k, v.as_deref()))
+            .collect::<Vec<_>>();
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+fn split_token(token: &str) -> (&str, Option<&str>) {
+    match token.find('=') {
+        Some(i) => (&token[..i], Some(&token[i + 1..])),
+        None => (token, None),
+    }
+}
+
+fn render(key: &str, val: Option<&str>) -> String {
+    match val {
+        Some(v) => format!("{}={}", key, v),
+        None => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmdline() {
+        let mut c = Cmdline::from(vec!["console=ttyS0", "rodata=n", "nokaslr"]);
+        assert_eq!(c.get("console"), Some(Some("ttyS0")));
+        assert_eq!(c.get("nokaslr"), Some(None));
+        assert!(c.add("console", Some("ttyS1")).is_err());
+        assert!(c.add("console", Some("ttyS0")).is_ok());
+
+        assert!(Cmdline::parse("console=ttyS0 console=tty0").is_err());
+        c.merge(&Cmdline::parse("rodata=y loglevel=7").unwrap());
+        c.remove("nokaslr");
+        assert_eq!(c.to_string(), "console=ttyS0 rodata=y loglevel=7");
+    }
+}
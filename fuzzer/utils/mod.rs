@@ -1,11 +1,21 @@
 pub mod cli;
+pub mod cmdline;
 pub mod process;
 pub mod queue;
+pub mod restore;
+pub mod rotate;
 pub mod split;
 
+use nix::fcntl::{flock, FlockArg};
+use std::collections::VecDeque;
+use std::env::temp_dir;
+use std::fs::{create_dir_all, File, OpenOptions};
 use std::future::Future;
 use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 
 pub async fn transaction_loop<T, F, Z>(
@@ -21,20 +31,85 @@ pub async fn transaction_loop<T, F, Z>(
 
     loop {
         match stop_signal.try_recv() {
-            Ok(_) => {
+            // sender dropped is same as stopping
+            Ok(_) | Err(Closed) => {
                 clean_up.await;
                 break;
             }
-            Err(Empty) => (),
-            Err(e) => panic!("Unexpected braodcast receiver state: {}", e),
+            Err(Empty) | Err(Lagged(_)) => (),
         }
         let transaction = transaction_gen();
         transaction.await;
     }
 }
 
+/// Wait until stop signal is received or sender is dropped, lagging is not stopping.
+pub async fn wait_stop(stop_signal: &mut broadcast::Receiver<()>) {
+    use broadcast::RecvError::*;
+
+    loop {
+        match stop_signal.recv().await {
+            Ok(_) | Err(Closed) => return,
+            Err(Lagged(_)) => continue,
+        }
+    }
+}
+
 lazy_static! {
     static ref SCAN_RANGE: Mutex<(u16, u16)> = Mutex::new((1 << 12, 0xFFFF));
+    static ref PORT_CLAIMS: Mutex<VecDeque<(Instant, File)>> = Mutex::new(VecDeque::new());
+}
+
+/// How long a picked port stays claimed, long enough for qemu or listener to bind it.
+const PORT_CLAIM_TTL: Duration = Duration::from_secs(300);
+
+fn port_lock_dir() -> PathBuf {
+    temp_dir().join("healer-ports")
+}
+
+/// Claim port host-wide with flock on lock file of the port, so that other healer
+/// instances on the same host won't pick it. Lock is released after `PORT_CLAIM_TTL`
+/// or when process exits.
+fn claim_port(port: u16) -> bool {
+    let mut claims = PORT_CLAIMS.lock().unwrap();
+    while let Some((t, _)) = claims.front() {
+        if t.elapsed() < PORT_CLAIM_TTL {
+            break;
+        }
+        claims.pop_front();
+    }
+
+    let dir = port_lock_dir();
+    if let Err(e) = create_dir_all(&dir) {
+        warn!("Fail to create port lock dir {}: {}", dir.display(), e);
+        return true;
+    }
+    let lock = match OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(dir.join(port.to_string()))
+    {
+        Ok(f) => f,
+        Err(e) => {
+            warn!("Fail to open lock file of port {}: {}", port, e);
+            return true;
+        }
+    };
+    if flock(lock.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
+        return false;
+    }
+    claims.push_back((Instant::now(), lock));
+    true
+}
+
+/// Parse `field` of /proc/meminfo, in MB.
+pub fn meminfo_mb(meminfo: &str, field: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|l| l.split(':').next() == Some(field))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb / 1024)
 }
 
 pub fn free_ipv4_port() -> Option<u16> {
@@ -48,7 +123,7 @@ pub fn free_ipv4_port() -> Option<u16> {
     let mut addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, r.0);
     for p in r.0..r.1 {
         addr.set_port(p);
-        if TcpListener::bind(&addr).is_ok() {
+        if TcpListener::bind(addr).is_ok() && claim_port(p) {
             r.0 = p + 1;
             return Some(p);
         }
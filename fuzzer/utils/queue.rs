@@ -1,27 +1,81 @@
 use std::collections::VecDeque;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{Mutex, Notify, Semaphore};
 
 pub struct CQueue<T> {
     inner: Mutex<VecDeque<T>>,
+    /// Capacity and free slots of bounded queue
+    slots: Option<(usize, Semaphore)>,
+    /// Nothing is pushed anymore, see `close`
+    closed: AtomicBool,
+    /// Wakes `pop_wait` on push or close
+    pushed: Notify,
 }
 
 impl<T> Default for CQueue<T> {
     fn default() -> Self {
+        Self::with_capacity(None)
+    }
+}
+
+impl<T> CQueue<T> {
+    /// Queue holding at most `cap` values, unbounded if None.
+    pub fn with_capacity(cap: Option<usize>) -> Self {
         Self {
             inner: Mutex::new(VecDeque::new()),
+            slots: cap.map(|cap| (cap, Semaphore::new(cap))),
+            closed: AtomicBool::new(false),
+            pushed: Notify::new(),
         }
     }
-}
 
-impl<T> CQueue<T> {
+    pub fn capacity(&self) -> Option<usize> {
+        self.slots.as_ref().map(|(cap, _)| *cap)
+    }
+
+    /// Push value, waits until a value is popped if bounded queue is full.
     pub async fn push(&self, v: T) {
+        if let Some((_, slots)) = self.slots.as_ref() {
+            slots.acquire().await.forget();
+        }
         let mut inner = self.inner.lock().await;
         inner.push_back(v);
+        self.pushed.notify();
+    }
+
+    /// Mark that nothing will be pushed anymore, `pop_wait` returns None once queue is empty.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.pushed.notify();
+    }
+
+    /// Queue is closed and all its values are popped.
+    pub async fn is_done(&self) -> bool {
+        self.closed.load(Ordering::SeqCst) && self.is_empty().await
+    }
+
+    /// Pop value, waits for next push if queue is empty but not closed yet.
+    pub async fn pop_wait(&self) -> Option<T> {
+        loop {
+            if let Some(v) = self.pop().await {
+                return Some(v);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                // pass wakeup of close on to other waiters
+                self.pushed.notify();
+                return self.pop().await;
+            }
+            self.pushed.notified().await;
+        }
     }
 
     pub async fn pop(&self) -> Option<T> {
         let mut inner = self.inner.lock().await;
-        inner.pop_front()
+        let v = inner.pop_front();
+        if let (Some(_), Some((_, slots))) = (v.as_ref(), self.slots.as_ref()) {
+            slots.add_permits(1);
+        }
+        v
     }
 
     pub async fn len(&self) -> usize {
@@ -39,6 +93,48 @@ impl<T> From<Vec<T>> for CQueue<T> {
     fn from(vals: Vec<T>) -> Self {
         Self {
             inner: Mutex::new(VecDeque::from(vals)),
+            ..Self::default()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::{delay_for, Duration};
+
+    #[tokio::test]
+    async fn push_blocks_when_full() {
+        let q = Arc::new(CQueue::with_capacity(Some(1)));
+        assert_eq!(q.capacity(), Some(1));
+        q.push(1).await;
+
+        let pusher = {
+            let q = q.clone();
+            tokio::spawn(async move { q.push(2).await })
+        };
+        delay_for(Duration::from_millis(100)).await;
+        assert_eq!(q.len().await, 1);
+
+        assert_eq!(q.pop().await, Some(1));
+        pusher.await.unwrap();
+        assert_eq!(q.pop().await, Some(2));
+        assert_eq!(q.pop().await, None);
+    }
+
+    #[tokio::test]
+    async fn pop_wait_until_closed() {
+        let q = Arc::new(CQueue::default());
+        let popper = {
+            let q = q.clone();
+            tokio::spawn(async move { (q.pop_wait().await, q.pop_wait().await) })
+        };
+        delay_for(Duration::from_millis(100)).await;
+        assert!(!q.is_done().await);
+        q.push(1).await;
+        q.close();
+        assert_eq!(popper.await.unwrap(), (Some(1), None));
+        assert!(q.is_done().await);
+    }
+}
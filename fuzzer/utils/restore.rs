@@ -0,0 +1,117 @@
+//! Guard of base image against mutation, restored from backup copy.
+use std::collections::HashMap;
+use std::fs::{copy, metadata, rename, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+lazy_static! {
+    /// Guards shared by guests booting the same image, so image is hashed only once.
+    static ref IMAGES: Mutex<HashMap<PathBuf, Arc<ImageGuard>>> = Mutex::new(HashMap::new());
+}
+
+pub struct ImageGuard {
+    image: PathBuf,
+    backup: PathBuf,
+    /// Md5 of image when it was first checked, image is intact before any guest boots
+    digest: Mutex<Option<md5::Digest>>,
+    /// Length and modified time of image when it was last known to be intact
+    stamp: Mutex<(u64, SystemTime)>,
+}
+
+impl ImageGuard {
+    /// Guard of image, image is hashed on first check, not here.
+    pub fn of(image: &str, backup: &str) -> Arc<ImageGuard> {
+        let mut images = IMAGES.lock().unwrap();
+        images
+            .entry(PathBuf::from(image))
+            .or_insert_with(|| {
+                let image = PathBuf::from(image);
+                Arc::new(ImageGuard {
+                    digest: Mutex::new(None),
+                    stamp: Mutex::new(stamp_of(&image)),
+                    image,
+                    backup: PathBuf::from(backup),
+                })
+            })
+            .clone()
+    }
+
+    /// Restore image from backup if it's changed since guarded, return true if restored.
+    /// Image is only hashed again if its length or modified time changed.
+    pub fn check(&self) -> bool {
+        let mut stamp = self.stamp.lock().unwrap();
+        let mut saved = self.digest.lock().unwrap();
+        let digest = match *saved {
+            Some(digest) => digest,
+            None => {
+                *stamp = stamp_of(&self.image);
+                *saved = Some(digest_of(&self.image));
+                return false;
+            }
+        };
+        let now = stamp_of(&self.image);
+        if now == *stamp {
+            return false;
+        }
+        if digest_of(&self.image) == digest {
+            *stamp = now;
+            return false;
+        }
+        self.do_restore();
+        *stamp = stamp_of(&self.image);
+        true
+    }
+
+    /// Restore image from backup unconditionally.
+    pub fn restore(&self) {
+        let mut stamp = self.stamp.lock().unwrap();
+        self.do_restore();
+        *stamp = stamp_of(&self.image);
+    }
+
+    /// Copy backup aside, then rename it over image, so running guests keep reading old one.
+    fn do_restore(&self) {
+        let mut tmp = self.image.clone().into_os_string();
+        tmp.push(".restoring");
+        let tmp = PathBuf::from(tmp);
+        copy(&self.backup, &tmp)
+            .and_then(|_| rename(&tmp, &self.image))
+            .unwrap_or_else(|e| {
+                exits!(
+                    exitcode::IOERR,
+                    "Fail to restore image {} from {}: {}",
+                    self.image.display(),
+                    self.backup.display(),
+                    e
+                )
+            });
+    }
+}
+
+fn stamp_of(path: &Path) -> (u64, SystemTime) {
+    let meta = metadata(path)
+        .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to stat {}: {}", path.display(), e));
+    (
+        meta.len(),
+        meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+    )
+}
+
+fn digest_of(path: &Path) -> md5::Digest {
+    let mut f = File::open(path)
+        .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to open {}: {}", path.display(), e));
+    let mut ctx = md5::Context::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = f
+            .read(&mut buf)
+            .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to read {}: {}", path.display(), e));
+        if n == 0 {
+            break;
+        }
+        ctx.consume(&buf[..n]);
+    }
+    ctx.compute()
+}
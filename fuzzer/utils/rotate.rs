@@ -0,0 +1,98 @@
+//! Size based rotating log file.
+use std::fs::{remove_file, rename, File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+
+/// Log file rotated to `path.1`, `path.2` ... once it exceeds `max_size` bytes,
+/// at most `keep` rotated files are kept.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open<P: AsRef<Path>>(path: P, max_size: u64, keep: usize) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    /// Write data as a whole, data is never split across files, so a crash log written
+    /// in one call stays intact. Rotation happens before writing if data doesn't fit.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.size + data.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Rotate unless current file is empty.
+    pub fn rotate(&mut self) -> Result<()> {
+        if self.size == 0 {
+            return Ok(());
+        }
+        self.file.flush()?;
+        let rotated = |i: usize| PathBuf::from(format!("{}.{}", self.path.display(), i));
+        if self.keep == 0 {
+            remove_file(&self.path)?;
+        } else {
+            let oldest = rotated(self.keep);
+            if oldest.exists() {
+                remove_file(&oldest)?;
+            }
+            for i in (1..self.keep).rev() {
+                let from = rotated(i);
+                if from.exists() {
+                    rename(&from, rotated(i + 1))?;
+                }
+            }
+            rename(&self.path, rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read;
+
+    #[test]
+    fn rotate() {
+        let dir = std::env::temp_dir().join(format!("healer-rotate-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log");
+        let mut f = RotatingFile::open(&path, 8, 2).unwrap();
+        f.write(b"aaaaaa").unwrap();
+        f.write(b"bbbbbbbbbbbb").unwrap();
+        f.write(b"cc").unwrap();
+        f.write(b"dddddddd").unwrap();
+
+        assert_eq!(read(&path).unwrap(), b"dddddddd");
+        assert_eq!(read(dir.join("log.1")).unwrap(), b"cc");
+        // written in one call, never split
+        assert_eq!(read(dir.join("log.2")).unwrap(), b"bbbbbbbbbbbb");
+        assert!(!dir.join("log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
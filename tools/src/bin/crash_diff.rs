@@ -0,0 +1,74 @@
+use fuzzer::crash::{normalize_title, title_of, trace_of};
+use fuzzer::report::CrashedCase;
+use std::fs::read;
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "crash-diff",
+    about = "Compare two crash reports to decide if they are the same bug"
+)]
+struct Settings {
+    /// First crash report
+    a: PathBuf,
+    /// Second crash report
+    b: PathBuf,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    let a = load_crash(&settings.a);
+    let b = load_crash(&settings.b);
+    let (log_a, log_b) = (&a.crash.inner, &b.crash.inner);
+
+    let title_a = title_of(log_a).unwrap_or_default();
+    let title_b = title_of(log_b).unwrap_or_default();
+    println!("A: {}", a.meta.title);
+    println!("   {}", title_a);
+    println!("B: {}", b.meta.title);
+    println!("   {}", title_b);
+    println!();
+
+    // signature is the key crashes are deduplicated by
+    println!(
+        "Same signature: {}",
+        a.crash.signature() == b.crash.signature()
+    );
+    println!(
+        "Same title:     {}",
+        normalize_title(title_a) == normalize_title(title_b)
+    );
+    println!();
+
+    // '=' same frame, '~' same function at different address, '!' different function
+    let frames_a = trace_of(log_a);
+    let frames_b = trace_of(log_b);
+    println!("Frames:");
+    for i in 0..frames_a.len().max(frames_b.len()) {
+        match (frames_a.get(i), frames_b.get(i)) {
+            (Some(fa), Some(fb)) if fa == fb => println!("  #{:<3} = {}", i, fa),
+            (Some(fa), Some(fb)) if fa.func == fb.func => {
+                println!("  #{:<3} ~ {} | {}", i, fa, fb)
+            }
+            (fa, fb) => println!(
+                "  #{:<3} ! {} | {}",
+                i,
+                fa.map_or_else(|| String::from("-"), |f| f.to_string()),
+                fb.map_or_else(|| String::from("-"), |f| f.to_string())
+            ),
+        }
+    }
+}
+
+fn load_crash(path: &PathBuf) -> CrashedCase {
+    let crash = read(path).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}: {}", path, e);
+        exit(exitcode::NOINPUT)
+    });
+    serde_json::from_slice(&crash).unwrap_or_else(|e| {
+        eprintln!("Fail to deserialize {:?}: {}", path, e);
+        exit(exitcode::DATAERR)
+    })
+}
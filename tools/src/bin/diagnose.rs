@@ -0,0 +1,174 @@
+use core::prog::Prog;
+use executor::exec::fork_exec;
+use executor::exec::ExecResult;
+use executor::{parse_sandbox, Config, ExecBackend, FailureKind};
+use fuzzer::feedback::{Block, Branch};
+use fuzzer::prog_io;
+use std::collections::HashSet;
+use std::fs::{read, read_to_string};
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use tools::load_target;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "diagnose",
+    about = "Execute one prog and explain its coverage, e.g. why it adds nothing new"
+)]
+struct Settings {
+    #[structopt(short = "p", long)]
+    prog: PathBuf,
+    #[structopt(short = "t", long)]
+    items: PathBuf,
+    /// Coverage dumped by fuzzer, one hex pc per line, blocks in it are known
+    #[structopt(short = "k", long)]
+    known: Option<PathBuf>,
+    /// Print raw pcs of each call
+    #[structopt(short = "v", long)]
+    verbose: bool,
+    #[structopt(short = "m", long)]
+    memleak_check: bool,
+    #[structopt(short = "c", long)]
+    concurrency: bool,
+    /// Comma separated namespaces to unshare before executing prog, e.g. mount,pid,net
+    #[structopt(short = "s", long)]
+    sandbox: Option<String>,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    let target = load_target(&settings.items);
+    let p = read(&settings.prog).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}:{}", &settings.prog, e);
+        exit(exitcode::NOINPUT)
+    });
+    let p: Prog = prog_io::load(&p).unwrap_or_else(|e| {
+        eprintln!("Fail to deserialize {:?}:{}", &settings.prog, e);
+        exit(exitcode::DATAERR)
+    });
+    let known = settings.known.as_ref().map(load_blocks).unwrap_or_default();
+
+    let conf = Config {
+        memleak_check: settings.memleak_check,
+        concurrency: settings.concurrency,
+        sandbox: parse_sandbox(settings.sandbox.as_deref().unwrap_or("")).unwrap_or_else(|e| {
+            eprintln!("Invalid sandbox: {}", e);
+            exit(exitcode::USAGE)
+        }),
+        cov_limit: None,
+        run_as: None,
+        trace_cmp: false,
+        pipe_size: None,
+        exec_timeout: None,
+        backend: ExecBackend::Tcc,
+    };
+
+    let covs = match fork_exec(p.clone(), &target, &conf) {
+        Ok(ExecResult::Ok(covs)) => {
+            println!("Result: ok");
+            covs
+        }
+        Ok(ExecResult::Truncated(covs)) => {
+            println!("Result: coverage truncated, later calls are missing");
+            covs
+        }
+        Ok(ExecResult::Partial { covs, err }) => {
+            println!("Result: prog failed after {} calls: {}", covs.len(), err);
+            covs
+        }
+        Ok(ExecResult::OkCmp { .. }) => unreachable!("cmps are not traced"),
+        Ok(ExecResult::Failed(reason)) => {
+            match reason.kind() {
+                FailureKind::Prog => println!("Result: prog failed before first call finished"),
+                FailureKind::Executor => println!("Result: executor failed"),
+            }
+            println!("Reason: {}", reason);
+            exit(exitcode::SOFTWARE)
+        }
+        Ok(ExecResult::Oom(reason)) => {
+            println!("Result: killed by OOM killer: {}", reason);
+            exit(exitcode::SOFTWARE)
+        }
+        Err(e) => {
+            println!("Result: {}", e);
+            exit(exitcode::TEMPFAIL)
+        }
+    };
+
+    // blocks and edges seen so far, by known coverage or earlier calls
+    let mut seen_blocks = known.clone();
+    let mut seen_edges = HashSet::new();
+    let (mut new_blocks, mut new_edges) = (0, 0);
+    for (i, c) in p.calls.iter().enumerate() {
+        let name = &target.fn_of(c.fid).dec_name;
+        let cov = match covs.get(i) {
+            Some(cov) => cov,
+            None => {
+                println!("call {} {}: not executed", i, name);
+                continue;
+            }
+        };
+        let blocks = cov.iter().map(|pc| Block::from(*pc)).collect::<Vec<_>>();
+        let edges = blocks
+            .windows(2)
+            .map(|w| Branch::from((w[0].clone(), w[1].clone())))
+            .collect::<HashSet<_>>();
+        let blocks = blocks.into_iter().collect::<HashSet<_>>();
+        let call_known = blocks.intersection(&known).count();
+        let call_new_blocks = blocks.difference(&seen_blocks).count();
+        let call_new_edges = edges.difference(&seen_edges).count();
+        println!(
+            "call {} {}: pcs {}, blocks {} (known {}, new {}), edges {} (new {})",
+            i,
+            name,
+            cov.len(),
+            blocks.len(),
+            call_known,
+            call_new_blocks,
+            edges.len(),
+            call_new_edges
+        );
+        if settings.verbose {
+            for pc in cov {
+                println!("  {:#x}", pc);
+            }
+        }
+        new_blocks += call_new_blocks;
+        new_edges += call_new_edges;
+        seen_blocks.extend(blocks);
+        seen_edges.extend(edges);
+    }
+
+    println!(
+        "Executed calls: {}/{}, new blocks: {}, new edges: {}",
+        covs.len().min(p.len()),
+        p.len(),
+        new_blocks,
+        new_edges
+    );
+    if covs.iter().all(Vec::is_empty) {
+        println!("No coverage collected, is kcov enabled in executor?");
+    } else if !known.is_empty() && new_blocks == 0 {
+        println!("Every block is already known, prog only hits covered code");
+    }
+}
+
+fn load_blocks(path: &PathBuf) -> HashSet<Block> {
+    let cover = read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}: {}", path, e);
+        exit(exitcode::NOINPUT)
+    });
+    cover
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|l| {
+            let pc = usize::from_str_radix(l.trim_start_matches("0x"), 16).unwrap_or_else(|e| {
+                eprintln!("Invalid pc {}: {}", l, e);
+                exit(exitcode::DATAERR)
+            });
+            Block::from(pc)
+        })
+        .collect()
+}
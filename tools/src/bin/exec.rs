@@ -1,7 +1,8 @@
 use core::prog::Prog;
-use executor::exec::fork_exec;
 use executor::exec::ExecResult;
-use executor::Config;
+use executor::exec::{fork_exec, fork_exec_stable};
+use executor::{parse_sandbox, parse_user, Config, ExecBackend};
+use fuzzer::prog_io;
 use std::fs::read;
 use std::path::PathBuf;
 use std::process::exit;
@@ -19,6 +20,21 @@ struct Settings {
     memleak_check: bool,
     #[structopt(short = "c", long)]
     concurrency: bool,
+    /// Comma separated namespaces to unshare before executing prog, e.g. mount,pid,net
+    #[structopt(short = "s", long)]
+    sandbox: Option<String>,
+    /// Max bytes of coverage collected, coverage beyond this is dropped
+    #[structopt(short = "l", long)]
+    cov_limit: Option<usize>,
+    /// Run prog as unprivileged user, `uid[:gid]`
+    #[structopt(short = "u", long)]
+    user: Option<String>,
+    /// Also trace comparison operands, kernel needs CONFIG_KCOV_ENABLE_COMPARISONS
+    #[structopt(long)]
+    trace_cmp: bool,
+    /// Execute prog this many times, only coverage seen in every run is reported
+    #[structopt(short = "r", long)]
+    runs: Option<usize>,
 }
 
 fn main() {
@@ -29,7 +45,7 @@ fn main() {
         exit(exitcode::NOINPUT)
     });
 
-    let p: Prog = bincode::deserialize(&p).unwrap_or_else(|e| {
+    let p: Prog = prog_io::load(&p).unwrap_or_else(|e| {
         eprintln!("Fail to deserialize {:?}:{}", &settings.prog, e);
         exit(exitcode::DATAERR)
     });
@@ -38,9 +54,33 @@ fn main() {
     let conf = Config {
         memleak_check: settings.memleak_check,
         concurrency: settings.concurrency,
+        sandbox: parse_sandbox(settings.sandbox.as_deref().unwrap_or("")).unwrap_or_else(|e| {
+            eprintln!("Invalid sandbox: {}", e);
+            exit(exitcode::USAGE)
+        }),
+        cov_limit: settings.cov_limit,
+        run_as: settings.user.as_deref().map(|user| {
+            parse_user(user).unwrap_or_else(|e| {
+                eprintln!("Invalid user: {}", e);
+                exit(exitcode::USAGE)
+            })
+        }),
+        trace_cmp: settings.trace_cmp,
+        pipe_size: None,
+        exec_timeout: None,
+        backend: ExecBackend::Tcc,
     };
-    match fork_exec(p, &target, &conf) {
-        ExecResult::Ok(covs) => {
+    let ret = match settings.runs {
+        Some(runs) if runs > 1 => fork_exec_stable(p, &target, &conf, runs),
+        _ => fork_exec(p, &target, &conf),
+    };
+    let ret = ret.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        exit(exitcode::TEMPFAIL)
+    });
+    let truncated = matches!(ret, ExecResult::Truncated(_));
+    match ret {
+        ExecResult::Ok(covs) | ExecResult::Truncated(covs) => {
             let mut total = 0;
             let mut each = Vec::new();
             for c in covs.iter() {
@@ -48,10 +88,45 @@ fn main() {
                 each.push(c.len());
             }
 
-            println!("Prog len:{},Total pc:{},Executed:{:?}", len, total, each);
+            println!(
+                "Prog len:{},Total pc:{},Executed:{:?},Truncated:{}",
+                len, total, each, truncated
+            );
             exit(exitcode::OK)
         }
-        ExecResult::Failed(e) => {
+        ExecResult::OkCmp { covs, cmps } => {
+            let each = covs.iter().map(Vec::len).collect::<Vec<_>>();
+            println!(
+                "Prog len:{},Total pc:{},Executed:{:?},Total cmp:{}",
+                len,
+                each.iter().sum::<usize>(),
+                each,
+                cmps.len()
+            );
+            for c in cmps.iter() {
+                println!(
+                    "{:#x}: {:#x} {:#x} size {}{}",
+                    c.pc,
+                    c.arg1,
+                    c.arg2,
+                    c.size,
+                    if c.is_const { " const" } else { "" }
+                );
+            }
+            exit(exitcode::OK)
+        }
+        ExecResult::Partial { covs, err } => {
+            let each = covs.iter().map(Vec::len).collect::<Vec<_>>();
+            println!(
+                "Prog len:{},Total pc:{},Executed:{:?},Error:{}",
+                len,
+                each.iter().sum::<usize>(),
+                each,
+                err
+            );
+            exit(exitcode::SOFTWARE)
+        }
+        ExecResult::Failed(e) | ExecResult::Oom(e) => {
             eprintln!("Error: {}", e);
             exit(exitcode::SOFTWARE)
         }
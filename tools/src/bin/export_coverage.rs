@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs::{read_to_string, write};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{exit, Command, Stdio};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "export-coverage",
+    about = "Symbolize coverage dumped by fuzzer and export it for coverage viewers"
+)]
+struct Settings {
+    /// Output format, only lcov is supported now
+    #[structopt(short = "f", long, default_value = "lcov")]
+    format: String,
+    /// Output file, stdout by default
+    #[structopt(short = "o", long)]
+    out: Option<PathBuf>,
+    /// Coverage file dumped by fuzzer, one hex pc per line
+    coverage: PathBuf,
+    /// Kernel image with debug info
+    vmlinux: PathBuf,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    if settings.format != "lcov" {
+        eprintln!("Unsupported format: {}", settings.format);
+        exit(exitcode::USAGE)
+    }
+
+    let pcs = load_pcs(&settings.coverage);
+    let lines = symbolize(&settings.vmlinux, &pcs);
+    let lcov = to_lcov(&lines);
+
+    match settings.out.as_ref() {
+        Some(out) => write(out, lcov).unwrap_or_else(|e| {
+            eprintln!("Fail to write {:?}: {}", out, e);
+            exit(exitcode::IOERR)
+        }),
+        None => print!("{}", lcov),
+    }
+}
+
+fn load_pcs(path: &PathBuf) -> Vec<usize> {
+    let cover = read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}: {}", path, e);
+        exit(exitcode::NOINPUT)
+    });
+    cover
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|l| {
+            usize::from_str_radix(l.trim_start_matches("0x"), 16).unwrap_or_else(|e| {
+                eprintln!("Invalid pc {}: {}", l, e);
+                exit(exitcode::DATAERR)
+            })
+        })
+        .collect()
+}
+
+/// Map pcs to covered lines of each source file with addr2line.
+fn symbolize(vmlinux: &PathBuf, pcs: &[usize]) -> BTreeMap<String, BTreeSet<u32>> {
+    let mut addr2line = Command::new("addr2line")
+        .arg("-e")
+        .arg(vmlinux)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| {
+            eprintln!("Fail to spawn addr2line: {}", e);
+            exit(exitcode::UNAVAILABLE)
+        });
+
+    // kcov records return address of coverage callback, step back into the call instruction.
+    let input = pcs
+        .iter()
+        .map(|pc| format!("{:#x}\n", pc - 1))
+        .collect::<String>();
+    let mut stdin = addr2line.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = addr2line.wait_with_output().unwrap_or_else(|e| {
+        eprintln!("Fail to wait addr2line: {}", e);
+        exit(exitcode::OSERR)
+    });
+    if let Err(e) = writer.join().unwrap() {
+        eprintln!("Fail to send pcs to addr2line: {}", e);
+        exit(exitcode::OSERR)
+    }
+    if !output.status.success() {
+        eprintln!("addr2line failed: {}", output.status);
+        exit(exitcode::SOFTWARE)
+    }
+
+    let mut lines: BTreeMap<String, BTreeSet<u32>> = BTreeMap::new();
+    let mut unknown = 0;
+    for l in String::from_utf8_lossy(&output.stdout).lines() {
+        // file:line or file:line (discriminator n)
+        let l = l.split(' ').next().unwrap();
+        match l.rfind(':').map(|i| (&l[..i], &l[i + 1..])) {
+            Some((file, line)) if file != "??" => match line.parse::<u32>() {
+                Ok(line) if line != 0 => {
+                    lines.entry(file.to_string()).or_default().insert(line);
+                }
+                _ => unknown += 1,
+            },
+            _ => unknown += 1,
+        }
+    }
+    if unknown != 0 {
+        eprintln!("{}/{} pcs can not be symbolized", unknown, pcs.len());
+    }
+    lines
+}
+
+fn to_lcov(lines: &BTreeMap<String, BTreeSet<u32>>) -> String {
+    let mut lcov = String::new();
+    writeln!(lcov, "TN:healer").unwrap();
+    for (file, lines) in lines.iter() {
+        writeln!(lcov, "SF:{}", file).unwrap();
+        for l in lines.iter() {
+            writeln!(lcov, "DA:{},1", l).unwrap();
+        }
+        writeln!(lcov, "LH:{}", lines.len()).unwrap();
+        writeln!(lcov, "LF:{}", lines.len()).unwrap();
+        writeln!(lcov, "end_of_record").unwrap();
+    }
+    lcov
+}
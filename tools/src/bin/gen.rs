@@ -1,4 +1,5 @@
 use core::{analyze, c, gen};
+use fuzzer::prog_io;
 use std::io::{stdout, Write};
 use std::path::PathBuf;
 use std::process::exit;
@@ -32,7 +33,7 @@ fn main() {
         let p = c::to_prog(&p, &target);
         println!("{}", p);
     } else {
-        let p = bincode::serialize(&p).unwrap_or_else(|e| {
+        let p = prog_io::dump(&p).unwrap_or_else(|e| {
             eprintln!("Fail to serialize: {}", e);
             exit(exitcode::SOFTWARE)
         });
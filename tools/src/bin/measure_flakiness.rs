@@ -0,0 +1,218 @@
+use core::prog::Prog;
+use core::reorder::shuffle;
+use core::target::Target;
+use executor::exec::fork_exec_streaming;
+use executor::exec::ExecResult;
+use executor::{parse_sandbox, Config, ExecBackend};
+use fuzzer::feedback::{Block, Branch};
+use fuzzer::prog_io;
+use std::collections::{HashMap, HashSet};
+use std::fs::read;
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use tools::load_target;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "measure-flakiness",
+    about = "Execute the same prog repeatedly and report coverage nondeterminism"
+)]
+struct Settings {
+    #[structopt(short = "p", long)]
+    prog: PathBuf,
+    #[structopt(short = "t", long)]
+    items: PathBuf,
+    /// Number of times to execute the prog
+    #[structopt(short = "n", long, default_value = "10")]
+    runs: usize,
+    #[structopt(short = "m", long)]
+    memleak_check: bool,
+    #[structopt(short = "c", long)]
+    concurrency: bool,
+    /// Comma separated namespaces to unshare before executing prog, e.g. mount,pid,net
+    #[structopt(short = "s", long)]
+    sandbox: Option<String>,
+    /// Number of shuffled call orderings to execute after forward runs, for detecting
+    /// order-dependent coverage
+    #[structopt(short = "o", long, default_value = "0")]
+    orders: usize,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    if settings.runs == 0 {
+        eprintln!("Number of runs must be greater than 0");
+        exit(exitcode::USAGE)
+    }
+
+    let target = load_target(&settings.items);
+    let p = read(&settings.prog).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}:{}", &settings.prog, e);
+        exit(exitcode::NOINPUT)
+    });
+    let p: Prog = prog_io::load(&p).unwrap_or_else(|e| {
+        eprintln!("Fail to deserialize {:?}:{}", &settings.prog, e);
+        exit(exitcode::DATAERR)
+    });
+
+    let conf = Config {
+        memleak_check: settings.memleak_check,
+        concurrency: settings.concurrency,
+        sandbox: parse_sandbox(settings.sandbox.as_deref().unwrap_or("")).unwrap_or_else(|e| {
+            eprintln!("Invalid sandbox: {}", e);
+            exit(exitcode::USAGE)
+        }),
+        cov_limit: None,
+        run_as: None,
+        trace_cmp: false,
+        pipe_size: None,
+        exec_timeout: None,
+        backend: ExecBackend::Tcc,
+    };
+
+    let mut edge_counts = Vec::new();
+    let mut hits: HashMap<Branch, usize> = HashMap::new();
+    // edges of each call seen in any run, and in all runs
+    let mut call_seen: Vec<HashSet<Branch>> = vec![HashSet::new(); p.len()];
+    let mut call_stable: Option<Vec<HashSet<Branch>>> = None;
+    let mut failed = 0;
+    for _ in 0..settings.runs {
+        let mut calls = Vec::new();
+        match fork_exec_streaming(p.clone(), &target, &conf, |cov| calls.push(edges_of(&cov))) {
+            Ok(ExecResult::Ok(_)) | Ok(ExecResult::Truncated(_)) => {
+                calls.resize(p.len(), HashSet::new());
+                let edges = calls.iter().flatten().cloned().collect::<HashSet<_>>();
+                edge_counts.push(edges.len());
+                for e in edges.into_iter() {
+                    *hits.entry(e).or_insert(0) += 1;
+                }
+                for (seen, edges) in call_seen.iter_mut().zip(calls.iter()) {
+                    seen.extend(edges.iter().cloned());
+                }
+                call_stable = Some(match call_stable {
+                    None => calls,
+                    Some(stable) => stable
+                        .iter()
+                        .zip(calls.iter())
+                        .map(|(s, c)| s.intersection(c).cloned().collect())
+                        .collect(),
+                });
+            }
+            Ok(ExecResult::OkCmp { .. }) => unreachable!("cmps are not traced"),
+            Ok(ExecResult::Failed(e))
+            | Ok(ExecResult::Oom(e))
+            | Ok(ExecResult::Partial { err: e, .. }) => {
+                eprintln!("Run failed: {}", e);
+                failed += 1;
+            }
+            Err(e) => {
+                eprintln!("Run failed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let succeeded = edge_counts.len();
+    if succeeded == 0 {
+        eprintln!("All {} runs failed", settings.runs);
+        exit(exitcode::SOFTWARE)
+    }
+
+    let min = edge_counts.iter().min().unwrap();
+    let max = edge_counts.iter().max().unwrap();
+    let mean = edge_counts.iter().sum::<usize>() as f64 / succeeded as f64;
+    let stable = hits.values().filter(|&&n| n == succeeded).count();
+    let flaky = hits.len() - stable;
+
+    println!(
+        "Prog len:{},Runs:{},Succeeded:{},Failed:{}",
+        p.len(),
+        settings.runs,
+        succeeded,
+        failed
+    );
+    println!("Edges per run:{:?}", edge_counts);
+    println!("Min:{},Max:{},Mean:{:.2}", min, max, mean);
+    println!(
+        "Total edges:{},Stable:{},Flaky:{},Flakiness:{:.2}%",
+        hits.len(),
+        stable,
+        flaky,
+        flaky as f64 * 100.0 / hits.len().max(1) as f64
+    );
+
+    if settings.orders != 0 {
+        check_orders(
+            &p,
+            &target,
+            &conf,
+            settings.orders,
+            &call_seen,
+            &call_stable.unwrap(),
+        );
+    }
+}
+
+/// Execute prog under shuffled call orderings, report calls whose coverage diverges
+/// from forward runs: edges never seen in forward runs, or stable edges that are lost.
+fn check_orders(
+    p: &Prog,
+    target: &Target,
+    conf: &Config,
+    orders: usize,
+    call_seen: &[HashSet<Branch>],
+    call_stable: &[HashSet<Branch>],
+) {
+    println!("Orderings:");
+    let mut divergent = 0;
+    for _ in 0..orders {
+        let (shuffled, order) = shuffle(p);
+        let mut calls = Vec::new();
+        match fork_exec_streaming(shuffled, target, conf, |cov| calls.push(edges_of(&cov))) {
+            Ok(ExecResult::Failed(e))
+            | Ok(ExecResult::Oom(e))
+            | Ok(ExecResult::Partial { err: e, .. }) => {
+                println!("  {:?}: failed: {}", order, e);
+                continue;
+            }
+            Err(e) => {
+                println!("  {:?}: failed: {}", order, e);
+                continue;
+            }
+            Ok(_) => (),
+        }
+        calls.resize(p.len(), HashSet::new());
+
+        let mut diffs = Vec::new();
+        for (edges, &i) in calls.iter().zip(order.iter()) {
+            let new = edges.difference(&call_seen[i]).count();
+            let lost = call_stable[i].difference(edges).count();
+            if new != 0 || lost != 0 {
+                diffs.push(format!(
+                    "call {}({}): +{} -{}",
+                    i,
+                    target.fn_of(p.calls[i].fid).dec_name,
+                    new,
+                    lost
+                ));
+            }
+        }
+        if diffs.is_empty() {
+            println!("  {:?}: same", order);
+        } else {
+            divergent += 1;
+            println!("  {:?}: {}", order, diffs.join(", "));
+        }
+    }
+    println!("Divergent orderings:{}/{}", divergent, orders);
+}
+
+/// Deduped edges of one call.
+fn edges_of(cov: &[usize]) -> HashSet<Branch> {
+    let blocks = cov.iter().map(|b| Block::from(*b)).collect::<Vec<_>>();
+    blocks
+        .windows(2)
+        .map(|w| Branch::from((w[0].clone(), w[1].clone())))
+        .collect()
+}
@@ -0,0 +1,93 @@
+use executor::exec::fork_exec;
+use executor::exec::ExecResult;
+use executor::{parse_sandbox, Config, ExecBackend};
+use fuzzer::journal;
+use std::fs::read;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use tools::load_target;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "replay-journal",
+    about = "Execute progs recorded in fuzzer journal in the same order"
+)]
+struct Settings {
+    /// Journal recorded by fuzzer
+    #[structopt(short = "j", long)]
+    journal: PathBuf,
+    #[structopt(short = "t", long)]
+    items: PathBuf,
+    /// Index of first prog to execute
+    #[structopt(short = "f", long, default_value = "0")]
+    from: usize,
+    /// Number of progs to execute, all progs after `from` by default
+    #[structopt(short = "n", long)]
+    count: Option<usize>,
+    #[structopt(short = "m", long)]
+    memleak_check: bool,
+    #[structopt(short = "c", long)]
+    concurrency: bool,
+    /// Comma separated namespaces to unshare before executing prog, e.g. mount,pid,net
+    #[structopt(short = "s", long)]
+    sandbox: Option<String>,
+}
+
+fn main() {
+    let settings = Settings::from_args();
+    let target = load_target(&settings.items);
+    let data = read(&settings.journal).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}: {}", &settings.journal, e);
+        exit(exitcode::NOINPUT)
+    });
+    let progs = journal::load(&data).unwrap_or_else(|e| {
+        eprintln!("Fail to load journal {:?}: {}", &settings.journal, e);
+        exit(exitcode::DATAERR)
+    });
+    let conf = Config {
+        memleak_check: settings.memleak_check,
+        concurrency: settings.concurrency,
+        sandbox: parse_sandbox(settings.sandbox.as_deref().unwrap_or("")).unwrap_or_else(|e| {
+            eprintln!("Invalid sandbox: {}", e);
+            exit(exitcode::USAGE)
+        }),
+        cov_limit: None,
+        run_as: None,
+        trace_cmp: false,
+        pipe_size: None,
+        exec_timeout: None,
+        backend: ExecBackend::Tcc,
+    };
+
+    let count = settings.count.unwrap_or(progs.len());
+    println!("Journal: {} progs", progs.len());
+    for (i, p) in progs
+        .into_iter()
+        .enumerate()
+        .skip(settings.from)
+        .take(count)
+    {
+        // flush before executing, so the last printed index points to the prog that crashed kernel
+        print!("#{} len:{} ", i, p.len());
+        stdout().flush().unwrap();
+        match fork_exec(p, &target, &conf) {
+            Ok(ExecResult::Ok(covs)) => {
+                println!("ok, pc:{}", covs.iter().map(Vec::len).sum::<usize>())
+            }
+            Ok(ExecResult::Truncated(_)) => println!("ok, coverage truncated"),
+            Ok(ExecResult::OkCmp { covs, cmps }) => println!(
+                "ok, pc:{}, cmp:{}",
+                covs.iter().map(Vec::len).sum::<usize>(),
+                cmps.len()
+            ),
+            Ok(ExecResult::Failed(e)) => println!("failed: {}", e),
+            Ok(ExecResult::Partial { covs, err }) => {
+                println!("partial, calls:{}, failed: {}", covs.len(), err)
+            }
+            Ok(ExecResult::Oom(e)) => println!("oom: {}", e),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use fuzzer::crash::CrashMeta;
 use fuzzer::report::*;
 use std::fmt::Write;
 use std::fs::create_dir_all;
 use std::fs::read;
+use std::fs::read_dir;
 use std::fs::write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use structopt::StructOpt;
 
@@ -20,6 +22,7 @@ level = 0
 #[derive(Debug, StructOpt)]
 #[structopt(name = "repot")]
 struct Settings {
+    /// Crash reports, dirs of crash signatures (crashes/<md5 of signature>) or crash dirs
     #[structopt(short = "c", long = "crash")]
     crashes: Option<Vec<PathBuf>>,
     #[structopt(short = "n", long = "normal")]
@@ -42,24 +45,13 @@ fn main() {
     writeln!(summary, "- [Crashes](crash/crash.md)").unwrap();
 
     if let Some(crashes) = settings.crashes {
-        let len = if crashes.len() < 50 {
-            crashes.len()
-        } else {
-            50
-        };
-        for crash in crashes.into_iter().take(len) {
-            let crash = read(&crash).unwrap_or_else(|e| {
-                eprintln!("Fail to read {:?}: {}", crash, e);
-                exit(1);
-            });
-            let crash: CrashedCase = serde_json::from_slice(&crash).unwrap_or_else(|e| {
-                eprintln!("Fail to deserialize: {}", e);
-                exit(1);
-            });
+        let crashes = crashes.iter().flat_map(|c| load_crashes(c));
+        for crash in crashes.take(50) {
             let crash_md = report_crash(&crash);
-            let path = format!("{}.md", crash.meta.title);
+            let title = &crash.case.meta.title;
+            let path = format!("{}.md", title);
 
-            writeln!(summary, "    - [{}](crash/{})", crash.meta.title, path).unwrap();
+            writeln!(summary, "    - [{}](crash/{})", title, path).unwrap();
             crashes_mds.push((path, crash_md));
         }
     }
@@ -144,11 +136,117 @@ fn main() {
     write(&out, BOOK_TOML).unwrap();
 }
 
-fn report_crash(crash: &CrashedCase) -> String {
+/// Saved crash report, with meta and prog in json of its signature if saved next to it.
+struct SavedCrash {
+    case: CrashedCase,
+    meta: Option<CrashMeta>,
+    prog_json: Option<String>,
+}
+
+/// Crashes saved at `path`: a crash report, a dir of a crash signature or a dir of those.
+fn load_crashes(path: &Path) -> Vec<SavedCrash> {
+    if !path.is_dir() {
+        if !is_report(path) {
+            return Vec::new();
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        return vec![load_report(path, dir)];
+    }
+    let mut entries = read_dir(path)
+        .unwrap_or_else(|e| {
+            eprintln!("Fail to read dir {:?}: {}", path, e);
+            exit(1);
+        })
+        .flatten()
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries
+        .iter()
+        .filter(|p| p.is_dir() || is_report(p))
+        .flat_map(|p| {
+            if p.is_dir() {
+                load_crashes(p)
+            } else {
+                vec![load_report(p, path)]
+            }
+        })
+        .collect()
+}
+
+/// Whether file is a crash report, other artifacts are saved next to it in dir of signature.
+fn is_report(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    ![CRASH_META, CRASH_LOG, CRASH_PROG_JSON].contains(&name)
+        && path.extension() != Some("prog".as_ref())
+}
+
+fn load_report(path: &Path, dir: &Path) -> SavedCrash {
+    let crash = read(path).unwrap_or_else(|e| {
+        eprintln!("Fail to read {:?}: {}", path, e);
+        exit(1);
+    });
+    let case: CrashedCase = serde_json::from_slice(&crash).unwrap_or_else(|e| {
+        eprintln!("Fail to deserialize {:?}: {}", path, e);
+        exit(1);
+    });
+    let meta = read(dir.join(CRASH_META)).ok().map(|meta| {
+        serde_json::from_slice(&meta).unwrap_or_else(|e| {
+            eprintln!("Fail to deserialize {:?}: {}", dir.join(CRASH_META), e);
+            exit(1);
+        })
+    });
+    let prog_json = read(dir.join(CRASH_PROG_JSON))
+        .ok()
+        .map(|p| String::from_utf8_lossy(&p).into_owned());
+    SavedCrash {
+        case,
+        meta,
+        prog_json,
+    }
+}
+
+fn report_crash(saved: &SavedCrash) -> String {
+    let crash = &saved.case;
     let mut buf = String::new();
     writeln!(buf, "# {}", crash.meta.title).unwrap();
     writeln!(buf, "**Id**:   {}</br>", crash.meta.id).unwrap();
     writeln!(buf, "**Repo**: {}</br>", crash.repo).unwrap();
+    if let Some(meta) = saved.meta.as_ref() {
+        writeln!(buf, "**Hits**: {}</br>", meta.hits).unwrap();
+        writeln!(buf, "**First Seen**: {}</br>", meta.first_seen).unwrap();
+    }
+    if let Some(report) = crash.report.as_ref() {
+        writeln!(buf, "**Kind**: {}</br>", report.kind).unwrap();
+        writeln!(buf, "**Title**: {}</br>", report.title).unwrap();
+    }
+    if let Some(r) = crash.repro_rate {
+        writeln!(
+            buf,
+            "**Repro Rate**: {}/{}{}</br>",
+            r.crashes,
+            r.runs,
+            if crash.flaky { " (flaky)" } else { "" }
+        )
+        .unwrap();
+    }
+    if let Some(verified) = crash.verified {
+        writeln!(buf, "**Verified**: {}</br>", verified).unwrap();
+    }
+    if let Some(fingerprint) = crash.fingerprint.as_ref() {
+        writeln!(buf, "**Fingerprint**: {} edges</br>", fingerprint.len()).unwrap();
+    }
+    if let Some(kernel_version) = crash.crash.kernel_version.as_ref() {
+        writeln!(buf, "**Kernel**: {}</br>", kernel_version).unwrap();
+    }
+    let mut labels = crash.labels.iter().collect::<Vec<_>>();
+    labels.sort();
+    for (k, v) in labels {
+        writeln!(buf, "**{}**: {}</br>", k, v).unwrap();
+    }
     writeln!(buf, "**Test Time**: {}</br>", crash.meta.test_time).unwrap();
     writeln!(buf, "## Prog").unwrap();
     writeln!(buf, "``` c").unwrap();
@@ -160,6 +258,23 @@ fn report_crash(crash: &CrashedCase) -> String {
     for line in crash.crash.to_string().lines() {
         writeln!(buf, "{}</br>", line).unwrap();
     }
+    if !crash.crash.triage.is_empty() {
+        writeln!(buf, "## Triage").unwrap();
+        for t in crash.crash.triage.iter() {
+            writeln!(buf, "### {}", t.cmd).unwrap();
+            writeln!(buf, "```").unwrap();
+            for line in t.output.lines() {
+                writeln!(buf, "{}", line).unwrap();
+            }
+            writeln!(buf, "```").unwrap();
+        }
+    }
+    if let Some(prog_json) = saved.prog_json.as_ref() {
+        writeln!(buf, "## Prog Json").unwrap();
+        writeln!(buf, "``` json").unwrap();
+        writeln!(buf, "{}", prog_json.trim_end()).unwrap();
+        writeln!(buf, "```").unwrap();
+    }
     buf
 }
 
@@ -1,5 +1,6 @@
 use core::c;
 use core::prog::Prog;
+use fuzzer::prog_io;
 use std::fs::read;
 use std::path::PathBuf;
 use std::process::exit;
@@ -26,7 +27,7 @@ fn main() {
         exit(exitcode::NOINPUT)
     });
 
-    let p: Prog = bincode::deserialize(&p).unwrap_or_else(|e| {
+    let p: Prog = prog_io::load(&p).unwrap_or_else(|e| {
         eprintln!("Fail to deserialize: {}", e);
         exit(exitcode::DATAERR)
     });